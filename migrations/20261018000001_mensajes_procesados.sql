@@ -0,0 +1,10 @@
+-- Bitácora de mensajes entrantes ya procesados (idempotencia por wamid).
+-- Meta reintenta las entregas del webhook; un wamid repetido se acusa pero no se re-ejecuta.
+CREATE TABLE IF NOT EXISTS mensajes_procesados (
+    wamid        TEXT PRIMARY KEY,
+    telefono     TEXT NOT NULL,
+    recibido_en  TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX IF NOT EXISTS idx_mensajes_procesados_recibido_en
+    ON mensajes_procesados (recibido_en);
@@ -0,0 +1,29 @@
+-- Mensajes enviados por el bot (wamid devuelto por Meta) y su último estado de entrega.
+CREATE TABLE IF NOT EXISTS mensajes_salientes (
+    wamid                 TEXT PRIMARY KEY,
+    telefono              TEXT NOT NULL,
+    tipo                  TEXT NOT NULL,
+    critico               BOOLEAN NOT NULL DEFAULT false,
+    estado                TEXT NOT NULL DEFAULT 'accepted',
+    rango_estado          SMALLINT NOT NULL DEFAULT 0,
+    error_code            BIGINT,
+    error_detalle         TEXT,
+    requiere_seguimiento  BOOLEAN NOT NULL DEFAULT false,
+    enviado_en            TIMESTAMPTZ NOT NULL DEFAULT now(),
+    actualizado_en        TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+-- Historial de transiciones (sent → delivered → read, o failed) tal como llegan de Meta.
+CREATE TABLE IF NOT EXISTS mensajes_salientes_eventos (
+    id             BIGSERIAL PRIMARY KEY,
+    wamid          TEXT NOT NULL,
+    estado         TEXT NOT NULL,
+    error_code     BIGINT,
+    error_detalle  TEXT,
+    ocurrido_en    TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX IF NOT EXISTS idx_mensajes_salientes_fallidos
+    ON mensajes_salientes (actualizado_en) WHERE estado = 'failed';
+CREATE INDEX IF NOT EXISTS idx_mensajes_salientes_eventos_wamid
+    ON mensajes_salientes_eventos (wamid);
@@ -0,0 +1,16 @@
+-- Cola durable de mensajes entrantes. El webhook solo encola y responde 200;
+-- los workers drenan la cola y, tras un reinicio, retoman lo que quedó pendiente.
+CREATE TABLE IF NOT EXISTS cola_mensajes (
+    id              BIGSERIAL PRIMARY KEY,
+    wamid           TEXT,
+    telefono        TEXT NOT NULL,
+    mensaje         JSONB NOT NULL,
+    estado          TEXT NOT NULL DEFAULT 'pendiente', -- pendiente | procesando | hecho | error
+    intentos        INT NOT NULL DEFAULT 0,
+    ultimo_error    TEXT,
+    creado_en       TIMESTAMPTZ NOT NULL DEFAULT now(),
+    actualizado_en  TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX IF NOT EXISTS idx_cola_mensajes_pendientes
+    ON cola_mensajes (id) WHERE estado IN ('pendiente', 'procesando');
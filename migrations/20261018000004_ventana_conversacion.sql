@@ -0,0 +1,6 @@
+-- Último mensaje entrante de cada teléfono. Meta solo permite mensajes de sesión
+-- (texto, botones, listas) dentro de las 24 horas siguientes; fuera de eso se requiere plantilla.
+CREATE TABLE IF NOT EXISTS ventana_conversacion (
+    telefono         TEXT PRIMARY KEY,
+    ultimo_entrante  TIMESTAMPTZ NOT NULL
+);
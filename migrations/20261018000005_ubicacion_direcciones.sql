@@ -0,0 +1,5 @@
+-- Ubicación compartida por WhatsApp para la entrega. `full_address` sigue guardando el texto
+-- (la dirección del lugar o las coordenadas) para que el resto del sistema no cambie.
+ALTER TABLE patient_addresses ADD COLUMN IF NOT EXISTS latitude DOUBLE PRECISION;
+ALTER TABLE patient_addresses ADD COLUMN IF NOT EXISTS longitude DOUBLE PRECISION;
+ALTER TABLE patient_addresses ADD COLUMN IF NOT EXISTS location_name TEXT;
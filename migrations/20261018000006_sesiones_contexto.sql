@@ -0,0 +1,8 @@
+-- Datos que se van juntando durante una conversación (nombre por confirmar, categoría elegida,
+-- página de la lista, producto en curso). Se guardan junto con el estado de la sesión en la
+-- misma transacción, en lugar de escribir datos provisionales en las columnas del usuario.
+CREATE TABLE IF NOT EXISTS sesiones_contexto (
+    telefono        TEXT PRIMARY KEY,
+    contexto        JSONB NOT NULL DEFAULT '{}'::jsonb,
+    actualizado_en  TIMESTAMPTZ NOT NULL DEFAULT now()
+);
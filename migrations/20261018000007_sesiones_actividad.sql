@@ -0,0 +1,2 @@
+-- Último mensaje del paciente en cada sesión, para vencer las que quedaron a medias.
+ALTER TABLE sesiones_contexto ADD COLUMN IF NOT EXISTS ultima_actividad TIMESTAMPTZ NOT NULL DEFAULT now();
@@ -0,0 +1,12 @@
+-- Pacientes que pidieron hablar con una persona. El equipo de atención las marca como atendidas.
+CREATE TABLE IF NOT EXISTS solicitudes_agente (
+    id BIGSERIAL PRIMARY KEY,
+    telefono TEXT NOT NULL,
+    estado TEXT NOT NULL,
+    creada_en TIMESTAMPTZ NOT NULL DEFAULT now(),
+    atendida_en TIMESTAMPTZ
+);
+
+-- Solo una solicitud abierta por teléfono
+CREATE UNIQUE INDEX IF NOT EXISTS solicitudes_agente_abiertas
+    ON solicitudes_agente (telefono) WHERE atendida_en IS NULL;
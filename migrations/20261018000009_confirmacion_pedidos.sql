@@ -0,0 +1,4 @@
+-- Número corto que ve el paciente al confirmar su pedido por WhatsApp y cuándo lo confirmó.
+ALTER TABLE orders ADD COLUMN IF NOT EXISTS numero_pedido BIGSERIAL;
+ALTER TABLE orders ADD COLUMN IF NOT EXISTS confirmada_en TIMESTAMPTZ;
+CREATE UNIQUE INDEX IF NOT EXISTS orders_numero_pedido ON orders (numero_pedido);
@@ -0,0 +1,28 @@
+-- Un renglón por medicamento en cada orden: volver a agregarlo suma a la cantidad.
+-- Primero juntamos los renglones repetidos que dejó la versión anterior.
+WITH repetidos AS (
+    SELECT order_id, med_id, SUM(quantity) AS cantidad, MIN(item_id::text) AS conservar
+    FROM medication_items
+    GROUP BY order_id, med_id
+    HAVING COUNT(*) > 1
+)
+UPDATE medication_items mi
+SET quantity = r.cantidad
+FROM repetidos r
+WHERE mi.item_id::text = r.conservar;
+
+DELETE FROM medication_items mi
+USING medication_items otro
+WHERE mi.order_id = otro.order_id
+  AND mi.med_id = otro.med_id
+  AND mi.item_id::text > otro.item_id::text;
+
+CREATE UNIQUE INDEX IF NOT EXISTS medication_items_orden_medicamento
+    ON medication_items (order_id, med_id);
+
+-- Totales de órdenes pendientes recalculados desde sus renglones
+UPDATE orders o
+SET total_amount = COALESCE((
+    SELECT SUM(mi.quantity * mi.unit_price) FROM medication_items mi WHERE mi.order_id = o.order_id
+), 0)
+WHERE o.p_status = 'pendiente';
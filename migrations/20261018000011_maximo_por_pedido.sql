@@ -0,0 +1,2 @@
+-- Piezas máximas de un medicamento en un mismo pedido (p. ej. controlados). NULL: sin límite.
+ALTER TABLE medications ADD COLUMN IF NOT EXISTS max_por_pedido INTEGER CHECK (max_por_pedido > 0);
@@ -0,0 +1,48 @@
+-- Existencias reales por medicamento; `stock` solo decía si había o no y ya no se lee.
+-- `reservadas` son las piezas en carritos pendientes: se liberan al quitarlas, cancelar o vencer
+-- el carrito y se descuentan de `existencias` al confirmar el pedido.
+ALTER TABLE medications
+    ADD COLUMN IF NOT EXISTS existencias INTEGER NOT NULL DEFAULT 0 CHECK (existencias >= 0),
+    ADD COLUMN IF NOT EXISTS reservadas INTEGER NOT NULL DEFAULT 0 CHECK (reservadas >= 0),
+    -- Con estas existencias o menos se levanta una alerta para resurtir
+    ADD COLUMN IF NOT EXISTS stock_minimo INTEGER NOT NULL DEFAULT 5 CHECK (stock_minimo >= 0);
+
+-- Lo que ya está en carritos pendientes queda reservado
+UPDATE medications m SET reservadas = r.piezas, existencias = r.piezas
+FROM (
+    SELECT mi.med_id, SUM(mi.quantity) AS piezas
+    FROM medication_items mi
+    JOIN orders o ON o.order_id = mi.order_id
+    WHERE o.p_status = 'pendiente'
+    GROUP BY mi.med_id
+) r
+WHERE m.med_id = r.med_id;
+
+-- Fuera de lo reservado, las existencias empiezan en 0: `stock` no dice cuántas piezas hay.
+-- El conteo real se carga aparte con `biotecza_bot existencias <conteo.csv>`.
+
+-- Nunca se reserva más de lo que hay
+DO $$
+BEGIN
+    IF NOT EXISTS (SELECT 1 FROM pg_constraint WHERE conname = 'medications_reservadas_existencias') THEN
+        ALTER TABLE medications ADD CONSTRAINT medications_reservadas_existencias CHECK (reservadas <= existencias);
+    END IF;
+END $$;
+
+-- Último cambio al carrito, para liberar los que se quedan olvidados
+ALTER TABLE orders ADD COLUMN IF NOT EXISTS carrito_actualizado_en TIMESTAMPTZ NOT NULL DEFAULT now();
+
+-- Medicamentos por resurtir. El bot avisa al equipo de compras (`avisada_en`) y ellos las marcan
+-- como atendidas.
+CREATE TABLE IF NOT EXISTS alertas_inventario (
+    id BIGSERIAL PRIMARY KEY,
+    med_id UUID NOT NULL REFERENCES medications (med_id),
+    existencias INTEGER NOT NULL,
+    creada_en TIMESTAMPTZ NOT NULL DEFAULT now(),
+    avisada_en TIMESTAMPTZ,
+    atendida_en TIMESTAMPTZ
+);
+
+-- Solo una alerta abierta por medicamento
+CREATE UNIQUE INDEX IF NOT EXISTS alertas_inventario_abiertas
+    ON alertas_inventario (med_id) WHERE atendida_en IS NULL;
@@ -0,0 +1,20 @@
+-- Avisos que el bot manda sin que el paciente escriba primero.
+-- El sistema de la farmacia pasa el pedido a 'listo'; el bot avisa y anota cuándo para no repetir.
+ALTER TABLE orders ADD COLUMN IF NOT EXISTS aviso_listo_en TIMESTAMPTZ;
+
+CREATE INDEX IF NOT EXISTS orders_listos_sin_aviso
+    ON orders (order_id) WHERE p_status = 'listo' AND aviso_listo_en IS NULL;
+
+-- Resultados que publica el laboratorio. El archivo lo entrega el laboratorio; aquí solo
+-- queda qué estudio, de quién y cuándo se avisó.
+CREATE TABLE IF NOT EXISTS resultados_laboratorio (
+    id            BIGSERIAL PRIMARY KEY,
+    patient_id    UUID NOT NULL REFERENCES patients (patient_id),
+    test_id       UUID NOT NULL REFERENCES lab_tests (test_id),
+    tomado_en     DATE NOT NULL,
+    publicado_en  TIMESTAMPTZ NOT NULL DEFAULT now(),
+    avisado_en    TIMESTAMPTZ
+);
+
+CREATE INDEX IF NOT EXISTS resultados_laboratorio_sin_aviso
+    ON resultados_laboratorio (id) WHERE avisado_en IS NULL;
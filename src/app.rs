@@ -0,0 +1,13 @@
+use sqlx::PgPool;
+use std::sync::Arc;
+use tokio::sync::mpsc;
+use crate::whatsapp::webhook::ConfigWebhook;
+
+/// Estado compartido por los manejadores de Axum
+#[derive(Clone)]
+pub struct AppState {
+    pub pool: PgPool,
+    // Avisa a los workers que hay un mensaje nuevo en `cola_mensajes`
+    pub cola: mpsc::Sender<i64>,
+    pub webhook: Arc<ConfigWebhook>,
+}
@@ -0,0 +1,196 @@
+//! Simulador de conversaciones: corre `bot_logic::procesar` contra la base local sin pasar por Meta.
+//!
+//! Uso:
+//!     cargo run --bin biotecza_sim -- [--telefono 5215512345678] [--guion conversacion.txt]
+//!
+//! Cada línea es un mensaje del paciente. Un número elige la opción de los últimos botones o lista.
+//! Comandos del simulador:
+//!     /id <accion>                  respuesta con un id de botón (p. ej. /id menu:farmacia)
+//!     /archivo [mime]               adjunta un archivo (receta)
+//!     /ubicacion <lat> <lon> [nombre]
+//!     /contacto <nombre> <telefono>
+//!     /reaccion <emoji>
+//!     /estado                       muestra el estado y el contexto guardados
+//!     /salir
+//! En un guion se ignoran las líneas vacías y las que empiezan con `#`.
+
+use biotecza_bot::bot_logic::{self, UserInput};
+use biotecza_bot::database;
+use biotecza_bot::storage::LocalStorage;
+use biotecza_bot::whatsapp::messenger::{MensajeEnviado, RecordingMessenger};
+use biotecza_bot::whatsapp::payload::{ContactName, ContactPhone, LocationPayload, MediaPayload, SharedContact};
+use sqlx::PgPool;
+use std::io::Write;
+
+/// Opción que el paciente puede elegir escribiendo su número
+struct Opcion {
+    id: String,
+    titulo: String,
+}
+
+/// Qué hacer con una línea escrita en el simulador
+enum Linea {
+    Mensaje(UserInput),
+    Estado,
+    Salir,
+    Invalida(String),
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    dotenvy::dotenv().ok();
+
+    let mut telefono = "5215500000000".to_string();
+    let mut guion: Option<String> = None;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--telefono" => telefono = args.next().ok_or("falta el número después de --telefono")?,
+            "--guion" => guion = Some(args.next().ok_or("falta el archivo después de --guion")?),
+            otro => return Err(format!("argumento desconocido: {}", otro).into()),
+        }
+    }
+
+    let database_url = std::env::var("DATABASE_URL")?;
+    let pool = PgPool::connect(&database_url).await?;
+    sqlx::migrate!("./migrations").run(&pool).await?;
+    bot_logic::flow::flujo().validar().map_err(|errores| errores.join("\n"))?;
+    bot_logic::sesion::migrar_estados(&pool).await;
+
+    let messenger = RecordingMessenger::new();
+    let storage = LocalStorage::new(std::env::temp_dir().join("biotecza_sim"));
+    println!("🧪 Simulador Biotecza — conversando como {} (/salir para terminar)\n", telefono);
+
+    // Un guion se lee completo; en modo interactivo se lee línea por línea para que las respuestas salgan entre mensajes
+    let mut lineas: Box<dyn Iterator<Item = String>> = match &guion {
+        Some(ruta) => Box::new(std::fs::read_to_string(ruta)?.lines().map(str::to_string).collect::<Vec<_>>().into_iter()),
+        None => Box::new(std::io::stdin().lines().map_while(Result::ok)),
+    };
+
+    let mut opciones: Vec<Opcion> = Vec::new();
+    let mut archivos = 0;
+    loop {
+        if guion.is_none() {
+            print!("👤 > ");
+            std::io::stdout().flush()?;
+        }
+        let Some(linea) = lineas.next() else { break };
+        if guion.is_some() {
+            if linea.trim().is_empty() || linea.trim_start().starts_with('#') {
+                continue;
+            }
+            println!("👤 > {}", linea);
+        }
+
+        let entrada = match interpretar(linea.trim(), &opciones, &mut archivos) {
+            Linea::Mensaje(entrada) => entrada,
+            Linea::Estado => {
+                let sesion = database::obtener_sesion(&pool, &telefono).await;
+                println!("📋 {} {:?}\n", sesion.estado, sesion.contexto);
+                continue;
+            }
+            Linea::Salir => break,
+            Linea::Invalida(error) => {
+                println!("⚠️ {}\n", error);
+                continue;
+            }
+        };
+
+        let antes = database::obtener_sesion(&pool, &telefono).await.estado;
+        database::registrar_ultimo_entrante(&pool, &telefono, None).await;
+        bot_logic::procesar(&pool, &messenger, &storage, &telefono, &entrada).await;
+        let despues = database::obtener_sesion(&pool, &telefono).await.estado;
+
+        let nuevas = mostrar(&messenger.tomar());
+        // Los botones siguen vigentes hasta que el bot mande otros
+        if !nuevas.is_empty() {
+            opciones = nuevas;
+        }
+        if antes != despues {
+            println!("🔀 {} → {}", antes, despues);
+        }
+        println!();
+    }
+
+    Ok(())
+}
+
+fn interpretar(linea: &str, opciones: &[Opcion], archivos: &mut usize) -> Linea {
+    if let Ok(n) = linea.parse::<usize>()
+        && let Some(opcion) = n.checked_sub(1).and_then(|i| opciones.get(i))
+    {
+        return Linea::Mensaje(UserInput::desde_respuesta(&opcion.id, &opcion.titulo));
+    }
+
+    let Some(comando) = linea.strip_prefix('/') else {
+        return Linea::Mensaje(UserInput::Texto(linea.to_string()));
+    };
+    let partes: Vec<&str> = comando.split_whitespace().collect();
+    match partes.as_slice() {
+        ["salir"] => Linea::Salir,
+        ["estado"] => Linea::Estado,
+        ["id", id] => Linea::Mensaje(UserInput::desde_respuesta(id, id)),
+        ["archivo", resto @ ..] => {
+            *archivos += 1;
+            Linea::Mensaje(UserInput::Media(MediaPayload {
+                id: format!("sim-{}", archivos),
+                mime_type: resto.first().unwrap_or(&"image/jpeg").to_string(),
+                ..Default::default()
+            }))
+        }
+        ["ubicacion", lat, lon, nombre @ ..] => match (lat.parse(), lon.parse()) {
+            (Ok(latitude), Ok(longitude)) => Linea::Mensaje(UserInput::Ubicacion(LocationPayload {
+                latitude,
+                longitude,
+                name: (!nombre.is_empty()).then(|| nombre.join(" ")),
+                address: None,
+            })),
+            _ => Linea::Invalida("uso: /ubicacion <lat> <lon> [nombre]".to_string()),
+        },
+        ["contacto", nombre, telefono] => Linea::Mensaje(UserInput::Contactos(vec![SharedContact {
+            name: ContactName { formatted_name: nombre.to_string() },
+            phones: vec![ContactPhone { phone: telefono.to_string(), wa_id: None }],
+        }])),
+        ["reaccion", emoji] => Linea::Mensaje(UserInput::Reaccion {
+            message_id: "wamid.simulado".to_string(),
+            emoji: emoji.to_string(),
+        }),
+        _ => Linea::Invalida(format!("comando desconocido: /{}", comando)),
+    }
+}
+
+/// Imprime lo que mandó el bot. Devuelve las opciones numeradas del último mensaje interactivo.
+fn mostrar(mensajes: &[MensajeEnviado]) -> Vec<Opcion> {
+    let mut opciones = Vec::new();
+    for mensaje in mensajes {
+        match mensaje {
+            MensajeEnviado::Texto { texto, .. } => println!("🤖 {}", texto),
+            MensajeEnviado::Botones { botones, .. } => {
+                println!("🤖 {}", botones.texto);
+                opciones = botones.botones.iter()
+                    .map(|b| Opcion { id: b.id.clone(), titulo: b.titulo.clone() })
+                    .collect();
+                for (i, o) in opciones.iter().enumerate() {
+                    println!("   [{}] {}", i + 1, o.titulo);
+                }
+            }
+            MensajeEnviado::Lista { lista, .. } => {
+                println!("🤖 *{}*\n{}", lista.titulo, lista.cuerpo);
+                opciones = lista.filas.iter()
+                    .map(|f| Opcion { id: f.id.clone(), titulo: f.titulo.clone() })
+                    .collect();
+                for (i, (o, f)) in opciones.iter().zip(&lista.filas).enumerate() {
+                    match &f.descripcion {
+                        Some(d) => println!("   [{}] {} — {}", i + 1, o.titulo, d),
+                        None => println!("   [{}] {}", i + 1, o.titulo),
+                    }
+                }
+            }
+            MensajeEnviado::Plantilla { plantilla, .. } => println!("🤖 [plantilla {}] {:?}", plantilla.nombre, plantilla.cuerpo),
+            MensajeEnviado::Media { link, caption, .. } => {
+                println!("🤖 [archivo {}] {}", link, caption.as_deref().unwrap_or_default())
+            }
+        }
+    }
+    opciones
+}
@@ -0,0 +1,177 @@
+use sqlx::PgPool;
+use crate::database;
+use super::flow::{self, Contexto, Saliente, Transicion};
+use super::states::UserState;
+use super::users::bienvenida;
+
+/// Comandos que el paciente puede escribir en cualquier paso de la conversación
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Comando {
+    /// "hola", "inicio": saludo; si la sesión venció primero se ofrece retomarla
+    Saludo,
+    /// "menú": directo al menú principal
+    Menu,
+    /// "cancelar": abandona el paso actual
+    Cancelar,
+    /// "atrás", "regresar": vuelve a la pregunta anterior
+    Atras,
+    /// "ayuda": explica qué espera el paso actual
+    Ayuda,
+    /// "agente": pide hablar con una persona
+    Agente,
+}
+
+impl Comando {
+    /// Reconoce un comando solo si es todo el mensaje ("cancelar", "¡Menú!", "ATRAS"),
+    /// para no confundirlo con una respuesta que lo menciona.
+    pub fn detectar(texto: &str) -> Option<Comando> {
+        match normalizar(texto).as_str() {
+            "hola" | "inicio" => Some(Comando::Saludo),
+            "menu" | "menu principal" | "ir al menu" | "volver al menu" => Some(Comando::Menu),
+            "cancelar" | "cancela" | "salir" => Some(Comando::Cancelar),
+            "atras" | "regresar" | "volver" | "anterior" => Some(Comando::Atras),
+            "ayuda" | "help" => Some(Comando::Ayuda),
+            "agente" | "asesor" | "humano" | "hablar con un agente" | "hablar con un asesor" => Some(Comando::Agente),
+            _ => None,
+        }
+    }
+}
+
+/// Minúsculas, sin acentos, sin signos de puntuación y con un solo espacio entre palabras
+pub fn normalizar(texto: &str) -> String {
+    let sin_acentos: String = texto.to_lowercase().chars()
+        .map(|c| match c {
+            'á' | 'à' | 'ä' | 'â' => 'a',
+            'é' | 'è' | 'ë' | 'ê' => 'e',
+            'í' | 'ì' | 'ï' | 'î' => 'i',
+            'ó' | 'ò' | 'ö' | 'ô' => 'o',
+            'ú' | 'ù' | 'ü' | 'û' => 'u',
+            c if c.is_alphanumeric() => c,
+            _ => ' ',
+        })
+        .collect();
+    sin_acentos.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// Comandos que no necesitan registrar al usuario ni al paciente; se atienden antes de crearlos en la DB.
+/// Devuelve `None` para los que sí los necesitan (saludo y atrás).
+pub async fn atender_sin_paciente(
+    pool: &PgPool,
+    telefono: &str,
+    estado: UserState,
+    comando: Comando,
+) -> Option<Transicion> {
+    let transicion = match comando {
+        Comando::Menu => {
+            let vaciado = descartar_carrito(pool, telefono).await;
+            let mut t = bienvenida(pool, telefono).await;
+            if vaciado {
+                t.mensajes.insert(0, Saliente::Texto("🗑️ Vaciamos tu carrito y liberamos sus piezas.".to_string()));
+            }
+            t
+        }
+        Comando::Cancelar => {
+            let texto = if descartar_carrito(pool, telefono).await {
+                "❌ Listo, cancelamos ese paso y vaciamos tu carrito."
+            } else {
+                "❌ Listo, cancelamos ese paso."
+            };
+            let mut t = bienvenida(pool, telefono).await;
+            t.mensajes.insert(0, Saliente::Texto(texto.to_string()));
+            t
+        }
+        Comando::Ayuda => Transicion::a(estado).texto(format!(
+            "ℹ️ {}\n\n\
+             También puedes escribir:\n\
+             • *menú* para ir al menú principal\n\
+             • *atrás* para volver al paso anterior\n\
+             • *cancelar* para dejar lo que estás haciendo\n\
+             • *agente* para hablar con una persona",
+            flow::flujo().ayuda(estado)
+        )),
+        Comando::Agente => {
+            let texto = if database::registrar_solicitud_agente(pool, telefono, &estado.to_string()).await {
+                println!("🙋 {} pidió hablar con un agente (en {})", telefono, estado);
+                "🙋 Le avisamos a nuestro equipo. Un asesor te escribirá por aquí lo antes posible."
+            } else {
+                "🙋 Ya tenemos tu solicitud; un asesor te escribirá por aquí en cuanto se desocupe."
+            };
+            Transicion::a(estado).texto(texto)
+        }
+        Comando::Saludo | Comando::Atras => return None,
+    };
+    Some(transicion)
+}
+
+/// Igual que el botón "Cancelar Pedido": al dejar la conversación el carrito pendiente se vacía
+/// y sus piezas reservadas vuelven al inventario. Devuelve `true` si tenía productos.
+async fn descartar_carrito(pool: &PgPool, telefono: &str) -> bool {
+    let Some(paciente) = database::obtener_patient_id_por_telefono(pool, telefono).await else { return false };
+    let Some(order_id) = database::obtener_orden_pendiente(pool, paciente.patient_id).await else { return false };
+    let tenia_productos = !database::obtener_carrito(pool, order_id).await.items.is_empty();
+    database::vaciar_carrito(pool, order_id).await;
+    tenia_productos
+}
+
+/// Vuelve a hacer la pregunta del paso anterior. Sin pasos guardados, regresa al menú.
+pub async fn atras(ctx: &Contexto<'_>) -> Transicion {
+    let mut historial = ctx.sesion.historial.clone();
+    let anterior = historial.pop().and_then(|e| UserState::resolver(&e));
+
+    match anterior {
+        Some(estado) => {
+            let transicion = flow::flujo().reanudar(ctx, estado).await;
+            // Si la pregunta anterior trae su propio contexto (p. ej. la página de la lista), conservamos la pila recortada
+            let mut nuevo = transicion.contexto.clone().unwrap_or_else(|| ctx.sesion.clone());
+            nuevo.historial = historial;
+            transicion.contexto(nuevo)
+        }
+        None => bienvenida(ctx.pool, ctx.telefono).await,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalizar_quita_mayusculas_acentos_y_signos() {
+        assert_eq!(normalizar("¡Menú!"), "menu");
+        assert_eq!(normalizar("  ATRÁS  "), "atras");
+        assert_eq!(normalizar("Volver   al\tmenú."), "volver al menu");
+        assert_eq!(normalizar("¿Hablar con un ASESOR?"), "hablar con un asesor");
+        assert_eq!(normalizar("Muñoz"), "muñoz");
+        assert_eq!(normalizar("🙂"), "");
+    }
+
+    #[test]
+    fn detecta_sin_importar_mayusculas_ni_acentos() {
+        for texto in ["menú", "MENU", "menu", "Menú principal", "¡Menú!"] {
+            assert_eq!(Comando::detectar(texto), Some(Comando::Menu), "{}", texto);
+        }
+        for texto in ["cancelar", "CANCELAR", "Cancela.", "salir"] {
+            assert_eq!(Comando::detectar(texto), Some(Comando::Cancelar), "{}", texto);
+        }
+        assert_eq!(Comando::detectar("Atrás"), Some(Comando::Atras));
+        assert_eq!(Comando::detectar("AYUDA"), Some(Comando::Ayuda));
+        assert_eq!(Comando::detectar("Hablar con un agente"), Some(Comando::Agente));
+        assert_eq!(Comando::detectar("Hola!"), Some(Comando::Saludo));
+    }
+
+    #[test]
+    fn palabras_dentro_de_una_respuesta_no_son_comandos() {
+        for texto in [
+            "",
+            "quiero cancelar mi pedido",
+            "el menú de farmacia",
+            "Calle Regresar 12",
+            "hola buenas tardes",
+            "menus",
+            "cancelado",
+            "Ayudante",
+            "tempra",
+        ] {
+            assert_eq!(Comando::detectar(texto), None, "{}", texto);
+        }
+    }
+}
@@ -0,0 +1,185 @@
+use std::str::FromStr;
+use super::flow::{Flujo, ESTADO_INICIAL, GLOBALES};
+use super::states::UserState;
+
+/// Formato del diagrama del flujo de conversación
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Formato {
+    /// Graphviz (`dot -Tsvg`)
+    Dot,
+    /// Mermaid (se ve directo en GitHub y en la wiki)
+    Mermaid,
+}
+
+impl FromStr for Formato {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "dot" => Ok(Formato::Dot),
+            "mermaid" => Ok(Formato::Mermaid),
+            _ => Err(format!("Formato desconocido: {} (usa dot o mermaid)", s)),
+        }
+    }
+}
+
+/// Nodo de los comandos globales ("hola", "menú", "cancelar"…) y de las sesiones vencidas
+const COMANDOS: &str = "COMANDOS";
+const ETIQUETA_COMANDOS: &str = "hola / menú / cancelar / sesión vencida";
+
+/// Diagrama de todos los estados con las entradas que atienden y sus transiciones declaradas
+pub fn generar(flujo: &Flujo, formato: Formato) -> String {
+    match formato {
+        Formato::Dot => dot(flujo),
+        Formato::Mermaid => mermaid(flujo),
+    }
+}
+
+fn dot(flujo: &Flujo) -> String {
+    let mut salida = String::from("digraph flujo {\n    rankdir=LR;\n    node [shape=box, style=rounded, fontname=\"Helvetica\"];\n\n");
+
+    salida.push_str("    INICIO_CONVERSACION [shape=point];\n");
+    salida.push_str(&format!("    {} [shape=ellipse, label=\"{}\"];\n", COMANDOS, ETIQUETA_COMANDOS));
+    for estado in UserState::TODOS {
+        let mut etiqueta = estado.to_string();
+        for entrada in flujo.entradas(estado) {
+            etiqueta.push_str(&format!("\\n• {}", entrada.replace('"', "\\\"")));
+        }
+        salida.push_str(&format!("    {} [label=\"{}\"];\n", estado, etiqueta));
+    }
+
+    salida.push('\n');
+    salida.push_str(&format!("    INICIO_CONVERSACION -> {};\n", ESTADO_INICIAL));
+    for destino in GLOBALES {
+        salida.push_str(&format!("    {} -> {} [style=dashed];\n", COMANDOS, destino));
+    }
+    for (origen, destino) in flujo.aristas() {
+        salida.push_str(&format!("    {} -> {};\n", origen, destino));
+    }
+    salida.push_str("}\n");
+    salida
+}
+
+fn mermaid(flujo: &Flujo) -> String {
+    let mut salida = String::from("flowchart LR\n");
+
+    salida.push_str("    INICIO_CONVERSACION(( ))\n");
+    salida.push_str(&format!("    {}([\"{}\"])\n", COMANDOS, ETIQUETA_COMANDOS));
+    for estado in UserState::TODOS {
+        let mut etiqueta = estado.to_string();
+        for entrada in flujo.entradas(estado) {
+            etiqueta.push_str(&format!("<br/>• {}", entrada.replace('"', "#quot;")));
+        }
+        salida.push_str(&format!("    {}[\"{}\"]\n", estado, etiqueta));
+    }
+
+    salida.push_str(&format!("    INICIO_CONVERSACION --> {}\n", ESTADO_INICIAL));
+    for destino in GLOBALES {
+        salida.push_str(&format!("    {} -.-> {}\n", COMANDOS, destino));
+    }
+    for (origen, destino) in flujo.aristas() {
+        salida.push_str(&format!("    {} --> {}\n", origen, destino));
+    }
+    salida
+}
+
+/// Estados sin ninguna arista que llegue o que salga (sin contar los comandos globales como salida)
+pub fn advertencias(flujo: &Flujo) -> Vec<String> {
+    let aristas = flujo.aristas();
+    let mut advertencias = Vec::new();
+
+    for estado in UserState::TODOS {
+        let entra = estado == ESTADO_INICIAL
+            || GLOBALES.contains(&estado)
+            || aristas.iter().any(|(o, d)| *d == estado && *o != estado);
+        let sale = aristas.iter().any(|(o, d)| *o == estado && *d != estado);
+
+        if !entra {
+            advertencias.push(format!("{} no tiene aristas de entrada", estado));
+        }
+        if !sale {
+            advertencias.push(format!("{} no tiene aristas de salida", estado));
+        }
+    }
+    advertencias
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::flow::{flujo, Contexto, StateHandler, Transicion};
+    use super::super::input::UserInput;
+    use super::super::{lab, pharmacy, sesion, users};
+    use async_trait::async_trait;
+
+    /// Manejador que cambia de estado sin declararlo en `transiciones()`
+    struct SinDeclarar(UserState);
+
+    #[async_trait]
+    impl StateHandler for SinDeclarar {
+        fn estado(&self) -> UserState { self.0 }
+        fn transiciones(&self) -> &'static [UserState] { &[] }
+        fn ayuda(&self) -> &'static str { "" }
+        fn entradas(&self) -> &'static [&'static str] { &[] }
+
+        async fn manejar(&self, _ctx: &Contexto<'_>, _entrada: &UserInput) -> Transicion {
+            Transicion::a(UserState::Inicio)
+        }
+    }
+
+    #[test]
+    fn dot_trae_cada_estado_y_cada_arista() {
+        let salida = generar(flujo(), Formato::Dot);
+        assert!(salida.starts_with("digraph flujo {"));
+        for estado in UserState::TODOS {
+            assert!(salida.contains(&format!("    {} [label=\"{}", estado, estado)), "falta el nodo {}", estado);
+        }
+        assert!(salida.contains(&format!("INICIO_CONVERSACION -> {};", ESTADO_INICIAL)));
+        for destino in GLOBALES {
+            assert!(salida.contains(&format!("{} -> {} [style=dashed];", COMANDOS, destino)));
+        }
+        for (origen, destino) in flujo().aristas() {
+            assert!(salida.contains(&format!("    {} -> {};\n", origen, destino)), "falta {} -> {}", origen, destino);
+        }
+    }
+
+    #[test]
+    fn mermaid_trae_cada_estado_y_cada_arista() {
+        let salida = generar(flujo(), Formato::Mermaid);
+        assert!(salida.starts_with("flowchart LR\n"));
+        for estado in UserState::TODOS {
+            assert!(salida.contains(&format!("    {}[\"{}", estado, estado)), "falta el nodo {}", estado);
+        }
+        assert!(salida.contains(&format!("INICIO_CONVERSACION --> {}\n", ESTADO_INICIAL)));
+        for destino in GLOBALES {
+            assert!(salida.contains(&format!("{} -.-> {}\n", COMANDOS, destino)));
+        }
+        for (origen, destino) in flujo().aristas() {
+            assert!(salida.contains(&format!("    {} --> {}\n", origen, destino)), "falta {} --> {}", origen, destino);
+        }
+    }
+
+    #[test]
+    fn el_flujo_registrado_no_tiene_advertencias() {
+        assert_eq!(advertencias(flujo()), Vec::<String>::new());
+    }
+
+    #[test]
+    fn transicion_sin_declarar_aparece_como_advertencia() {
+        let manejadores = users::manejadores().into_iter()
+            .chain(pharmacy::manejadores())
+            .chain(lab::manejadores())
+            .chain(sesion::manejadores())
+            .chain(std::iter::once(Box::new(SinDeclarar(UserState::EsperandoReceta)) as Box<dyn StateHandler>));
+        let flujo = Flujo::desde(manejadores);
+
+        let advertencias = advertencias(&flujo);
+        // Sin la arista receta → pago, al método de pago tampoco se llega
+        assert_eq!(advertencias, vec![
+            "ESPERANDO_METODO_PAGO no tiene aristas de entrada".to_string(),
+            "ESPERANDO_RECETA no tiene aristas de salida".to_string(),
+        ]);
+        assert!(!generar(&flujo, Formato::Dot).contains("ESPERANDO_RECETA -> "));
+        assert!(!generar(&flujo, Formato::Mermaid).contains("ESPERANDO_RECETA --> "));
+    }
+}
@@ -0,0 +1,288 @@
+use async_trait::async_trait;
+use sqlx::PgPool;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::OnceLock;
+use uuid::Uuid;
+use crate::database;
+use crate::storage::MediaStorage;
+use crate::whatsapp::{Botones, Lista, Messenger};
+use super::input::UserInput;
+use super::models::SessionContext;
+use super::states::UserState;
+use super::{lab, pharmacy, sesion, users};
+
+/// Mensaje que un manejador quiere enviar. El motor los manda en orden después de guardar el estado.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Saliente {
+    Texto(String),
+    /// Texto que debe llegar sí o sí (confirmación de pedido); su falta de entrega queda marcada
+    TextoCritico(String),
+    Botones(Botones),
+    Lista(Lista),
+}
+
+/// Resultado de atender un mensaje: a qué estado pasa la conversación y qué se le responde
+#[derive(Debug, Clone, PartialEq)]
+pub struct Transicion {
+    pub siguiente: UserState,
+    pub mensajes: Vec<Saliente>,
+    /// Nuevo contexto de la sesión; `None` conserva el actual
+    pub contexto: Option<SessionContext>,
+}
+
+impl Transicion {
+    pub fn a(siguiente: UserState) -> Self {
+        Transicion { siguiente, mensajes: Vec::new(), contexto: None }
+    }
+
+    pub fn contexto(mut self, contexto: SessionContext) -> Self {
+        self.contexto = Some(contexto);
+        self
+    }
+
+    pub fn texto(mut self, texto: impl Into<String>) -> Self {
+        self.mensajes.push(Saliente::Texto(texto.into()));
+        self
+    }
+
+    pub fn texto_critico(mut self, texto: impl Into<String>) -> Self {
+        self.mensajes.push(Saliente::TextoCritico(texto.into()));
+        self
+    }
+
+    pub fn botones(mut self, botones: Botones) -> Self {
+        self.mensajes.push(Saliente::Botones(botones));
+        self
+    }
+
+    pub fn lista(mut self, lista: Lista) -> Self {
+        self.mensajes.push(Saliente::Lista(lista));
+        self
+    }
+}
+
+/// Lo que un manejador necesita para atender un mensaje
+pub struct Contexto<'a> {
+    pub pool: &'a PgPool,
+    /// Solo para descargar archivos; las respuestas van en la `Transicion`
+    pub messenger: &'a dyn Messenger,
+    pub storage: &'a dyn MediaStorage,
+    pub telefono: &'a str,
+    pub user_id: Uuid,
+    pub patient_id: Uuid,
+    /// Contexto guardado con el estado actual
+    pub sesion: SessionContext,
+}
+
+/// Manejador de un estado de la conversación
+#[async_trait]
+pub trait StateHandler: Send + Sync {
+    fn estado(&self) -> UserState;
+
+    /// Estados a los que este manejador puede llevar la conversación (además de quedarse igual)
+    fn transiciones(&self) -> &'static [UserState];
+
+    /// Qué espera este estado, para el comando "ayuda"
+    fn ayuda(&self) -> &'static str;
+
+    /// Botones y respuestas que este estado sabe atender, para documentar el flujo
+    fn entradas(&self) -> &'static [&'static str];
+
+    async fn manejar(&self, ctx: &Contexto<'_>, entrada: &UserInput) -> Transicion;
+
+    /// Vuelve a hacer la pregunta de este estado cuando el paciente retoma una sesión vencida
+    async fn reanudar(&self, _ctx: &Contexto<'_>) -> Transicion {
+        Transicion::a(self.estado()).texto("👍 Sigamos donde te quedaste.")
+    }
+}
+
+/// Estados a los que se llega desde cualquier parte: los comandos globales o una sesión vencida
+pub const GLOBALES: &[UserState] = &[UserState::Inicio, UserState::EsperandoNombre, UserState::ReanudandoSesion];
+
+/// Estado en el que empieza un teléfono que nunca nos ha escrito
+pub const ESTADO_INICIAL: UserState = UserState::Nuevo;
+
+/// Tabla de manejadores, uno por estado
+pub struct Flujo {
+    manejadores: HashMap<UserState, Box<dyn StateHandler>>,
+}
+
+impl Flujo {
+    /// Junta los manejadores de cada módulo del bot
+    pub fn cargar() -> Self {
+        Flujo::desde(
+            users::manejadores().into_iter()
+                .chain(pharmacy::manejadores())
+                .chain(lab::manejadores())
+                .chain(sesion::manejadores()),
+        )
+    }
+
+    /// Tabla con estos manejadores; si dos atienden el mismo estado gana el último
+    pub fn desde(todos: impl IntoIterator<Item = Box<dyn StateHandler>>) -> Self {
+        let mut manejadores: HashMap<UserState, Box<dyn StateHandler>> = HashMap::new();
+        for m in todos {
+            if let Some(anterior) = manejadores.insert(m.estado(), m) {
+                eprintln!("⚠️ Manejador duplicado para {}", anterior.estado());
+            }
+        }
+        Flujo { manejadores }
+    }
+
+    /// Transiciones declaradas, para validarlas o dibujarlas
+    pub fn aristas(&self) -> Vec<(UserState, UserState)> {
+        UserState::TODOS.iter()
+            .filter_map(|e| self.manejadores.get(e))
+            .flat_map(|m| m.transiciones().iter().map(move |d| (m.estado(), *d)))
+            .collect()
+    }
+
+    /// Revisa al arrancar que cada estado tenga manejador, que se pueda llegar a él desde
+    /// `ESTADO_INICIAL` o los `GLOBALES` y que desde él se pueda volver a `Inicio` (sin callejones sin salida).
+    pub fn validar(&self) -> Result<(), Vec<String>> {
+        let mut errores = Vec::new();
+
+        for estado in UserState::TODOS {
+            if !self.manejadores.contains_key(&estado) {
+                errores.push(format!("{} no tiene manejador", estado));
+            }
+        }
+
+        let aristas = self.aristas();
+        let alcanzables: HashSet<UserState> = std::iter::once(&ESTADO_INICIAL).chain(GLOBALES)
+            .flat_map(|origen| recorrer(*origen, &aristas))
+            .collect();
+        let hacia_inicio: Vec<(UserState, UserState)> = aristas.iter().map(|(o, d)| (*d, *o)).collect();
+        let regresan = recorrer(UserState::Inicio, &hacia_inicio);
+
+        for estado in UserState::TODOS {
+            if !alcanzables.contains(&estado) {
+                errores.push(format!("{} no es alcanzable", estado));
+            }
+            if !regresan.contains(&estado) {
+                errores.push(format!("{} es un callejón sin salida (no regresa a INICIO)", estado));
+            }
+        }
+
+        if errores.is_empty() { Ok(()) } else { Err(errores) }
+    }
+
+    /// Atiende un mensaje con el manejador del estado actual
+    pub async fn ejecutar(&self, ctx: &Contexto<'_>, estado: UserState, entrada: &UserInput) {
+        let Some(manejador) = self.manejadores.get(&estado) else {
+            eprintln!("❌ Sin manejador para {}", estado);
+            return;
+        };
+
+        let transicion = manejador.manejar(ctx, entrada).await;
+        let declarada = transicion.siguiente == estado
+            || manejador.transiciones().contains(&transicion.siguiente)
+            || GLOBALES.contains(&transicion.siguiente)
+            || ctx.sesion.estado_anterior.as_deref() == Some(transicion.siguiente.to_string().as_str());
+        if !declarada {
+            eprintln!(
+                "⚠️ Transición no declarada {} → {}",
+                estado, transicion.siguiente
+            );
+        }
+
+        // Recordamos el paso anterior para "atrás"; el menú principal empieza la pila de cero
+        let mut transicion = transicion;
+        if transicion.siguiente != estado && estado != UserState::ReanudandoSesion {
+            let mut contexto = transicion.contexto.take().unwrap_or_else(|| ctx.sesion.clone());
+            if transicion.siguiente == UserState::Inicio {
+                contexto.historial.clear();
+            } else {
+                contexto.historial.push(estado.to_string());
+                if contexto.historial.len() > MAX_HISTORIAL {
+                    contexto.historial.remove(0);
+                }
+            }
+            transicion.contexto = Some(contexto);
+        }
+
+        self.aplicar(ctx, estado, transicion).await;
+    }
+
+    /// Texto de ayuda del estado actual
+    pub fn ayuda(&self, estado: UserState) -> &'static str {
+        self.manejadores.get(&estado).map(|m| m.ayuda()).unwrap_or("Responde al último mensaje que te enviamos.")
+    }
+
+    /// Entradas que atiende el estado, para el diagrama
+    pub fn entradas(&self, estado: UserState) -> &'static [&'static str] {
+        self.manejadores.get(&estado).map(|m| m.entradas()).unwrap_or(&[])
+    }
+
+    /// Pregunta del estado al que regresa una sesión vencida (o "atrás")
+    pub async fn reanudar(&self, ctx: &Contexto<'_>, estado: UserState) -> Transicion {
+        match self.manejadores.get(&estado) {
+            Some(manejador) => manejador.reanudar(ctx).await,
+            None => Transicion::a(UserState::Inicio),
+        }
+    }
+
+    /// Guarda el nuevo estado (con su contexto) y envía los mensajes
+    pub async fn aplicar(&self, ctx: &Contexto<'_>, estado: UserState, transicion: Transicion) {
+        aplicar(ctx.pool, ctx.messenger, ctx.telefono, estado, &ctx.sesion, transicion).await;
+    }
+}
+
+/// Pasos que se recuerdan para "atrás"
+const MAX_HISTORIAL: usize = 10;
+
+/// Igual que `Flujo::aplicar`, para quien todavía no tiene un `Contexto` (los comandos globales
+/// se atienden antes de buscar al usuario y al paciente)
+pub async fn aplicar(
+    pool: &PgPool,
+    messenger: &dyn Messenger,
+    telefono: &str,
+    estado: UserState,
+    sesion: &SessionContext,
+    transicion: Transicion,
+) {
+    if transicion.siguiente != estado || transicion.contexto.is_some() {
+        let contexto = transicion.contexto.as_ref().unwrap_or(sesion);
+        database::guardar_sesion(pool, telefono, &transicion.siguiente.to_string(), contexto).await;
+    }
+
+    for mensaje in transicion.mensajes {
+        match mensaje {
+            Saliente::Texto(t) => { messenger.enviar_texto(telefono, &t).await; }
+            Saliente::TextoCritico(t) => { messenger.enviar_texto_critico(telefono, &t).await; }
+            Saliente::Botones(b) => { messenger.enviar_botones(telefono, b).await; }
+            Saliente::Lista(l) => { messenger.enviar_lista(telefono, l).await; }
+        }
+    }
+}
+
+/// Estados alcanzables desde `origen` siguiendo las aristas
+fn recorrer(origen: UserState, aristas: &[(UserState, UserState)]) -> HashSet<UserState> {
+    let mut vistos = HashSet::from([origen]);
+    let mut pendientes = VecDeque::from([origen]);
+    while let Some(actual) = pendientes.pop_front() {
+        for (_, destino) in aristas.iter().filter(|(o, _)| *o == actual) {
+            if vistos.insert(*destino) {
+                pendientes.push_back(*destino);
+            }
+        }
+    }
+    vistos
+}
+
+static FLUJO: OnceLock<Flujo> = OnceLock::new();
+
+/// Tabla de manejadores compartida por todos los workers
+pub fn flujo() -> &'static Flujo {
+    FLUJO.get_or_init(Flujo::cargar)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn el_flujo_registrado_es_valido() {
+        assert_eq!(flujo().validar(), Ok(()));
+    }
+}
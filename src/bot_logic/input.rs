@@ -0,0 +1,291 @@
+use std::fmt;
+use std::str::FromStr;
+use uuid::Uuid;
+use crate::whatsapp::payload::{InboundMessage, LocationPayload, MediaPayload, SharedContact};
+
+/// Acción que viaja en el id de un botón o de una fila de lista.
+/// El título que ve el paciente puede cambiar libremente; el id no.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Accion {
+    // Menú principal
+    Laboratorio,
+    Medicamentos,
+
+    // Registro
+    NombreCorrecto,
+    CorregirNombre,
+    Genero(String),
+
+    // Farmacia
+    Buscar,
+    VerLista,
+    Regresar,
+    AgregarMas,
+    FinalizarPedido,
+    CancelarPedido,
+    ConfirmarPedido,
+    EditarPedido,
+    Categoria(String),
+    AgregarMed(Uuid),
+    Cantidad(i32),
+
+    // Ver carrito: un renglón por medicamento
+    VerCarrito,
+    SumarPieza(Uuid),
+    RestarPieza(Uuid),
+    QuitarProducto(Uuid),
+
+    // Entrega y pago
+    UsarDireccion,
+    OtraDireccion,
+    MetodoPago(String),
+
+    // Laboratorio
+    Estudio(String),
+
+    // Sesión vencida
+    Continuar,
+    EmpezarDeNuevo,
+
+    // Fila "Ver más" de una lista paginada
+    VerMas { pagina: usize, contexto: String },
+    // "Ver más" de los resultados de una búsqueda: lleva el término para repetirla
+    VerMasBusqueda { pagina: usize, busqueda: String },
+}
+
+impl fmt::Display for Accion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Accion::Laboratorio => write!(f, "menu:lab"),
+            Accion::Medicamentos => write!(f, "menu:farmacia"),
+            Accion::NombreCorrecto => write!(f, "nombre:ok"),
+            Accion::CorregirNombre => write!(f, "nombre:corregir"),
+            Accion::Genero(g) => write!(f, "genero:{}", g),
+            Accion::Buscar => write!(f, "farmacia:buscar"),
+            Accion::VerLista => write!(f, "farmacia:lista"),
+            Accion::Regresar => write!(f, "nav:regresar"),
+            Accion::AgregarMas => write!(f, "carrito:mas"),
+            Accion::FinalizarPedido => write!(f, "carrito:finalizar"),
+            Accion::CancelarPedido => write!(f, "carrito:cancelar"),
+            Accion::ConfirmarPedido => write!(f, "pedido:confirmar"),
+            Accion::EditarPedido => write!(f, "pedido:editar"),
+            Accion::Categoria(c) => write!(f, "cat:{}", c),
+            Accion::AgregarMed(id) => write!(f, "add_med:{}", id),
+            Accion::Cantidad(n) => write!(f, "cantidad:{}", n),
+            Accion::VerCarrito => write!(f, "carrito:ver"),
+            Accion::SumarPieza(id) => write!(f, "item_mas:{}", id),
+            Accion::RestarPieza(id) => write!(f, "item_menos:{}", id),
+            Accion::QuitarProducto(id) => write!(f, "item_quitar:{}", id),
+            Accion::UsarDireccion => write!(f, "direccion:usar"),
+            Accion::OtraDireccion => write!(f, "direccion:otra"),
+            Accion::MetodoPago(m) => write!(f, "pago:{}", m),
+            Accion::Estudio(e) => write!(f, "estudio:{}", e),
+            Accion::Continuar => write!(f, "sesion:continuar"),
+            Accion::EmpezarDeNuevo => write!(f, "sesion:reiniciar"),
+            Accion::VerMas { pagina, contexto } => write!(f, "ver_mas:{}:{}", pagina, contexto),
+            Accion::VerMasBusqueda { pagina, busqueda } => write!(f, "ver_mas_busqueda:{}:{}", pagina, busqueda),
+        }
+    }
+}
+
+impl FromStr for Accion {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (tipo, valor) = s.split_once(':').ok_or_else(|| format!("Id sin prefijo: {}", s))?;
+        match (tipo, valor) {
+            ("menu", "lab") => Ok(Accion::Laboratorio),
+            ("menu", "farmacia") => Ok(Accion::Medicamentos),
+            ("nombre", "ok") => Ok(Accion::NombreCorrecto),
+            ("nombre", "corregir") => Ok(Accion::CorregirNombre),
+            ("genero", g) => Ok(Accion::Genero(g.to_string())),
+            ("farmacia", "buscar") => Ok(Accion::Buscar),
+            ("farmacia", "lista") => Ok(Accion::VerLista),
+            ("nav", "regresar") => Ok(Accion::Regresar),
+            ("carrito", "mas") => Ok(Accion::AgregarMas),
+            ("carrito", "finalizar") => Ok(Accion::FinalizarPedido),
+            ("carrito", "cancelar") => Ok(Accion::CancelarPedido),
+            ("pedido", "confirmar") => Ok(Accion::ConfirmarPedido),
+            ("pedido", "editar") => Ok(Accion::EditarPedido),
+            ("cat", c) => Ok(Accion::Categoria(c.to_string())),
+            ("add_med", id) => Uuid::parse_str(id).map(Accion::AgregarMed).map_err(|e| e.to_string()),
+            ("cantidad", n) => n.parse().map(Accion::Cantidad).map_err(|_| format!("Cantidad inválida: {}", s)),
+            ("carrito", "ver") => Ok(Accion::VerCarrito),
+            ("item_mas", id) => Uuid::parse_str(id).map(Accion::SumarPieza).map_err(|e| e.to_string()),
+            ("item_menos", id) => Uuid::parse_str(id).map(Accion::RestarPieza).map_err(|e| e.to_string()),
+            ("item_quitar", id) => Uuid::parse_str(id).map(Accion::QuitarProducto).map_err(|e| e.to_string()),
+            ("direccion", "usar") => Ok(Accion::UsarDireccion),
+            ("direccion", "otra") => Ok(Accion::OtraDireccion),
+            ("pago", m) => Ok(Accion::MetodoPago(m.to_string())),
+            ("estudio", e) => Ok(Accion::Estudio(e.to_string())),
+            ("sesion", "continuar") => Ok(Accion::Continuar),
+            ("sesion", "reiniciar") => Ok(Accion::EmpezarDeNuevo),
+            ("ver_mas", resto) => {
+                let (pagina, contexto) = resto.split_once(':').ok_or_else(|| format!("Ver más inválido: {}", s))?;
+                let pagina = pagina.parse().map_err(|_| format!("Página inválida: {}", s))?;
+                Ok(Accion::VerMas { pagina, contexto: contexto.to_string() })
+            }
+            ("ver_mas_busqueda", resto) => {
+                let (pagina, busqueda) = resto.split_once(':').ok_or_else(|| format!("Ver más inválido: {}", s))?;
+                let pagina = pagina.parse().map_err(|_| format!("Página inválida: {}", s))?;
+                Ok(Accion::VerMasBusqueda { pagina, busqueda: busqueda.to_string() })
+            }
+            _ => Err(format!("Acción desconocida: {}", s)),
+        }
+    }
+}
+
+/// Lo que mandó el paciente, ya decodificado
+#[derive(Debug, Clone, PartialEq)]
+pub enum UserInput {
+    /// Texto escrito a mano
+    Texto(String),
+    /// Botón o fila de lista con un id conocido
+    Accion(Accion),
+    /// Foto o documento adjunto
+    Media(MediaPayload),
+    /// Ubicación compartida (pin del mapa)
+    Ubicacion(LocationPayload),
+    /// Uno o más contactos compartidos
+    Contactos(Vec<SharedContact>),
+    /// Reacción con emoji a un mensaje nuestro
+    Reaccion { message_id: String, emoji: String },
+    /// Stickers, audios, videos y cualquier tipo que todavía no manejamos
+    NoSoportado(String),
+}
+
+impl UserInput {
+    pub fn desde_mensaje(msg: &InboundMessage) -> Self {
+        if let Some(media) = msg.media() {
+            return UserInput::Media(media.clone());
+        }
+
+        if let Some(ubicacion) = &msg.location {
+            return UserInput::Ubicacion(ubicacion.clone());
+        }
+
+        if !msg.contacts.is_empty() {
+            return UserInput::Contactos(msg.contacts.clone());
+        }
+
+        if let Some(r) = &msg.reaction {
+            return UserInput::Reaccion { message_id: r.message_id.clone(), emoji: r.emoji.clone() };
+        }
+
+        if let Some(i) = &msg.interactive
+            && let Some(reply) = i.button_reply.as_ref().or(i.list_reply.as_ref())
+        {
+            return UserInput::desde_respuesta(&reply.id, &reply.title);
+        }
+
+        // Botón de plantilla: el payload lleva la acción
+        if let Some(b) = &msg.button
+            && let Ok(accion) = Accion::from_str(&b.payload)
+        {
+            return UserInput::Accion(accion);
+        }
+
+        let texto = msg.texto();
+        if texto.is_empty() && msg.tipo != "text" {
+            return UserInput::NoSoportado(msg.tipo.clone());
+        }
+        UserInput::Texto(texto)
+    }
+
+    /// Respuesta a un botón o fila de lista.
+    /// Ids de mensajes viejos (cuando el id era el título) se tratan como texto.
+    pub fn desde_respuesta(id: &str, titulo: &str) -> Self {
+        match Accion::from_str(id) {
+            Ok(accion) => UserInput::Accion(accion),
+            Err(_) => UserInput::Texto(titulo.to_string()),
+        }
+    }
+
+    /// Texto escrito por el paciente ("" si fue otra cosa)
+    pub fn texto(&self) -> &str {
+        match self {
+            UserInput::Texto(t) => t.as_str(),
+            _ => "",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ida_y_vuelta(accion: Accion) {
+        let id = accion.to_string();
+        assert_eq!(id.parse::<Accion>(), Ok(accion), "{}", id);
+    }
+
+    #[test]
+    fn cada_accion_regresa_igual_desde_su_id() {
+        let med = Uuid::parse_str("7d1c2b0e-3f4a-4c5d-9e8f-0a1b2c3d4e5f").unwrap();
+        let acciones = [
+            Accion::Laboratorio,
+            Accion::Medicamentos,
+            Accion::NombreCorrecto,
+            Accion::CorregirNombre,
+            Accion::Genero("F".to_string()),
+            Accion::Buscar,
+            Accion::VerLista,
+            Accion::Regresar,
+            Accion::AgregarMas,
+            Accion::FinalizarPedido,
+            Accion::CancelarPedido,
+            Accion::ConfirmarPedido,
+            Accion::EditarPedido,
+            Accion::Categoria("Analgésicos".to_string()),
+            Accion::AgregarMed(med),
+            Accion::Cantidad(3),
+            Accion::VerCarrito,
+            Accion::SumarPieza(med),
+            Accion::RestarPieza(med),
+            Accion::QuitarProducto(med),
+            Accion::UsarDireccion,
+            Accion::OtraDireccion,
+            Accion::MetodoPago("tarjeta".to_string()),
+            Accion::Estudio("Biometría hemática".to_string()),
+            Accion::Continuar,
+            Accion::EmpezarDeNuevo,
+            Accion::VerMas { pagina: 2, contexto: "Analgésicos".to_string() },
+            Accion::VerMas { pagina: 0, contexto: String::new() },
+            Accion::VerMasBusqueda { pagina: 1, busqueda: "tempra".to_string() },
+        ];
+        for accion in acciones {
+            ida_y_vuelta(accion);
+        }
+    }
+
+    #[test]
+    fn el_termino_de_busqueda_puede_traer_dos_puntos() {
+        ida_y_vuelta(Accion::VerMasBusqueda { pagina: 3, busqueda: "vitamina: c 500".to_string() });
+        ida_y_vuelta(Accion::Categoria("Salud: piel".to_string()));
+        assert_eq!(
+            "ver_mas_busqueda:1:a:b:c".parse::<Accion>(),
+            Ok(Accion::VerMasBusqueda { pagina: 1, busqueda: "a:b:c".to_string() })
+        );
+    }
+
+    #[test]
+    fn ids_sin_prefijo_o_desconocidos_se_rechazan() {
+        assert!("farmacia".parse::<Accion>().unwrap_err().contains("sin prefijo"));
+        assert!("".parse::<Accion>().is_err());
+        assert!("menu:otro".parse::<Accion>().unwrap_err().contains("desconocida"));
+        assert!("promo:2x1".parse::<Accion>().unwrap_err().contains("desconocida"));
+    }
+
+    #[test]
+    fn uuid_cantidad_o_pagina_invalidos_se_rechazan() {
+        for id in ["add_med:no-es-uuid", "item_mas:123", "item_menos:", "item_quitar:zzzz"] {
+            assert!(id.parse::<Accion>().is_err(), "{}", id);
+        }
+        assert!("cantidad:dos".parse::<Accion>().unwrap_err().contains("Cantidad inválida"));
+        assert!("ver_mas:x:Analgésicos".parse::<Accion>().unwrap_err().contains("Página inválida"));
+        assert!("ver_mas:-1:Analgésicos".parse::<Accion>().unwrap_err().contains("Página inválida"));
+        assert!("ver_mas:3".parse::<Accion>().unwrap_err().contains("Ver más inválido"));
+        assert!("ver_mas_busqueda:uno:tempra".parse::<Accion>().unwrap_err().contains("Página inválida"));
+        assert!("ver_mas_busqueda:2".parse::<Accion>().unwrap_err().contains("Ver más inválido"));
+    }
+}
@@ -0,0 +1,46 @@
+use uuid::Uuid;
+
+/// Lee el conteo de inventario en CSV: una línea `med_id,existencias` por medicamento.
+/// Se ignoran las líneas vacías, las que empiezan con `#` y el encabezado `med_id,existencias`.
+pub fn leer_conteo(csv: &str) -> Result<Vec<(Uuid, i32)>, String> {
+    let mut conteo = Vec::new();
+    for (i, linea) in csv.lines().enumerate() {
+        let linea = linea.trim().trim_start_matches('\u{feff}');
+        if linea.is_empty() || linea.starts_with('#') || linea.eq_ignore_ascii_case("med_id,existencias") {
+            continue;
+        }
+        let (med_id, existencias) = linea.split_once(',')
+            .ok_or_else(|| format!("línea {}: se esperaba med_id,existencias", i + 1))?;
+        let med_id = med_id.trim().parse::<Uuid>()
+            .map_err(|_| format!("línea {}: '{}' no es un med_id", i + 1, med_id.trim()))?;
+        let existencias = existencias.trim().parse::<i32>().ok().filter(|n| *n >= 0)
+            .ok_or_else(|| format!("línea {}: '{}' no es una cantidad de piezas", i + 1, existencias.trim()))?;
+        conteo.push((med_id, existencias));
+    }
+    if conteo.is_empty() {
+        return Err("el archivo no trae ningún conteo".to_string());
+    }
+    Ok(conteo)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEMPRA: &str = "6f1c2b1e-3d4a-4c5b-9e8f-0a1b2c3d4e5f";
+
+    #[test]
+    fn lee_el_conteo_sin_encabezado_ni_comentarios() {
+        let csv = format!("med_id,existencias\n# conteo del 18 de octubre\n\n{} , 40\n", TEMPRA);
+        assert_eq!(leer_conteo(&csv), Ok(vec![(TEMPRA.parse().unwrap(), 40)]));
+    }
+
+    #[test]
+    fn rechaza_lineas_mal_formadas() {
+        assert!(leer_conteo("").is_err());
+        assert!(leer_conteo(TEMPRA).unwrap_err().contains("línea 1"));
+        assert!(leer_conteo("tempra,40").unwrap_err().contains("no es un med_id"));
+        assert!(leer_conteo(&format!("{},-3", TEMPRA)).unwrap_err().contains("no es una cantidad"));
+        assert!(leer_conteo(&format!("{},muchas", TEMPRA)).unwrap_err().contains("no es una cantidad"));
+    }
+}
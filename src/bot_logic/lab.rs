@@ -1,31 +1,72 @@
+use async_trait::async_trait;
 use sqlx::PgPool;
-use crate::{database, whatsapp};
+use crate::database;
+use crate::whatsapp::{Botones, Lista};
+use super::flow::{Contexto, StateHandler, Transicion};
+use super::models::SessionContext;
 use super::states::UserState;
+use super::input::{Accion, UserInput};
+use super::users::bienvenida;
 
-pub async fn procesar_lab(
-    pool: &PgPool,
-    telefono: &str,
-    entrada: &str,
-    estado: UserState,
-) -> bool {
-    match estado {
-        UserState::SeleccionandoExamen => {
-            if let Some(estudio) = database::obtener_detalle_estudio(pool, entrada).await {
-                let mensaje = format!(
-                    "✅ *Información del Estudio*\n━━━━━━━━━━━━━━━\n\n🧪 *{}*\n📝 *Instrucciones:* {}\n💰 *Precio:* ${}\n\n¿Deseas consultar otro estudio?",
-                    estudio.test_name.to_uppercase(), estudio.instructions, estudio.price
-                );
-                whatsapp::enviar_texto(telefono, &mensaje).await;
-                
-                let estudios = database::obtener_nombres_estudios(pool).await;
-                whatsapp::enviar_lista(telefono, "🔬 Otros Estudios", "Selecciona otro:", "Ver Estudios", estudios).await;
-                whatsapp::enviar_botones(telefono, "O vuelve al inicio:", vec!["Regresar"]).await;
-            } else if entrada == "Regresar" {
-                super::users::enviar_bienvenida(pool, telefono).await;
+pub fn manejadores() -> Vec<Box<dyn StateHandler>> {
+    vec![Box::new(SeleccionandoExamenHandler)]
+}
+
+/// Lista interactiva con los estudios del catálogo, paginada con "Ver más"
+pub async fn lista_estudios(pool: &PgPool, titulo: &str, cuerpo: &str, pagina: usize) -> Lista {
+    database::obtener_nombres_estudios(pool).await.iter()
+        .fold(Lista::new(titulo, cuerpo, "Ver Estudios"), |l, e| l.fila(Accion::Estudio(e.clone()), e))
+        .paginar(pagina, |siguiente| Accion::VerMas { pagina: siguiente, contexto: String::new() }.to_string())
+}
+
+struct SeleccionandoExamenHandler;
+
+#[async_trait]
+impl StateHandler for SeleccionandoExamenHandler {
+    fn estado(&self) -> UserState { UserState::SeleccionandoExamen }
+    fn transiciones(&self) -> &'static [UserState] { &[UserState::Inicio, UserState::EsperandoNombre] }
+
+    fn ayuda(&self) -> &'static str { "Elige un estudio de la lista o escribe su nombre para ver su precio e indicaciones." }
+    fn entradas(&self) -> &'static [&'static str] { &["estudio (lista o texto)", "Ver más", "Regresar"] }
+
+    async fn reanudar(&self, ctx: &Contexto<'_>) -> Transicion {
+        Transicion::a(UserState::SeleccionandoExamen)
+            .lista(lista_estudios(ctx.pool, "🔬 Estudios", "Selecciona un estudio:", ctx.sesion.pagina).await)
+    }
+
+    async fn manejar(&self, ctx: &Contexto<'_>, entrada: &UserInput) -> Transicion {
+        let nombre = match entrada {
+            // "Ver más" en la lista de estudios: mandamos la siguiente página
+            UserInput::Accion(Accion::VerMas { pagina, .. }) => {
+                let lista = lista_estudios(ctx.pool, "🔬 Estudios", "Selecciona un estudio:", *pagina).await;
+                let (lista, pagina) = if lista.filas.is_empty() {
+                    (lista_estudios(ctx.pool, "🔬 Estudios", "Selecciona un estudio:", 0).await, 0)
+                } else {
+                    (lista, *pagina)
+                };
+                return Transicion::a(UserState::SeleccionandoExamen)
+                    .lista(lista)
+                    .contexto(SessionContext { pagina, ..ctx.sesion.clone() });
             }
-            true
-        },
+            UserInput::Accion(Accion::Estudio(e)) => e.as_str(),
+            UserInput::Accion(Accion::Regresar) => return bienvenida(ctx.pool, ctx.telefono).await,
+            otro => otro.texto(),
+        };
+
+        let Some(estudio) = database::obtener_detalle_estudio(ctx.pool, nombre).await else {
+            return Transicion::a(UserState::SeleccionandoExamen)
+                .lista(lista_estudios(ctx.pool, "🔬 Estudios", "No encontré ese estudio. Selecciona uno de la lista:", 0).await);
+        };
+
+        let mensaje = format!(
+            "✅ *Información del Estudio*\n━━━━━━━━━━━━━━━\n\n🧪 *{}*\n📝 *Instrucciones:* {}\n💰 *Precio:* ${}\n\n¿Deseas consultar otro estudio?",
+            estudio.test_name.to_uppercase(), estudio.instructions, estudio.price
+        );
 
-        _ => false,
+        Transicion::a(UserState::SeleccionandoExamen)
+            .texto(mensaje)
+            .lista(lista_estudios(ctx.pool, "🔬 Otros Estudios", "Selecciona otro:", 0).await)
+            .botones(Botones::new("O vuelve al inicio:").boton(Accion::Regresar, "Regresar"))
+            .contexto(SessionContext { pagina: 0, ..ctx.sesion.clone() })
     }
 }
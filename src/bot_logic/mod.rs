@@ -4,23 +4,50 @@ pub mod pharmacy;
 pub mod lab;
 pub mod states;
 pub mod models;
-use crate::whatsapp;
+pub mod input;
+pub mod notificaciones;
+pub mod flow;
+pub mod sesion;
+pub mod comandos;
+pub mod diagrama;
+pub mod inventario;
+use crate::whatsapp::Messenger;
 
 // Re-exportar funciones principales
-pub use users::{enviar_bienvenida, procesar_usuario};
-pub use pharmacy::procesar_farmacia;
-pub use lab::procesar_lab;
 pub use states::UserState;
+pub use input::UserInput;
 
 use sqlx::PgPool;
 use crate::database;
-use std::str::FromStr;
+use crate::storage::MediaStorage;
+use flow::Contexto;
+use comandos::Comando;
 
-pub async fn procesar(pool: &PgPool, telefono: &str, entrada: &str) {
-    let estado_str: String = database::obtener_estado(pool, telefono).await;
-    let estado = UserState::from_str(&estado_str).unwrap_or(UserState::Nuevo);
-    
-    // 1. Identificación de IDs
+pub async fn procesar(pool: &PgPool, messenger: &dyn Messenger, storage: &dyn MediaStorage, telefono: &str, entrada: &UserInput) {
+    let guardada = database::obtener_sesion(pool, telefono).await;
+    let estado = match UserState::resolver(&guardada.estado) {
+        Some(estado) => estado,
+        None => {
+            // Un estado renombrado sin alias no debe mandar a un paciente registrado a registrarse otra vez
+            let registrado = database::obtener_usuario_por_telefono(pool, telefono).await.is_some();
+            let respaldo = if registrado { UserState::Inicio } else { UserState::Nuevo };
+            eprintln!("⚠️ Estado desconocido '{}' en la sesión de {}; se trata como {}", guardada.estado, telefono, respaldo);
+            respaldo
+        }
+    };
+    database::registrar_actividad(pool, telefono).await;
+    let flujo = flow::flujo();
+
+    // 1. Comandos globales que no dependen del paciente (menú, cancelar, ayuda, agente)
+    let comando = Comando::detectar(entrada.texto());
+    if let Some(c) = comando
+        && let Some(transicion) = comandos::atender_sin_paciente(pool, telefono, estado, c).await
+    {
+        flow::aplicar(pool, messenger, telefono, estado, &guardada.contexto, transicion).await;
+        return;
+    }
+
+    // 2. Identificación de IDs
     let user_id = match database::obtener_usuario_por_telefono(pool, telefono).await {
         Some(user) => user.user_id,
         None => {
@@ -33,76 +60,60 @@ pub async fn procesar(pool: &PgPool, telefono: &str, entrada: &str) {
         Some(p) => p,
         None => database::registrar_paciente_completo(pool, telefono, "").await,
     };
-    let patient_id = paciente.patient_id;
 
-    // 2. Comandos Globales
-    if entrada.to_lowercase() == "hola" || entrada.to_lowercase() == "inicio" {
-        enviar_bienvenida(pool, telefono).await;
+    let ctx = Contexto { pool, messenger, storage, telefono, user_id, patient_id: paciente.patient_id, sesion: guardada.contexto.clone() };
+
+    // 3. Sesión vencida: preguntamos si continúa o empieza de nuevo ("atrás" es explícito y no espera)
+    let ahora = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or_default();
+    if !matches!(entrada, UserInput::Reaccion { .. })
+        && comando != Some(Comando::Atras)
+        && let Some(transicion) = sesion::revisar_expiracion(&ctx, estado, &guardada, ahora).await
+    {
+        flujo.aplicar(&ctx, estado, transicion).await;
         return;
     }
 
-    // 3. Máquina de Estados Principal - Delegar según estado
-    match estado {
-
-        UserState::Nuevo => {
-            let saludo = "Hola, no te había visto por aquí. 👀 Mucho gusto, soy el Asistente virtual de *Biotecza*.\n\n\
-                          ¿Cuál es tu nombre? 👇🏼\n\
-                          _Escribe solo tu nombre_";
-            
-            crate::database::cambiar_estado(pool, telefono, &UserState::EsperandoNombre.to_string()).await;
-            whatsapp::enviar_texto(telefono, saludo).await;
+    // 4. Comandos globales que necesitan al paciente
+    match comando {
+        Some(Comando::Saludo) => {
+            flujo.aplicar(&ctx, estado, users::bienvenida(pool, telefono).await).await;
+            return;
         }
-
-UserState::EsperandoNombre => {
-    // Usamos 'entrada' que es el texto que envió el usuario
-    let nombre_recibido = entrada.trim();
-    
-    // Guardamos en la tabla 'users' directamente o en un campo temporal
-    database::users::actualizar_nombre_temporal(pool, user_id, nombre_recibido).await;
-    
-    let pregunta = format!("¡Hola, *{}*! ¿Es correcto tu nombre? 👇🏼", nombre_recibido);
-    let botones = vec!["✅ Sí, es correcto", "❌ No, corregir"];
-    
-    database::cambiar_estado(pool, telefono, &UserState::ConfirmandoNombre.to_string()).await;
-    whatsapp::enviar_botones(telefono, &pregunta, botones).await;
-}
-
-UserState::ConfirmandoNombre => {
-    if entrada.contains("Sí") {
-        // Recuperamos el nombre que guardamos en el paso anterior
-        let nombre = database::users::obtener_nombre_temporal(pool, user_id).await.unwrap_or("Amigo".to_string());
-        
-        // Mensaje de Privacidad independiente
-        let aviso = format!("¡Mucho gusto, *{}*! Conoce aquí nuestro Aviso de Privacidad 👇\n\
-                             https://biotecza.com/privacidad", nombre);
-        whatsapp::enviar_texto(telefono, &aviso).await;
-
-        // Menú Principal
-        let menu = "¿Qué necesitas hoy? Elige la opción que mejor se adapte a tu solicitud 👇😊";
-        let opciones = vec!["🔬 Laboratorio", "💊 Medicamentos"];
-        
-        database::cambiar_estado(pool, telefono, &UserState::Inicio.to_string()).await; // Volvemos a Inicio para que el menú funcione
-        whatsapp::enviar_botones(telefono, menu, opciones).await;
-    } else {
-        let reintento = "No te preocupes, ¿cómo te llamas entonces? 👇🏼";
-        database::cambiar_estado(pool, telefono, &UserState::EsperandoNombre.to_string()).await;
-        whatsapp::enviar_texto(telefono, reintento).await;
+        Some(Comando::Atras) => {
+            flujo.aplicar(&ctx, estado, comandos::atras(&ctx).await).await;
+            return;
+        }
+        _ => {}
     }
-} // Delegar a lab
-        UserState::SeleccionandoExamen => {
-            let _ = procesar_lab(pool, telefono, entrada, estado).await;
-        },
-
-        // Delegar a pharmacy
-        UserState::MenuFarmacia | UserState::EsperandoCategoria | UserState::AgregandoProducto | UserState::EsperandoBusqueda => {
-            let _ = procesar_farmacia(pool, telefono, entrada, estado, &patient_id).await;
-        },
-
-        // Delegar a users
-        UserState::ConfirmandoPedido | UserState::EsperandoPrimerNombre | UserState::EsperandoApellidoPaterno | UserState::EsperandoApellidoMaterno | UserState::EsperandoEmail | UserState::EsperandoCurp | UserState::EsperandoGenero | UserState::EsperandoDireccion => {
-            let _ = procesar_usuario(pool, telefono, entrada, estado, &user_id, &patient_id).await;
-        },
 
-        _ => { enviar_bienvenida(pool, telefono).await; }
+    // 5. Entradas que ningún estado espera se contestan aquí
+    match entrada {
+        UserInput::Media(_) if estado != UserState::EsperandoReceta => {
+            messenger.enviar_texto(
+                telefono,
+                "📎 Recibimos tu archivo, pero por ahora solo podemos procesar archivos cuando te pedimos tu receta médica.",
+            ).await;
+            return;
+        }
+        // Una reacción no es una respuesta; no movemos la conversación
+        UserInput::Reaccion { emoji, .. } => {
+            println!("💬 Reacción de {}: {}", telefono, emoji);
+            return;
+        }
+        UserInput::Ubicacion(_) if estado != UserState::EsperandoDireccion => {
+            messenger.enviar_texto(telefono, "📍 Gracias por tu ubicación. Te la pediremos cuando llegue el momento de la entrega.").await;
+            return;
+        }
+        UserInput::Contactos(_) | UserInput::NoSoportado(_) => {
+            messenger.enviar_texto(telefono, "🙏 Por ahora solo puedo leer mensajes de texto, botones y archivos de tu receta. ¿Me lo puedes escribir?").await;
+            return;
+        }
+        _ => {}
     }
+
+    // 6. Máquina de estados: el manejador del estado actual decide
+    flujo.ejecutar(&ctx, estado, entrada).await;
 }
@@ -1,3 +1,4 @@
+use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 use rust_decimal::Decimal;
 
@@ -38,7 +39,10 @@ pub struct Medication {
     pub presentation: Option<String>,
     pub price: Decimal,
     pub category: Option<String>,
-    pub stock: bool,
+    /// Existencias que no están reservadas en algún carrito
+    pub disponibles: i32,
+    /// Piezas máximas por pedido (controlados); `None` sin límite
+    pub max_por_pedido: Option<i32>,
 }
 
 /// Modelo para estudio/prueba de laboratorio
@@ -73,12 +77,27 @@ pub struct MedicationOrderItem {
 }
 
 /// Modelo para carrito de compras temporal
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default)]
 pub struct Cart {
     pub items: Vec<CartItem>,
     pub total: Decimal,
 }
 
+/// Resultado de confirmar la orden pendiente. Todo se revisa dentro de la transacción que la cierra.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Confirmacion {
+    /// (número de pedido, total, dirección de entrega)
+    Confirmada(i64, Decimal, String),
+    /// El carrito quedó vacío, p. ej. venció mientras el paciente elegía cómo pagar
+    CarritoVacio,
+    /// Medicamentos que ya no tienen sus piezas apartadas
+    SinExistencias(Vec<String>),
+    /// El total guardado no coincidía con los renglones; ya se corrigió con este
+    TotalCambio(Decimal),
+    /// Ya no estaba pendiente o falló la base
+    NoConfirmada,
+}
+
 /// Item en el carrito
 #[derive(Debug, Clone)]
 pub struct CartItem {
@@ -137,5 +156,38 @@ impl Cart {
 pub struct UserSession {
     pub telefono: String,
     pub estado: String,
-    pub ultima_actualizacion: i64, // Unix timestamp
+    pub ultima_actualizacion: i64, // Unix timestamp del último mensaje del paciente
+    pub contexto: SessionContext,
+}
+
+/// Datos de la conversación en curso que se guardan junto con el estado (JSONB).
+/// Todos los campos tienen default para que agregar uno nuevo no rompa sesiones guardadas.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SessionContext {
+    /// Nombre que escribió el paciente y falta confirmar
+    pub nombre_pendiente: Option<String>,
+    /// Última categoría de farmacia elegida
+    pub categoria: Option<String>,
+    /// Página de la lista que se le mostró
+    pub pagina: usize,
+    /// Medicamento al que se le está pidiendo cantidad
+    pub producto: Option<Uuid>,
+    /// Estado en el que se quedó antes de que la sesión venciera
+    pub estado_anterior: Option<String>,
+    /// Estados por los que pasó desde el menú principal, para el comando "atrás"
+    pub historial: Vec<String>,
+}
+
+/// Modelo para mensaje saliente cuya entrega falló
+#[derive(Debug, Clone)]
+pub struct MensajeFallido {
+    pub wamid: String,
+    pub telefono: String,
+    pub tipo: String, // "text", "interactive", ...
+    pub critico: bool,
+    pub requiere_seguimiento: bool,
+    pub error_code: Option<i64>,
+    pub error_detalle: Option<String>,
+    pub fallido_en: i64, // Unix timestamp
 }
@@ -0,0 +1,149 @@
+use rust_decimal::Decimal;
+use sqlx::PgPool;
+use crate::database;
+use crate::whatsapp::client::MessageId;
+use crate::whatsapp::{Messenger, ParametroPlantilla, Plantilla};
+
+const IDIOMA: &str = "es_MX";
+
+/// Aviso que no es respuesta a un mensaje del paciente. Dentro de la ventana de 24 horas
+/// se manda como texto normal; fuera de ella solo se puede con una plantilla aprobada.
+pub async fn notificar(
+    pool: &PgPool,
+    messenger: &dyn Messenger,
+    telefono: &str,
+    texto: &str,
+    plantilla: Plantilla,
+) -> Option<MessageId> {
+    if database::dentro_de_ventana(pool, telefono).await {
+        messenger.enviar_texto(telefono, texto).await
+    } else {
+        println!("📨 {} fuera de la ventana de 24h, se usa la plantilla '{}'", telefono, plantilla.nombre);
+        messenger.enviar_plantilla(telefono, plantilla).await
+    }
+}
+
+pub async fn notificar_pedido_listo(
+    pool: &PgPool,
+    messenger: &dyn Messenger,
+    telefono: &str,
+    nombre: &str,
+    numero_pedido: &str,
+    total: Decimal,
+) -> Option<MessageId> {
+    let texto = format!(
+        "📦 ¡Hola, *{}*! Tu pedido *{}* por *${}* está listo para entrega.",
+        nombre, numero_pedido, total.round_dp(2)
+    );
+    notificar(pool, messenger, telefono, &texto, plantilla_pedido_listo(nombre, numero_pedido, total)).await
+}
+
+fn plantilla_pedido_listo(nombre: &str, numero_pedido: &str, total: Decimal) -> Plantilla {
+    Plantilla::new("pedido_listo", IDIOMA)
+        .cuerpo(ParametroPlantilla::texto(nombre))
+        .cuerpo(ParametroPlantilla::texto(numero_pedido))
+        .cuerpo(ParametroPlantilla::pesos(total))
+}
+
+pub async fn notificar_resultados_lab(
+    pool: &PgPool,
+    messenger: &dyn Messenger,
+    telefono: &str,
+    nombre: &str,
+    estudio: &str,
+    fecha: &str,
+) -> Option<MessageId> {
+    let texto = format!(
+        "🔬 ¡Hola, *{}*! Los resultados de tu estudio *{}* del {} ya están disponibles.",
+        nombre, estudio, fecha
+    );
+    notificar(pool, messenger, telefono, &texto, plantilla_resultados_lab(nombre, estudio, fecha)).await
+}
+
+fn plantilla_resultados_lab(nombre: &str, estudio: &str, fecha: &str) -> Plantilla {
+    Plantilla::new("resultados_laboratorio", IDIOMA)
+        .cuerpo(ParametroPlantilla::texto(nombre))
+        .cuerpo(ParametroPlantilla::texto(estudio))
+        .cuerpo(ParametroPlantilla::fecha(fecha))
+}
+
+/// Avisa de los pedidos que la farmacia marcó como listos y de los resultados de laboratorio
+/// publicados. Cada aviso se reclama en la base antes de mandarlo, así que varias instancias
+/// no lo repiten; si el envío falla se libera para la siguiente vuelta. Devuelve cuántos salieron.
+pub async fn enviar_avisos_pendientes(pool: &PgPool, messenger: &dyn Messenger) -> usize {
+    let mut enviados = 0;
+
+    for (order_id, telefono, nombre, numero, total) in database::reclamar_avisos_pedido_listo(pool).await {
+        let numero = format!("#{}", numero);
+        if notificar_pedido_listo(pool, messenger, &telefono, &nombre, &numero, total).await.is_some() {
+            enviados += 1;
+        } else {
+            eprintln!("❌ No se pudo avisar a {} que su pedido {} está listo", telefono, numero);
+            database::liberar_aviso_pedido_listo(pool, order_id).await;
+        }
+    }
+
+    for (id, telefono, nombre, estudio, fecha) in database::reclamar_avisos_resultados(pool).await {
+        if notificar_resultados_lab(pool, messenger, &telefono, &nombre, &estudio, &fecha).await.is_some() {
+            enviados += 1;
+        } else {
+            eprintln!("❌ No se pudo avisar a {} de sus resultados de {}", telefono, estudio);
+            database::liberar_aviso_resultados(pool, id).await;
+        }
+    }
+
+    enviados
+}
+
+fn plantilla_alerta_inventario(medicamento: &str, existencias: i32, minimo: i32) -> Plantilla {
+    Plantilla::new("alerta_inventario", IDIOMA)
+        .cuerpo(ParametroPlantilla::texto(medicamento))
+        .cuerpo(ParametroPlantilla::texto(existencias))
+        .cuerpo(ParametroPlantilla::texto(minimo))
+}
+
+/// Avisa al equipo de compras de los medicamentos que llegaron a su mínimo. Siempre queda en la
+/// bitácora; con `telefono` (el de compras) también sale por WhatsApp, y si el envío falla la
+/// alerta se libera para la siguiente vuelta. Devuelve cuántas alertas se avisaron.
+pub async fn avisar_alertas_inventario(pool: &PgPool, messenger: &dyn Messenger, telefono: Option<&str>) -> usize {
+    let mut avisadas = 0;
+
+    for (id, medicamento, existencias, minimo) in database::reclamar_alertas_inventario(pool).await {
+        println!("🚨 Pocas existencias de {}: quedan {} (mínimo {})", medicamento, existencias, minimo);
+        if let Some(telefono) = telefono {
+            let texto = format!(
+                "🚨 Quedan *{}* piezas de *{}* (mínimo {}). Hay que resurtir.",
+                existencias, medicamento, minimo
+            );
+            let plantilla = plantilla_alerta_inventario(&medicamento, existencias, minimo);
+            if notificar(pool, messenger, telefono, &texto, plantilla).await.is_none() {
+                eprintln!("❌ No se pudo avisar a compras de las existencias de {}", medicamento);
+                database::liberar_alerta_inventario(pool, id).await;
+                continue;
+            }
+        }
+        avisadas += 1;
+    }
+
+    avisadas
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn plantillas_de_avisos_traen_los_parametros_aprobados() {
+        assert_eq!(plantilla_pedido_listo("Ana", "#12", Decimal::new(15050, 2)).validar(), Ok(()));
+        assert_eq!(plantilla_resultados_lab("Ana", "Biometría hemática", "18/10/2026").validar(), Ok(()));
+        assert_eq!(plantilla_alerta_inventario("Tempra", 3, 5).validar(), Ok(()));
+    }
+
+    #[test]
+    fn plantilla_pedido_listo_manda_el_total_en_milesimas() {
+        let json = plantilla_pedido_listo("Ana", "#12", Decimal::new(15050, 2)).a_json();
+        let total = &json["components"][0]["parameters"][2]["currency"];
+        assert_eq!(total["amount_1000"], 150500);
+        assert_eq!(total["fallback_value"], "$150.50");
+    }
+}
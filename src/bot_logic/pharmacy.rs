@@ -1,124 +1,664 @@
+use async_trait::async_trait;
 use sqlx::PgPool;
 use crate::{database, whatsapp};
+use crate::whatsapp::{Botones, Lista};
+use crate::whatsapp::interactive::rango_pagina;
 use rust_decimal::Decimal;
 use uuid::Uuid;
+use super::flow::{Contexto, Saliente, StateHandler, Transicion};
 use super::states::UserState;
+use super::models::{Cart, CartItem, Confirmacion, Medication, SessionContext};
+use super::input::{Accion, UserInput};
+use super::users::{self, bienvenida};
+use crate::whatsapp::payload::MediaPayload;
 
-pub async fn procesar_farmacia(
-    pool: &PgPool,
-    telefono: &str,
-    entrada: &str,
-    estado: UserState,
-    patient_id: &Uuid,
-) -> bool {
-    match estado {
-        UserState::MenuFarmacia => {
-            match entrada {
-                "Ver Lista" => {
-                    let cats = database::obtener_categorias(pool).await;
-                    database::cambiar_estado(pool, telefono, "ESPERANDO_CATEGORIA").await;
-                    whatsapp::enviar_lista(telefono, "📂 Categorías", "Elige una:", "Ver", cats).await;
-                },
-                "Buscar" => {
-                    database::cambiar_estado(pool, telefono, "ESPERANDO_BUSQUEDA").await;
-                    whatsapp::enviar_texto(telefono, "🔍 Escribe el nombre del medicamento:").await;
-                },
-                "Regresar" => { super::users::enviar_bienvenida(pool, telefono).await; },
-                _ => {}
-            }
-            true
-        },
-
-        UserState::EsperandoCategoria => {
-            // Enviar primero la lista detallada (nombre de patente, compuesto activo, precio)
-            let detalle = formatear_lista_medicamentos(pool, entrada).await;
-            whatsapp::enviar_texto(telefono, &detalle).await;
-
-            // Luego enviar la lista interactiva para poder añadir al carrito por nombre
-            let productos = database::obtener_productos_nombres_y_ids(pool, entrada).await;
-            database::cambiar_estado(pool, telefono, &UserState::AgregandoProducto.to_string()).await;
-            whatsapp::enviar_lista(telefono, &format!("💊 {}", entrada), "Añadir al carrito:", "Añadir", productos).await;
-            true
-        },
-
-UserState::AgregandoProducto => {
-    if entrada == "Finalizar Pedido" {
-        // ... (tu lógica actual de finalizar pedido)
-    } else if entrada == "Ver Lista" {
-        // ... (tu lógica actual de ver lista)
-    } else if entrada == "Agregar más" {
-        // Mostramos las opciones de búsqueda/navegación nuevamente
-        let msg = "🛒 ¿Cómo deseas buscar el siguiente producto?";
-        whatsapp::enviar_botones(telefono, msg, vec!["Buscar", "Ver Lista", "Finalizar Pedido"]).await;
-    } else if entrada == "Cancelar Pedido" {
-        // Lógica opcional para limpiar el carrito o simplemente volver al inicio
-        super::users::enviar_bienvenida(pool, telefono).await;
-    } else {
-        // Lógica para añadir el producto seleccionado
-        if let Some(med) = database::obtener_detalle_med_por_nombre(pool, entrada).await {
-            let order_id = database::obtener_o_crear_orden(pool, *patient_id).await;
-            database::agregar_al_carrito(pool, order_id, med.med_id, med.price).await;
-            
-            // CAMBIO AQUÍ: Enviamos botones que inviten a seguir o terminar
-            let msg = format!("✅ *{}* añadido al carrito.", med.brand_name);
-            whatsapp::enviar_botones(
-                telefono, 
-                &msg, 
-                vec!["Agregar más", "Finalizar Pedido", "Cancelar Pedido"]
-            ).await;
-            
-            // IMPORTANTE: Mantenemos el estado en AgregandoProducto para procesar 
-            // los botones que acabamos de enviar.
+pub fn manejadores() -> Vec<Box<dyn StateHandler>> {
+    vec![
+        Box::new(MenuFarmaciaHandler),
+        Box::new(EsperandoCategoriaHandler),
+        Box::new(AgregandoProductoHandler),
+        Box::new(EsperandoCantidadHandler),
+        Box::new(EsperandoBusquedaHandler),
+        Box::new(RevisandoCarritoHandler),
+        Box::new(ConfirmandoPedidoHandler),
+        Box::new(EsperandoMetodoPagoHandler),
+    ]
+}
+
+struct MenuFarmaciaHandler;
+
+#[async_trait]
+impl StateHandler for MenuFarmaciaHandler {
+    fn estado(&self) -> UserState { UserState::MenuFarmacia }
+    fn transiciones(&self) -> &'static [UserState] {
+        &[UserState::EsperandoCategoria, UserState::EsperandoBusqueda, UserState::Inicio, UserState::EsperandoNombre]
+    }
+
+    fn ayuda(&self) -> &'static str { "Elige si quieres ver la lista de categorías o buscar tu medicamento por nombre." }
+    fn entradas(&self) -> &'static [&'static str] { &["Buscar", "Ver Lista", "Regresar"] }
+
+    async fn reanudar(&self, _ctx: &Contexto<'_>) -> Transicion {
+        Transicion::a(UserState::MenuFarmacia).botones(botones_menu_farmacia("💊 ¿Cómo quieres buscar tu medicamento?"))
+    }
+
+    async fn manejar(&self, ctx: &Contexto<'_>, entrada: &UserInput) -> Transicion {
+        match entrada {
+            UserInput::Accion(Accion::VerLista) => {
+                Transicion::a(UserState::EsperandoCategoria).lista(lista_categorias(ctx.pool, 0).await)
+            }
+            UserInput::Accion(Accion::Buscar) => {
+                Transicion::a(UserState::EsperandoBusqueda).texto("🔍 Escribe el nombre del medicamento:")
+            }
+            UserInput::Accion(Accion::Regresar) => bienvenida(ctx.pool, ctx.telefono).await,
+            _ => Transicion::a(UserState::MenuFarmacia).botones(botones_menu_farmacia("¿Qué deseas hacer?")),
+        }
+    }
+}
+
+struct EsperandoCategoriaHandler;
+
+#[async_trait]
+impl StateHandler for EsperandoCategoriaHandler {
+    fn estado(&self) -> UserState { UserState::EsperandoCategoria }
+    fn transiciones(&self) -> &'static [UserState] { &[UserState::AgregandoProducto, UserState::MenuFarmacia] }
+
+    fn ayuda(&self) -> &'static str { "Elige una categoría de la lista para ver sus medicamentos." }
+    fn entradas(&self) -> &'static [&'static str] { &["categoría (lista)", "➡️ Ver más"] }
+
+    async fn reanudar(&self, ctx: &Contexto<'_>) -> Transicion {
+        Transicion::a(UserState::EsperandoCategoria).lista(lista_categorias(ctx.pool, ctx.sesion.pagina).await)
+    }
+
+    async fn manejar(&self, ctx: &Contexto<'_>, entrada: &UserInput) -> Transicion {
+        let categoria = match entrada {
+            // "Ver más" en la lista de categorías: mandamos la siguiente página
+            UserInput::Accion(Accion::VerMas { pagina, .. }) => {
+                return Transicion::a(UserState::EsperandoCategoria)
+                    .lista(lista_categorias(ctx.pool, *pagina).await)
+                    .contexto(SessionContext { pagina: *pagina, ..ctx.sesion.clone() });
+            }
+            UserInput::Accion(Accion::Categoria(c)) => c.as_str(),
+            _ => return Transicion::a(UserState::MenuFarmacia).botones(botones_menu_farmacia("¿Qué deseas hacer?")),
+        };
+
+        // Enviar primero la lista detallada (nombre de patente, compuesto activo, precio)
+        // y luego la lista interactiva para poder añadir al carrito
+        Transicion::a(UserState::AgregandoProducto)
+            .texto(formatear_lista_medicamentos(ctx.pool, categoria, 0).await)
+            .lista(lista_productos_categoria(ctx.pool, categoria, 0).await)
+            .contexto(SessionContext { categoria: Some(categoria.to_string()), pagina: 0, ..ctx.sesion.clone() })
+    }
+}
+
+struct AgregandoProductoHandler;
+
+#[async_trait]
+impl StateHandler for AgregandoProductoHandler {
+    fn estado(&self) -> UserState { UserState::AgregandoProducto }
+    fn transiciones(&self) -> &'static [UserState] {
+        &[
+            UserState::EsperandoCantidad, UserState::ConfirmandoPedido, UserState::RevisandoCarrito, UserState::EsperandoCategoria,
+            UserState::EsperandoBusqueda, UserState::Inicio, UserState::EsperandoNombre,
+        ]
+    }
+
+    fn ayuda(&self) -> &'static str { "Elige un medicamento de la lista para agregarlo a tu carrito, o usa los botones para ver más, buscar, revisar tu carrito o finalizar tu pedido." }
+    fn entradas(&self) -> &'static [&'static str] {
+        &["medicamento (lista)", "➡️ Ver más", "Agregar más", "Ver carrito", "Buscar", "Ver Lista", "Finalizar Pedido", "Cancelar Pedido"]
+    }
+
+    async fn reanudar(&self, ctx: &Contexto<'_>) -> Transicion {
+        let transicion = Transicion::a(UserState::AgregandoProducto);
+        let transicion = match &ctx.sesion.categoria {
+            Some(categoria) => transicion.lista(lista_productos_categoria(ctx.pool, categoria, ctx.sesion.pagina).await),
+            None => transicion,
+        };
+        transicion.botones(botones_seguir_comprando("🛒 ¿Cómo deseas buscar el siguiente producto?"))
+    }
+
+    async fn manejar(&self, ctx: &Contexto<'_>, entrada: &UserInput) -> Transicion {
+        match entrada {
+            // "Ver más" viejo de la lista de categorías (no trae categoría): la volvemos a mandar
+            UserInput::Accion(Accion::VerMas { pagina, contexto }) if contexto.is_empty() => {
+                repreguntar_categoria(ctx, *pagina).await
+            }
+            // "Ver más" en la lista de productos de una categoría
+            UserInput::Accion(Accion::VerMas { pagina, contexto }) => {
+                let lista = lista_productos_categoria(ctx.pool, contexto, *pagina).await;
+                if lista.filas.is_empty() {
+                    // La categoría ya no tiene esa página (se agotaron productos)
+                    return repreguntar_categoria(ctx, 0).await;
+                }
+                Transicion::a(UserState::AgregandoProducto)
+                    .texto(formatear_lista_medicamentos(ctx.pool, contexto, *pagina).await)
+                    .lista(lista)
+                    .contexto(SessionContext { pagina: *pagina, ..ctx.sesion.clone() })
+            }
+            UserInput::Accion(Accion::VerMasBusqueda { pagina, busqueda }) => {
+                resultados_busqueda(ctx, busqueda, *pagina).await
+            }
+            UserInput::Accion(Accion::FinalizarPedido) => confirmar_pedido(ctx).await,
+            UserInput::Accion(Accion::VerCarrito) => ver_carrito(ctx).await,
+            UserInput::Accion(Accion::VerLista) => {
+                Transicion::a(UserState::EsperandoCategoria).lista(lista_categorias(ctx.pool, 0).await)
+            }
+            UserInput::Accion(Accion::Buscar) => {
+                Transicion::a(UserState::EsperandoBusqueda).texto("🔍 Escribe el nombre del medicamento:")
+            }
+            UserInput::Accion(Accion::AgregarMas) => {
+                // Mostramos las opciones de búsqueda/navegación nuevamente
+                Transicion::a(UserState::AgregandoProducto)
+                    .botones(botones_seguir_comprando("🛒 ¿Cómo deseas buscar el siguiente producto?"))
+            }
+            UserInput::Accion(Accion::CancelarPedido) => cancelar_pedido(ctx).await,
+            // Eligió un producto: antes de agregarlo preguntamos cuántas piezas
+            UserInput::Accion(Accion::AgregarMed(med_id)) => pedir_cantidad(ctx, *med_id).await,
+            _ => Transicion::a(UserState::AgregandoProducto).botones(botones_seguir_comprando("¿Qué deseas hacer?")),
+        }
+    }
+}
+
+struct EsperandoCantidadHandler;
+
+#[async_trait]
+impl StateHandler for EsperandoCantidadHandler {
+    fn estado(&self) -> UserState { UserState::EsperandoCantidad }
+    fn transiciones(&self) -> &'static [UserState] { &[UserState::AgregandoProducto] }
+
+    fn ayuda(&self) -> &'static str { "Elige cuántas piezas quieres con los botones o escribe el número, por ejemplo *4*." }
+    fn entradas(&self) -> &'static [&'static str] { &["1", "2", "3", "cantidad (número)", "medicamento (lista)"] }
+
+    async fn reanudar(&self, ctx: &Contexto<'_>) -> Transicion {
+        match ctx.sesion.producto {
+            Some(med_id) => pedir_cantidad(ctx, med_id).await,
+            None => Transicion::a(UserState::AgregandoProducto)
+                .botones(botones_seguir_comprando("🛒 ¿Cómo deseas buscar el siguiente producto?")),
+        }
+    }
+
+    async fn manejar(&self, ctx: &Contexto<'_>, entrada: &UserInput) -> Transicion {
+        let cantidad = match entrada {
+            // Tocó otro producto de la lista: preguntamos por ese
+            UserInput::Accion(Accion::AgregarMed(med_id)) => return pedir_cantidad(ctx, *med_id).await,
+            UserInput::Accion(Accion::Cantidad(n)) => Some(*n),
+            otro => otro.texto().trim().parse::<i32>().ok(),
+        };
+
+        let Some(med_id) = ctx.sesion.producto else { return self.reanudar(ctx).await };
+        let Some(med) = database::obtener_detalle_med_por_id(ctx.pool, med_id).await else {
+            return producto_no_disponible(ctx);
+        };
+        let Some(cantidad) = cantidad else {
+            return repreguntar_cantidad(ctx, &med, "🔢 Escribe solo el número de piezas, por ejemplo *2*.".to_string());
+        };
+
+        let order_id = database::obtener_o_crear_orden(ctx.pool, ctx.patient_id).await;
+        let en_carrito = database::obtener_carrito(ctx.pool, order_id).await.items.iter()
+            .find(|i| i.med_id == med_id)
+            .map(|i| i.quantity)
+            .unwrap_or(0);
+        if let Err(aviso) = validar_cantidad(&med, en_carrito, cantidad) {
+            return repreguntar_cantidad(ctx, &med, aviso);
+        }
+
+        // Si ya estaba en el carrito se suma a la misma línea
+        let msg = match database::agregar_al_carrito(ctx.pool, order_id, med.med_id, med.price, cantidad).await {
+            0 => {
+                // Otro paciente pudo llevarse las últimas piezas mientras elegía
+                let med = database::obtener_detalle_med_por_id(ctx.pool, med_id).await.unwrap_or(med);
+                let aviso = validar_cantidad(&med, en_carrito, cantidad).err()
+                    .unwrap_or_else(|| "😕 No pudimos agregar ese producto. ¿Puedes intentarlo de nuevo?".to_string());
+                return repreguntar_cantidad(ctx, &med, aviso);
+            }
+            1 => format!("✅ *{}* añadido al carrito.", med.brand_name),
+            total if total == cantidad => format!("✅ Agregamos {} piezas de *{}* a tu carrito.", cantidad, med.brand_name),
+            total => format!("✅ Agregamos {} más de *{}*. Llevas {} en tu carrito.", cantidad, med.brand_name, total),
+        };
+
+        // Botones que inviten a seguir o terminar; en AgregandoProducto se atienden
+        let botones = Botones::new(&msg)
+            .boton(Accion::AgregarMas, "Agregar más")
+            .boton(Accion::VerCarrito, "Ver carrito")
+            .boton(Accion::FinalizarPedido, "Finalizar Pedido");
+        Transicion::a(UserState::AgregandoProducto)
+            .botones(botones)
+            .contexto(SessionContext { producto: None, ..ctx.sesion.clone() })
+    }
+}
+
+/// Piezas que se aceptan escritas a mano; pedidos más grandes los atiende una persona
+const MAX_CANTIDAD: i32 = 99;
+
+/// Pregunta cuántas piezas del medicamento quiere y lo recuerda en el contexto
+async fn pedir_cantidad(ctx: &Contexto<'_>, med_id: Uuid) -> Transicion {
+    match database::obtener_detalle_med_por_id(ctx.pool, med_id).await {
+        Some(med) if med.disponibles > 0 => Transicion::a(UserState::EsperandoCantidad)
+            .botones(botones_cantidad(&med))
+            .contexto(SessionContext { producto: Some(med_id), ..ctx.sesion.clone() }),
+        _ => producto_no_disponible(ctx),
+    }
+}
+
+fn producto_no_disponible(ctx: &Contexto<'_>) -> Transicion {
+    Transicion::a(UserState::AgregandoProducto)
+        .texto("😕 Ese producto ya no está disponible.")
+        .botones(botones_seguir_comprando("🛒 ¿Cómo deseas buscar el siguiente producto?"))
+        .contexto(SessionContext { producto: None, ..ctx.sesion.clone() })
+}
+
+/// Vuelve a preguntar la cantidad con un aviso; si ya no quedan piezas, a buscar otro producto
+fn repreguntar_cantidad(ctx: &Contexto<'_>, med: &Medication, aviso: String) -> Transicion {
+    if med.disponibles < 1 {
+        return producto_no_disponible(ctx);
+    }
+    Transicion::a(UserState::EsperandoCantidad).texto(aviso).botones(botones_cantidad(med))
+}
+
+/// Botones 1, 2 y 3 (sin pasar del máximo por pedido ni de las disponibles); cualquier otra cantidad se escribe
+fn botones_cantidad(med: &Medication) -> Botones {
+    let mut texto = format!("🔢 ¿Cuántas piezas de *{}* quieres? (${} c/u)", med.brand_name, med.price);
+    if let Some(maximo) = med.max_por_pedido {
+        texto.push_str(&format!("\n⚠️ Máximo {} por pedido.", maximo));
+    }
+    if med.disponibles < 3 {
+        texto.push_str(&format!("\n⚠️ {}.", quedan(med)));
+    }
+    texto.push_str("\n\nElige una opción o escribe la cantidad.");
+
+    (1..=3)
+        .filter(|n| *n <= med.disponibles && med.max_por_pedido.is_none_or(|maximo| *n <= maximo))
+        .fold(Botones::new(&texto), |b, n| b.boton(Accion::Cantidad(n), &n.to_string()))
+}
+
+/// Revisa que se puedan agregar `cantidad` piezas a las que ya están en el carrito
+fn validar_cantidad(med: &Medication, en_carrito: i32, cantidad: i32) -> Result<(), String> {
+    if cantidad < 1 {
+        return Err("🔢 La cantidad debe ser de al menos *1* pieza.".to_string());
+    }
+    if cantidad > MAX_CANTIDAD {
+        return Err(format!("🔢 Para más de {} piezas escribe *agente* y te atendemos personalmente.", MAX_CANTIDAD));
+    }
+    if med.disponibles < 1 {
+        return Err(format!("😕 *{}* ya no está disponible.", med.brand_name));
+    }
+    if cantidad > med.disponibles {
+        return Err(format!("😕 {} de *{}*.", quedan(med), med.brand_name));
+    }
+    if let Some(maximo) = med.max_por_pedido
+        && en_carrito + cantidad > maximo
+    {
+        let llevas = if en_carrito > 0 { format!(" y ya llevas {} en tu carrito", en_carrito) } else { String::new() };
+        return Err(format!("⚠️ *{}* tiene un máximo de {} piezas por pedido{}.", med.brand_name, maximo, llevas));
+    }
+    Ok(())
+}
+
+/// "Solo quedan N piezas" / "Solo queda 1 pieza"
+fn quedan(med: &Medication) -> String {
+    match med.disponibles {
+        1 => "Solo queda 1 pieza".to_string(),
+        n => format!("Solo quedan {} piezas", n),
+    }
+}
+
+struct EsperandoBusquedaHandler;
+
+#[async_trait]
+impl StateHandler for EsperandoBusquedaHandler {
+    fn estado(&self) -> UserState { UserState::EsperandoBusqueda }
+    fn transiciones(&self) -> &'static [UserState] { &[UserState::AgregandoProducto, UserState::MenuFarmacia] }
+
+    fn ayuda(&self) -> &'static str { "Escribe el nombre del medicamento o su sustancia activa, por ejemplo *paracetamol*." }
+    fn entradas(&self) -> &'static [&'static str] { &["nombre o sustancia (texto)"] }
+
+    async fn reanudar(&self, _ctx: &Contexto<'_>) -> Transicion {
+        Transicion::a(UserState::EsperandoBusqueda).texto("🔍 Escribe el nombre del medicamento:")
+    }
+
+    async fn manejar(&self, ctx: &Contexto<'_>, entrada: &UserInput) -> Transicion {
+        // Interpretar la entrada como término de búsqueda (nombre o compuesto)
+        // Buscamos coincidencias en la DB y enviamos una lista de resultados similares
+        let term = entrada.texto().trim();
+        if term.is_empty() {
+            return Transicion::a(UserState::MenuFarmacia).botones(botones_menu_farmacia("¿Qué deseas hacer?"));
         }
+
+        // El término viaja en el id de "Ver más", que Meta limita a 200 caracteres
+        let term: String = term.chars().take(100).collect();
+        resultados_busqueda(ctx, &term, 0).await
+    }
+}
+
+/// Resultados de una búsqueda: el detalle en texto y la lista para añadir, los dos con la misma página
+async fn resultados_busqueda(ctx: &Contexto<'_>, term: &str, pagina: usize) -> Transicion {
+    let resultados = database::buscar_medicamentos_similares(ctx.pool, term).await;
+    let (rango, _) = rango_pagina(resultados.len(), pagina);
+
+    if rango.is_empty() {
+        // "Ver más" de una búsqueda vieja cuyos resultados ya cambiaron
+        if pagina > 0 {
+            return Transicion::a(UserState::EsperandoBusqueda)
+                .texto(format!("Ya no hay más resultados para '{}'.\n\n🔍 Escribe el nombre del medicamento:", term));
+        }
+        let msg = format!("No encontré medicamentos relacionados con '{}'. Intenta con otra palabra clave.", term);
+        return Transicion::a(UserState::MenuFarmacia)
+            .texto(msg)
+            .botones(botones_menu_farmacia("¿Qué deseas hacer?"));
+    }
+
+    // Formatear resultados en texto legible
+    let mut texto = format!("🔎 Resultados para '{}':\n", term);
+    texto.push_str("━━━━━━━━━━━━━━━\n\n");
+    for (_, brand, compound, presentation, price) in &resultados[rango] {
+        let pres = presentation.clone().unwrap_or_else(|| "N/A".to_string());
+        texto.push_str(&format!("• *{}*\n  Compuesto: {}\n  Presentación: {}\n  💰 ${}\n\n", brand, compound, pres, price));
+    }
+    texto.push_str("⚠️ Si quieres agregar un producto, tocá su nombre en la lista siguiente.");
+
+    let lista = resultados.iter()
+        .fold(Lista::new("🔎 Selecciona uno:", "Añadir al carrito:", "Añadir"), |l, (med_id, brand, ..)| {
+            l.fila(Accion::AgregarMed(*med_id), brand)
+        })
+        .paginar(pagina, |siguiente| Accion::VerMasBusqueda { pagina: siguiente, busqueda: term.to_string() }.to_string());
+
+    // Texto con detalles y luego la lista interactiva (por med_id)
+    Transicion::a(UserState::AgregandoProducto).texto(texto).lista(lista)
+}
+
+struct RevisandoCarritoHandler;
+
+#[async_trait]
+impl StateHandler for RevisandoCarritoHandler {
+    fn estado(&self) -> UserState { UserState::RevisandoCarrito }
+    fn transiciones(&self) -> &'static [UserState] {
+        &[UserState::AgregandoProducto, UserState::ConfirmandoPedido, UserState::Inicio, UserState::EsperandoNombre]
+    }
+
+    fn ayuda(&self) -> &'static str { "Usa ➕ y ➖ para cambiar la cantidad de cada producto o 🗑️ para quitarlo. Cuando esté listo, toca *Finalizar Pedido*." }
+    fn entradas(&self) -> &'static [&'static str] { &["➕ 1", "➖ 1", "🗑️ Quitar", "Agregar más", "Finalizar Pedido", "Cancelar Pedido"] }
+
+    async fn reanudar(&self, ctx: &Contexto<'_>) -> Transicion {
+        ver_carrito(ctx).await
     }
-    true
-},
-        UserState::EsperandoBusqueda => {
-            // Interpretar la entrada como término de búsqueda (nombre o compuesto)
-            // Buscamos coincidencias en la DB y enviamos una lista de resultados similares
-            let term = entrada.trim();
-            if term.is_empty() {
-                database::cambiar_estado(pool, telefono, &UserState::MenuFarmacia.to_string()).await;
-                whatsapp::enviar_botones(telefono, "¿Qué deseas hacer?", vec!["Buscar", "Ver Lista", "Regresar"]).await;
-                return true;
+
+    async fn manejar(&self, ctx: &Contexto<'_>, entrada: &UserInput) -> Transicion {
+        let Some(order_id) = database::obtener_orden_pendiente(ctx.pool, ctx.patient_id).await else {
+            return ver_carrito(ctx).await;
+        };
+        let carrito = database::obtener_carrito(ctx.pool, order_id).await;
+        let nombre = |med_id: &Uuid| carrito.items.iter().find(|i| i.med_id == *med_id).map(|i| i.brand_name.clone());
+
+        match entrada {
+            UserInput::Accion(Accion::SumarPieza(med_id) | Accion::RestarPieza(med_id)) => {
+                // Una pieza más también respeta la disponibilidad y el máximo por pedido
+                if let UserInput::Accion(Accion::SumarPieza(_)) = entrada
+                    && let Some(med) = database::obtener_detalle_med_por_id(ctx.pool, *med_id).await
+                    && let Some(item) = carrito.items.iter().find(|i| i.med_id == *med_id)
+                    && let Err(aviso) = validar_cantidad(&med, item.quantity, 1)
+                {
+                    return tras_cambio(ctx, order_id, *med_id, Some(aviso)).await;
+                }
+                let delta = if matches!(entrada, UserInput::Accion(Accion::SumarPieza(_))) { 1 } else { -1 };
+                match (nombre(med_id), database::cambiar_cantidad_carrito(ctx.pool, order_id, *med_id, delta).await) {
+                    (Some(nombre), Some(0)) => tras_cambio(ctx, order_id, *med_id, Some(format!("🗑️ Quitamos *{}* de tu carrito.", nombre))).await,
+                    (_, Some(_)) => tras_cambio(ctx, order_id, *med_id, None).await,
+                    _ => ver_carrito(ctx).await,
+                }
+            }
+            UserInput::Accion(Accion::QuitarProducto(med_id)) => {
+                let Some(nombre) = nombre(med_id) else { return ver_carrito(ctx).await };
+                database::quitar_del_carrito(ctx.pool, order_id, *med_id).await;
+                tras_cambio(ctx, order_id, *med_id, Some(format!("🗑️ Quitamos *{}* de tu carrito.", nombre))).await
             }
+            UserInput::Accion(Accion::AgregarMas) => {
+                Transicion::a(UserState::AgregandoProducto)
+                    .botones(botones_seguir_comprando("🛒 ¿Cómo deseas buscar el siguiente producto?"))
+            }
+            UserInput::Accion(Accion::FinalizarPedido) => confirmar_pedido(ctx).await,
+            UserInput::Accion(Accion::CancelarPedido) => cancelar_pedido(ctx).await,
+            _ => ver_carrito(ctx).await,
+        }
+    }
+}
+
+/// "Ver carrito": un mensaje por producto con sus botones ➕ ➖ 🗑️ y al final el total
+async fn ver_carrito(ctx: &Contexto<'_>) -> Transicion {
+    let carrito = match database::obtener_orden_pendiente(ctx.pool, ctx.patient_id).await {
+        Some(order_id) => database::obtener_carrito(ctx.pool, order_id).await,
+        None => Cart::default(),
+    };
+    if carrito.items.is_empty() {
+        return Transicion::a(UserState::AgregandoProducto)
+            .botones(botones_seguir_comprando("🛒 Tu carrito está vacío. ¿Cómo quieres buscar tu medicamento?"));
+    }
+
+    let transicion = Transicion::a(UserState::RevisandoCarrito)
+        .texto(format!("🛒 *TU CARRITO* ({} producto(s))", carrito.item_count()));
+    carrito.items.iter()
+        .fold(transicion, |t, item| t.botones(botones_renglon(item)))
+        .botones(botones_total(&carrito))
+}
+
+/// Antepone un aviso a la respuesta
+fn con_aviso(mut transicion: Transicion, aviso: String) -> Transicion {
+    transicion.mensajes.insert(0, Saliente::Texto(aviso));
+    transicion
+}
+
+/// Respuesta después de cambiar un renglón: el renglón actualizado (si sigue) y el nuevo total
+async fn tras_cambio(ctx: &Contexto<'_>, order_id: Uuid, med_id: Uuid, aviso: Option<String>) -> Transicion {
+    let carrito = database::obtener_carrito(ctx.pool, order_id).await;
+    if carrito.items.is_empty() {
+        let mut transicion = Transicion::a(UserState::AgregandoProducto);
+        if let Some(aviso) = aviso {
+            transicion = transicion.texto(aviso);
+        }
+        return transicion.botones(botones_seguir_comprando("🛒 Tu carrito quedó vacío. ¿Cómo quieres buscar tu medicamento?"));
+    }
 
-            let resultados = database::buscar_medicamentos_similares(pool, term).await;
+    let mut transicion = Transicion::a(UserState::RevisandoCarrito);
+    if let Some(aviso) = aviso {
+        transicion = transicion.texto(aviso);
+    }
+    if let Some(item) = carrito.items.iter().find(|i| i.med_id == med_id) {
+        transicion = transicion.botones(botones_renglon(item));
+    }
+    transicion.botones(botones_total(&carrito))
+}
+
+/// Un producto del carrito con sus botones para cambiar la cantidad
+fn botones_renglon(item: &CartItem) -> Botones {
+    let texto = format!("• *{}*\n  {} x ${} = ${}", item.brand_name, item.quantity, item.unit_price, item.subtotal);
+    Botones::new(&texto)
+        .boton(Accion::SumarPieza(item.med_id), "➕ 1")
+        .boton(Accion::RestarPieza(item.med_id), "➖ 1")
+        .boton(Accion::QuitarProducto(item.med_id), "🗑️ Quitar")
+}
+
+fn botones_total(carrito: &Cart) -> Botones {
+    Botones::new(&format!("💰 *Total: ${}*", carrito.total))
+        .boton(Accion::AgregarMas, "Agregar más")
+        .boton(Accion::FinalizarPedido, "Finalizar Pedido")
+        .boton(Accion::CancelarPedido, "Cancelar Pedido")
+}
+
+struct ConfirmandoPedidoHandler;
+
+#[async_trait]
+impl StateHandler for ConfirmandoPedidoHandler {
+    fn estado(&self) -> UserState { UserState::ConfirmandoPedido }
+    fn transiciones(&self) -> &'static [UserState] {
+        &[
+            UserState::EsperandoPrimerNombre, UserState::ConfirmandoDireccion, UserState::EsperandoDireccion,
+            UserState::RevisandoCarrito, UserState::AgregandoProducto, UserState::Inicio, UserState::EsperandoNombre,
+        ]
+    }
 
-            if resultados.is_empty() {
-                let msg = format!("No encontré medicamentos relacionados con '{}'. Intenta con otra palabra clave.", term);
-                whatsapp::enviar_texto(telefono, &msg).await;
-                database::cambiar_estado(pool, telefono, &UserState::MenuFarmacia.to_string()).await;
-                whatsapp::enviar_botones(telefono, "¿Qué deseas hacer?", vec!["Buscar", "Ver Lista", "Regresar"]).await;
-                return true;
+    fn ayuda(&self) -> &'static str { "Revisa tu pedido: confírmalo, edítalo para cambiar cantidades o cancélalo con los botones." }
+    fn entradas(&self) -> &'static [&'static str] { &["Confirmar Pedido", "Editar Pedido", "Cancelar Pedido"] }
+
+    async fn reanudar(&self, ctx: &Contexto<'_>) -> Transicion {
+        confirmar_pedido(ctx).await
+    }
+
+    async fn manejar(&self, ctx: &Contexto<'_>, entrada: &UserInput) -> Transicion {
+        match entrada {
+            // Si ya tenemos sus datos completos de otra compra, pasamos directo a la entrega
+            UserInput::Accion(Accion::ConfirmarPedido) if users::datos_completos(ctx).await => users::paso_direccion(ctx).await,
+            UserInput::Accion(Accion::ConfirmarPedido) => {
+                Transicion::a(UserState::EsperandoPrimerNombre).texto("¡Excelente! ¿Cuál es tu *nombre*?")
             }
+            UserInput::Accion(Accion::EditarPedido) => ver_carrito(ctx).await,
+            UserInput::Accion(Accion::CancelarPedido) => cancelar_pedido(ctx).await,
+            _ => confirmar_pedido(ctx).await,
+        }
+    }
+}
 
-            // Formatear resultados en texto legible
-            let mut texto = format!("🔎 Resultados para '{}':\n", term);
-            texto.push_str("━━━━━━━━━━━━━━━\n\n");
-            let mut nombres: Vec<String> = Vec::new();
+struct EsperandoMetodoPagoHandler;
+
+#[async_trait]
+impl StateHandler for EsperandoMetodoPagoHandler {
+    fn estado(&self) -> UserState { UserState::EsperandoMetodoPago }
+    fn transiciones(&self) -> &'static [UserState] {
+        &[UserState::Inicio, UserState::EsperandoNombre, UserState::RevisandoCarrito, UserState::AgregandoProducto]
+    }
+
+    fn ayuda(&self) -> &'static str { "Elige cómo vas a pagar tu pedido: efectivo, tarjeta o transferencia." }
+    fn entradas(&self) -> &'static [&'static str] { &["💵 Efectivo", "💳 Tarjeta", "🏦 Transferencia"] }
+
+    async fn reanudar(&self, _ctx: &Contexto<'_>) -> Transicion {
+        Transicion::a(UserState::EsperandoMetodoPago).botones(botones_metodo_pago("💰 ¿Cómo vas a pagar tu pedido?"))
+    }
 
-            for (brand, compound, presentation, price) in &resultados {
-                let pres = presentation.clone().unwrap_or_else(|| "N/A".to_string());
-                texto.push_str(&format!("• *{}*\n  Compuesto: {}\n  Presentación: {}\n  💰 ${}\n\n", brand, compound, pres, price));
-                nombres.push(brand.clone());
+    async fn manejar(&self, ctx: &Contexto<'_>, entrada: &UserInput) -> Transicion {
+        let metodo = match entrada {
+            UserInput::Accion(Accion::MetodoPago(m)) if METODOS_PAGO.iter().any(|(id, _)| id == m) => m.as_str(),
+            _ => return Transicion::a(UserState::EsperandoMetodoPago)
+                .botones(botones_metodo_pago("Elige tu método de pago con los botones 👇")),
+        };
+
+        let Some(order_id) = database::obtener_orden_pendiente(ctx.pool, ctx.patient_id).await else {
+            return bienvenida(ctx.pool, ctx.telefono).await;
+        };
+        let (numero, total, direccion) = match database::confirmar_orden(ctx.pool, order_id, metodo).await {
+            Confirmacion::Confirmada(numero, total, direccion) => (numero, total, direccion),
+            Confirmacion::CarritoVacio => {
+                return con_aviso(ver_carrito(ctx).await, "⏰ Tu carrito se vació porque pasó mucho tiempo sin confirmar el pedido.".to_string());
+            }
+            Confirmacion::SinExistencias(nombres) => {
+                let aviso = format!(
+                    "😕 Ya no tenemos apartadas las piezas de *{}*. Ajusta tu carrito y vuelve a finalizar tu pedido.",
+                    nombres.join("*, *")
+                );
+                return con_aviso(ver_carrito(ctx).await, aviso);
+            }
+            Confirmacion::TotalCambio(total) => {
+                let aviso = format!("🔄 El total de tu pedido cambió a *${}*. Revísalo y vuelve a finalizar tu pedido.", total.round_dp(2));
+                return con_aviso(ver_carrito(ctx).await, aviso);
             }
+            Confirmacion::NoConfirmada => {
+                return Transicion::a(UserState::EsperandoMetodoPago)
+                    .texto("😕 No pudimos confirmar tu pedido. ¿Puedes intentarlo de nuevo?")
+                    .botones(botones_metodo_pago("💰 ¿Cómo vas a pagar tu pedido?"));
+            }
+        };
 
-            texto.push_str("⚠️ Si quieres agregar un producto, tocá su nombre en la lista siguiente.");
+        println!("🧾 Pedido #{} confirmado para {} ({}, ${})", numero, ctx.telefono, metodo, total);
+        let etiqueta = METODOS_PAGO.iter().find(|(id, _)| *id == metodo).map(|(_, e)| *e).unwrap_or(metodo);
+        let confirmacion = format!(
+            "✅ *¡Pedido #{} confirmado!*\n\n💰 Total: ${}\n💳 Pago: {}\n📍 Entrega: {}\n\n\
+             Nuestro equipo revisará tu receta y te avisaremos cuando tu pedido vaya en camino. ¡Gracias por comprar en *Biotecza*! 💊",
+            numero, total, etiqueta, direccion
+        );
+        Transicion::a(UserState::Inicio)
+            .texto_critico(confirmacion)
+            .contexto(SessionContext::default())
+    }
+}
 
-            // Enviar texto con detalles y luego la lista interactiva (por brand_name)
-            whatsapp::enviar_texto(telefono, &texto).await;
-            database::cambiar_estado(pool, telefono, &UserState::AgregandoProducto.to_string()).await;
-            whatsapp::enviar_lista(telefono, &format!("🔎 Selecciona uno:"), "Añadir al carrito:", "Añadir", nombres).await;
-            true
-        },
+/// Ticket del carrito con los botones para confirmar, editar o cancelar
+async fn confirmar_pedido(ctx: &Contexto<'_>) -> Transicion {
+    let order_id = database::obtener_o_crear_orden(ctx.pool, ctx.patient_id).await;
+    let ticket = generar_ticket_virtual(ctx.pool, order_id).await;
+    if database::obtener_resumen_carrito(ctx.pool, order_id).await.is_empty() {
+        return Transicion::a(UserState::AgregandoProducto).texto(ticket).botones(botones_seguir_comprando("¿Qué deseas hacer?"));
+    }
+    let botones = Botones::new("Elige una opción 👇")
+        .boton(Accion::ConfirmarPedido, "Confirmar Pedido")
+        .boton(Accion::EditarPedido, "Editar Pedido")
+        .boton(Accion::CancelarPedido, "Cancelar Pedido");
+    Transicion::a(UserState::ConfirmandoPedido).texto(ticket).botones(botones)
+}
 
-        _ => false,
+/// Vacía el carrito y regresa al menú principal
+async fn cancelar_pedido(ctx: &Contexto<'_>) -> Transicion {
+    if let Some(order_id) = database::obtener_orden_pendiente(ctx.pool, ctx.patient_id).await {
+        database::vaciar_carrito(ctx.pool, order_id).await;
     }
+    let mut transicion = bienvenida(ctx.pool, ctx.telefono).await;
+    transicion.mensajes.insert(0, Saliente::Texto("🗑️ Cancelamos tu pedido y vaciamos tu carrito.".to_string()));
+    transicion
+}
+
+/// Métodos de pago: id que viaja en el botón y título que ve el paciente
+const METODOS_PAGO: &[(&str, &str)] = &[
+    ("efectivo", "💵 Efectivo"),
+    ("tarjeta", "💳 Tarjeta"),
+    ("transferencia", "🏦 Transferencia"),
+];
+
+/// Botones para elegir el método de pago
+pub fn botones_metodo_pago(texto: &str) -> Botones {
+    METODOS_PAGO.iter()
+        .fold(Botones::new(texto), |b, (id, titulo)| b.boton(Accion::MetodoPago(id.to_string()), titulo))
+}
+
+/// Botones del menú de farmacia
+pub fn botones_menu_farmacia(texto: &str) -> Botones {
+    Botones::new(texto)
+        .boton(Accion::Buscar, "Buscar")
+        .boton(Accion::VerLista, "Ver Lista")
+        .boton(Accion::Regresar, "Regresar")
+}
+
+/// Botones para seguir agregando productos o cerrar el pedido
+fn botones_seguir_comprando(texto: &str) -> Botones {
+    Botones::new(texto)
+        .boton(Accion::Buscar, "Buscar")
+        .boton(Accion::VerLista, "Ver Lista")
+        .boton(Accion::FinalizarPedido, "Finalizar Pedido")
+}
+
+/// Lista de categorías, paginada de 9 en 9 con una fila "Ver más"
+async fn lista_categorias(pool: &PgPool, pagina: usize) -> Lista {
+    let cats = database::obtener_categorias(pool).await;
+    cats.iter()
+        .fold(Lista::new("📂 Categorías", "Elige una:", "Ver"), |l, c| l.fila(Accion::Categoria(c.clone()), c))
+        .paginar(pagina, |siguiente| Accion::VerMas { pagina: siguiente, contexto: String::new() }.to_string())
+}
+
+/// Vuelve a mandar la lista de categorías en la `pagina` pedida, o en la primera si esa ya no existe
+async fn repreguntar_categoria(ctx: &Contexto<'_>, pagina: usize) -> Transicion {
+    let lista = lista_categorias(ctx.pool, pagina).await;
+    let (lista, pagina) = if lista.filas.is_empty() { (lista_categorias(ctx.pool, 0).await, 0) } else { (lista, pagina) };
+    Transicion::a(UserState::EsperandoCategoria)
+        .lista(lista)
+        .contexto(SessionContext { pagina, ..ctx.sesion.clone() })
+}
+
+/// Productos de una categoría para añadir al carrito, paginados con "Ver más"
+async fn lista_productos_categoria(pool: &PgPool, categoria: &str, pagina: usize) -> Lista {
+    let productos = database::obtener_productos_nombres_y_ids(pool, categoria).await;
+    productos.iter()
+        .fold(Lista::new(&format!("💊 {}", categoria), "Añadir al carrito:", "Añadir"), |l, (med_id, nombre)| {
+            l.fila(Accion::AgregarMed(*med_id), nombre)
+        })
+        .paginar(pagina, |siguiente| Accion::VerMas { pagina: siguiente, contexto: categoria.to_string() }.to_string())
 }
 
-#[allow(dead_code)]
-pub async fn formatear_lista_medicamentos(pool: &sqlx::PgPool, categoria: &str) -> String {
+/// Detalle en texto de una página de la categoría; es la misma página que `lista_productos_categoria`
+pub async fn formatear_lista_medicamentos(pool: &sqlx::PgPool, categoria: &str, pagina: usize) -> String {
     let items: Vec<(String, String, Option<String>, Decimal)> = 
         database::buscar_productos_categoria(pool, categoria).await;
 
@@ -126,10 +666,15 @@ pub async fn formatear_lista_medicamentos(pool: &sqlx::PgPool, categoria: &str)
         return format!("Por el momento no tenemos stock disponible en la categoría *{}*.", categoria);
     }
 
-    let mut res = format!("💊 *Productos en {}:*\n", categoria);
+    let titulo = match pagina {
+        0 => categoria.to_string(),
+        p => format!("{} (página {})", categoria, p + 1),
+    };
+    let mut res = format!("💊 *Productos en {}:*\n", titulo);
     res.push_str("━━━━━━━━━━━━━━━\n\n");
 
-    for i in items {
+    let (rango, _) = rango_pagina(items.len(), pagina);
+    for i in items.into_iter().skip(rango.start).take(rango.len()) {
         let dosis = i.2.unwrap_or_else(|| "N/A".to_string());
         
         res.push_str(&format!(
@@ -146,26 +691,54 @@ pub async fn formatear_lista_medicamentos(pool: &sqlx::PgPool, categoria: &str)
 }
 
 pub async fn generar_ticket_virtual(pool: &PgPool, order_id: Uuid) -> String {
-    let items = database::obtener_resumen_carrito(pool, order_id).await;
+    let carrito = database::obtener_carrito(pool, order_id).await;
     
-    if items.is_empty() {
+    if carrito.items.is_empty() {
         return "Tu carrito está vacío. 🛒".to_string();
     }
 
     let mut ticket = "📝 *RESUMEN DE TU PEDIDO*\n".to_string();
     ticket.push_str("━━━━━━━━━━━━━━━\n\n");
 
-    let mut total: Decimal = Decimal::from(0);
-
-    for (nombre, cant, precio) in items {
-        let subtotal = precio * Decimal::from(cant);
-        total += subtotal;
-        ticket.push_str(&format!("• {} (x{})\n  Subtotal: ${}\n\n", nombre, cant, subtotal));
+    for item in &carrito.items {
+        ticket.push_str(&format!("• {} (x{})\n  Subtotal: ${}\n\n", item.brand_name, item.quantity, item.subtotal));
     }
 
     ticket.push_str("━━━━━━━━━━━━━━━\n");
-    ticket.push_str(&format!("💰 *TOTAL A PAGAR: ${}*\n\n", total));
+    ticket.push_str(&format!("💰 *TOTAL A PAGAR: ${}*\n\n", carrito.total));
     ticket.push_str("¿Deseas confirmar este pedido?");
     
     ticket
 }
+
+/// Descarga la receta que mandó el paciente, la guarda en el almacenamiento
+/// configurado y la liga a su orden pendiente.
+pub async fn recibir_receta(ctx: &Contexto<'_>, media: &MediaPayload) -> Transicion {
+    let archivo = match ctx.messenger.descargar_media(&media.id).await {
+        Ok(a) => a,
+        Err(e) => {
+            eprintln!("❌ No se pudo descargar la receta {}: {}", media.id, e);
+            return Transicion::a(UserState::EsperandoReceta)
+                .texto("😕 No pudimos descargar tu archivo. ¿Puedes enviarlo de nuevo?");
+        }
+    };
+
+    let ruta = format!(
+        "recetas/{}/{}.{}",
+        ctx.patient_id, media.id, whatsapp::media::extension_para(&archivo.mime_type)
+    );
+
+    match ctx.storage.guardar(&ruta, &archivo.contenido, &archivo.mime_type).await {
+        Ok(ubicacion) => {
+            database::guardar_receta_orden(ctx.pool, ctx.patient_id, &ubicacion).await;
+            Transicion::a(UserState::EsperandoMetodoPago)
+                .texto("✅ ¡Recibimos tu receta! Nuestro equipo la revisará antes de enviar tu pedido. 💊")
+                .botones(botones_metodo_pago("💰 Por último, ¿cómo vas a pagar tu pedido?"))
+        }
+        Err(e) => {
+            eprintln!("❌ No se pudo guardar la receta {}: {}", media.id, e);
+            Transicion::a(UserState::EsperandoReceta)
+                .texto("😕 Tuvimos un problema guardando tu receta. ¿Puedes enviarla de nuevo?")
+        }
+    }
+}
@@ -0,0 +1,178 @@
+use async_trait::async_trait;
+use sqlx::PgPool;
+use std::str::FromStr;
+use std::sync::OnceLock;
+use crate::database;
+use crate::whatsapp::Botones;
+use super::flow::{self, Contexto, StateHandler, Transicion};
+use super::input::{Accion, UserInput};
+use super::models::{SessionContext, UserSession};
+use super::states::{alias, UserState};
+use super::users::bienvenida;
+
+/// Grupos de estados que comparten tiempo de inactividad
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GrupoEstado {
+    /// Menú principal: no hay nada a medias
+    Menu,
+    Registro,
+    Farmacia,
+    Laboratorio,
+}
+
+pub fn grupo(estado: UserState) -> GrupoEstado {
+    match estado {
+        UserState::Inicio | UserState::ReanudandoSesion => GrupoEstado::Menu,
+        UserState::SeleccionandoExamen => GrupoEstado::Laboratorio,
+        UserState::MenuFarmacia
+        | UserState::EsperandoCategoria
+        | UserState::AgregandoProducto
+        | UserState::EsperandoCantidad
+        | UserState::EsperandoBusqueda
+        | UserState::RevisandoCarrito
+        | UserState::ConfirmandoPedido
+        | UserState::EsperandoMetodoPago => GrupoEstado::Farmacia,
+        UserState::Nuevo
+        | UserState::EsperandoNombre
+        | UserState::ConfirmandoNombre
+        | UserState::EsperandoPrimerNombre
+        | UserState::EsperandoApellidoPaterno
+        | UserState::EsperandoApellidoMaterno
+        | UserState::EsperandoEmail
+        | UserState::EsperandoCurp
+        | UserState::EsperandoGenero
+        | UserState::ConfirmandoDireccion
+        | UserState::EsperandoDireccion
+        | UserState::EsperandoReceta => GrupoEstado::Registro,
+    }
+}
+
+/// Minutos de inactividad tras los cuales una sesión a medias vence. 0 desactiva el grupo.
+#[derive(Debug, Clone)]
+pub struct ConfigExpiracion {
+    pub registro_minutos: i64,
+    pub farmacia_minutos: i64,
+    pub laboratorio_minutos: i64,
+}
+
+impl ConfigExpiracion {
+    pub fn desde_env() -> Self {
+        let minutos = |var: &str, defecto: i64| {
+            std::env::var(var).ok().and_then(|v| v.parse().ok()).unwrap_or(defecto)
+        };
+        ConfigExpiracion {
+            registro_minutos: minutos("SESION_EXPIRA_REGISTRO_MIN", 24 * 60),
+            farmacia_minutos: minutos("SESION_EXPIRA_FARMACIA_MIN", 4 * 60),
+            laboratorio_minutos: minutos("SESION_EXPIRA_LAB_MIN", 60),
+        }
+    }
+
+    pub fn limite(&self, grupo: GrupoEstado) -> Option<i64> {
+        let minutos = match grupo {
+            GrupoEstado::Menu => 0,
+            GrupoEstado::Registro => self.registro_minutos,
+            GrupoEstado::Farmacia => self.farmacia_minutos,
+            GrupoEstado::Laboratorio => self.laboratorio_minutos,
+        };
+        (minutos > 0).then_some(minutos)
+    }
+}
+
+static CONFIG: OnceLock<ConfigExpiracion> = OnceLock::new();
+
+fn config() -> &'static ConfigExpiracion {
+    CONFIG.get_or_init(ConfigExpiracion::desde_env)
+}
+
+/// Si la sesión quedó a medias más tiempo del permitido, en lugar de atender el mensaje
+/// se le pregunta al paciente si quiere continuar o empezar de nuevo.
+pub async fn revisar_expiracion(ctx: &Contexto<'_>, estado: UserState, sesion: &UserSession, ahora: i64) -> Option<Transicion> {
+    let limite = config().limite(grupo(estado))?;
+    let inactivo = (ahora - sesion.ultima_actualizacion) / 60;
+    if inactivo < limite {
+        return None;
+    }
+
+    println!("⏰ Sesión de {} vencida en {} ({} min sin actividad)", ctx.telefono, estado, inactivo);
+    let contexto = SessionContext { estado_anterior: Some(estado.to_string()), ..ctx.sesion.clone() };
+    Some(Transicion::a(UserState::ReanudandoSesion).botones(botones_reanudar(ctx).await).contexto(contexto))
+}
+
+async fn botones_reanudar(ctx: &Contexto<'_>) -> Botones {
+    let mut texto = "👋 ¡Hola de nuevo! Tenemos guardado dónde te quedaste la última vez.".to_string();
+    if let Some(order_id) = database::obtener_orden_pendiente(ctx.pool, ctx.patient_id).await {
+        let productos = database::obtener_resumen_carrito(ctx.pool, order_id).await.len();
+        if productos > 0 {
+            texto.push_str(&format!("\n\n🛒 Tienes {} producto(s) en tu carrito.", productos));
+        }
+    }
+    texto.push_str("\n\n¿Cómo quieres seguir?");
+
+    Botones::new(&texto)
+        .boton(Accion::Continuar, "▶️ Continuar")
+        .boton(Accion::EmpezarDeNuevo, "🔄 Empezar de nuevo")
+}
+
+/// Al arrancar: pasa las sesiones con estados renombrados a su alias y avisa de las que no se reconocen.
+/// Devuelve los estados desconocidos que quedaron sin migrar.
+pub async fn migrar_estados(pool: &PgPool) -> Vec<String> {
+    let mut desconocidos = Vec::new();
+
+    for (guardado, sesiones) in database::contar_estados_sesiones(pool).await {
+        if UserState::from_str(&guardado).is_ok() {
+            continue;
+        }
+        match alias().get(&guardado) {
+            Some(nuevo) => {
+                let migradas = database::renombrar_estado_sesiones(pool, &guardado, nuevo.nombre()).await;
+                println!("🔁 {} sesiones pasaron de {} a {}", migradas, guardado, nuevo);
+            }
+            None => {
+                eprintln!("⚠️ {} sesiones en el estado desconocido '{}' (agrega un alias en ESTADOS_ALIAS)", sesiones, guardado);
+                desconocidos.push(guardado);
+            }
+        }
+    }
+    desconocidos
+}
+
+pub fn manejadores() -> Vec<Box<dyn StateHandler>> {
+    vec![Box::new(ReanudandoSesionHandler)]
+}
+
+struct ReanudandoSesionHandler;
+
+#[async_trait]
+impl StateHandler for ReanudandoSesionHandler {
+    fn estado(&self) -> UserState { UserState::ReanudandoSesion }
+
+    // "Continuar" regresa al estado guardado en el contexto; como "atrás", no es una arista del flujo
+    fn transiciones(&self) -> &'static [UserState] { &[UserState::Inicio, UserState::EsperandoNombre] }
+
+    fn ayuda(&self) -> &'static str { "Elige *Continuar* para seguir donde te quedaste o *Empezar de nuevo* para volver al menú." }
+    fn entradas(&self) -> &'static [&'static str] { &["▶️ Continuar", "🔄 Empezar de nuevo"] }
+
+    async fn manejar(&self, ctx: &Contexto<'_>, entrada: &UserInput) -> Transicion {
+        let limpio = SessionContext { estado_anterior: None, ..ctx.sesion.clone() };
+
+        match entrada {
+            UserInput::Accion(Accion::Continuar) => {
+                let anterior = ctx.sesion.estado_anterior.as_deref()
+                    .and_then(UserState::resolver)
+                    .filter(|e| *e != UserState::ReanudandoSesion);
+                match anterior {
+                    Some(estado) => flow::flujo().reanudar(ctx, estado).await.contexto(limpio),
+                    None => bienvenida(ctx.pool, ctx.telefono).await,
+                }
+            }
+            UserInput::Accion(Accion::EmpezarDeNuevo) => {
+                // El carrito a medias se descarta junto con la conversación
+                if let Some(order_id) = database::obtener_orden_pendiente(ctx.pool, ctx.patient_id).await {
+                    database::vaciar_carrito(ctx.pool, order_id).await;
+                }
+                bienvenida(ctx.pool, ctx.telefono).await
+            }
+            _ => Transicion::a(UserState::ReanudandoSesion).botones(botones_reanudar(ctx).await),
+        }
+    }
+}
@@ -1,86 +1,116 @@
+use std::collections::HashMap;
+use std::fmt;
 use std::str::FromStr;
+use std::sync::OnceLock;
+use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-pub enum UserState {
+/// Genera el enum, `TODOS`, `Display`, `FromStr` y serde desde una sola tabla variante => nombre guardado
+macro_rules! estados {
+    ($($(#[$meta:meta])* $variante:ident => $nombre:literal,)*) => {
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+        pub enum UserState {
+            $($(#[$meta])* #[serde(rename = $nombre)] $variante,)*
+        }
+
+        impl UserState {
+            /// Todos los estados, para validar que cada uno tenga su manejador
+            pub const TODOS: [UserState; [$(UserState::$variante),*].len()] = [$(UserState::$variante),*];
+
+            /// Nombre con el que se guarda en `sesiones`
+            pub const fn nombre(self) -> &'static str {
+                match self {
+                    $(UserState::$variante => $nombre,)*
+                }
+            }
+        }
+
+        impl FromStr for UserState {
+            type Err = String;
+
+            fn from_str(s: &str) -> Result<Self, Self::Err> {
+                match s {
+                    $($nombre => Ok(UserState::$variante),)*
+                    _ => Err(format!("Estado desconocido: {}", s)),
+                }
+            }
+        }
+    };
+}
+
+estados! {
     // Estados iniciales
-    Inicio,
-    MenuPrincipal,
-    Nuevo,            // No registrado
-    EsperandoNombre,  // Le preguntamos el nombre
-    ConfirmandoNombre,// Confirmando si escribió bien su nombre
-    
+    Inicio => "INICIO",
+    /// No registrado
+    Nuevo => "NUEVO",
+    /// Le preguntamos el nombre
+    EsperandoNombre => "ESPERANDO_NOMBRE",
+    /// Confirmando si escribió bien su nombre
+    ConfirmandoNombre => "CONFIRMANDO_NOMBRE",
+
     // Laboratorio
-    SeleccionandoExamen,
-    
+    SeleccionandoExamen => "SELECCIONANDO_EXAMEN",
+
     // Farmacia
-    MenuFarmacia,
-    EsperandoCategoria,
-    AgregandoProducto,
-    EsperandoBusqueda,
-    ConfirmandoPedido,
-    
+    MenuFarmacia => "MENU_FARMACIA",
+    EsperandoCategoria => "ESPERANDO_CATEGORIA",
+    AgregandoProducto => "AGREGANDO_PRODUCTO",
+    /// Eligió un medicamento: preguntamos cuántas piezas
+    EsperandoCantidad => "ESPERANDO_CANTIDAD",
+    EsperandoBusqueda => "ESPERANDO_BUSQUEDA",
+    /// "Ver carrito": cambiando cantidades o quitando productos
+    RevisandoCarrito => "REVISANDO_CARRITO",
+    ConfirmandoPedido => "CONFIRMANDO_PEDIDO",
+    EsperandoMetodoPago => "ESPERANDO_METODO_PAGO",
+
     // Usuario / Perfil
-    EsperandoPrimerNombre,
-    EsperandoApellidoPaterno,
-    EsperandoApellidoMaterno,
-    EsperandoEmail,
-    EsperandoCurp,
-    EsperandoGenero,
-    EsperandoDireccion,
-    EsperandoReceta,
+    EsperandoPrimerNombre => "ESPERANDO_PRIMER_NOMBRE",
+    EsperandoApellidoPaterno => "ESPERANDO_APELLIDO_PATERNO",
+    EsperandoApellidoMaterno => "ESPERANDO_APELLIDO_MATERNO",
+    EsperandoEmail => "ESPERANDO_EMAIL",
+    EsperandoCurp => "ESPERANDO_CURP",
+    EsperandoGenero => "ESPERANDO_GENERO",
+    /// Ya tiene una dirección guardada: preguntamos si la usamos
+    ConfirmandoDireccion => "CONFIRMANDO_DIRECCION",
+    EsperandoDireccion => "ESPERANDO_DIRECCION",
+    EsperandoReceta => "ESPERANDO_RECETA",
+
+    /// Sesión vencida: preguntamos si continúa o empieza de nuevo
+    ReanudandoSesion => "REANUDANDO_SESION",
 }
 
-impl ToString for UserState {
-    fn to_string(&self) -> String {
-        match self {
-            UserState::Inicio => "INICIO".to_string(),
-            UserState::MenuPrincipal => "MENU_PRINCIPAL".to_string(),
-            UserState::SeleccionandoExamen => "SELECCIONANDO_EXAMEN".to_string(),
-            UserState::MenuFarmacia => "MENU_FARMACIA".to_string(),
-            UserState::EsperandoCategoria => "ESPERANDO_CATEGORIA".to_string(),
-            UserState::AgregandoProducto => "AGREGANDO_PRODUCTO".to_string(),
-            UserState::EsperandoBusqueda => "ESPERANDO_BUSQUEDA".to_string(),
-            UserState::ConfirmandoPedido => "CONFIRMANDO_PEDIDO".to_string(),
-            UserState::EsperandoPrimerNombre => "ESPERANDO_PRIMER_NOMBRE".to_string(),
-            UserState::EsperandoApellidoPaterno => "ESPERANDO_APELLIDO_PATERNO".to_string(),
-            UserState::EsperandoApellidoMaterno => "ESPERANDO_APELLIDO_MATERNO".to_string(),
-            UserState::EsperandoEmail => "ESPERANDO_EMAIL".to_string(),
-            UserState::EsperandoCurp => "ESPERANDO_CURP".to_string(),
-            UserState::EsperandoGenero => "ESPERANDO_GENERO".to_string(),
-            UserState::EsperandoDireccion => "ESPERANDO_DIRECCION".to_string(),
-            UserState::EsperandoReceta => "ESPERANDO_RECETA".to_string(),
-            UserState::Nuevo => "NUEVO".to_string(),
-            UserState::EsperandoNombre => "ESPERANDO_NOMBRE".to_string(),
-            UserState::ConfirmandoNombre => "CONFIRMANDO_NOMBRE".to_string(),
-        }
+impl fmt::Display for UserState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.nombre())
     }
 }
 
-impl FromStr for UserState {
-    type Err = String;
+/// Nombres viejos que siguen apareciendo en sesiones guardadas
+const ALIAS_PREDETERMINADOS: &[(&str, UserState)] = &[
+    // El menú principal ahora vive en INICIO
+    ("MENU_PRINCIPAL", UserState::Inicio),
+];
+
+/// Alias de estados renombrados. `ESTADOS_ALIAS="VIEJO=NUEVO,OTRO=INICIO"` agrega o reemplaza los predeterminados.
+pub fn alias() -> &'static HashMap<String, UserState> {
+    static ALIAS: OnceLock<HashMap<String, UserState>> = OnceLock::new();
+    ALIAS.get_or_init(|| {
+        let mut alias: HashMap<String, UserState> = ALIAS_PREDETERMINADOS.iter()
+            .map(|(viejo, nuevo)| (viejo.to_string(), *nuevo))
+            .collect();
 
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        match s {
-            "INICIO" => Ok(UserState::Inicio),
-            "MENU_PRINCIPAL" => Ok(UserState::MenuPrincipal),
-            "SELECCIONANDO_EXAMEN" => Ok(UserState::SeleccionandoExamen),
-            "MENU_FARMACIA" => Ok(UserState::MenuFarmacia),
-            "ESPERANDO_CATEGORIA" => Ok(UserState::EsperandoCategoria),
-            "AGREGANDO_PRODUCTO" => Ok(UserState::AgregandoProducto),
-            "ESPERANDO_BUSQUEDA" => Ok(UserState::EsperandoBusqueda),
-            "CONFIRMANDO_PEDIDO" => Ok(UserState::ConfirmandoPedido),
-            "ESPERANDO_PRIMER_NOMBRE" => Ok(UserState::EsperandoPrimerNombre),
-            "ESPERANDO_APELLIDO_PATERNO" => Ok(UserState::EsperandoApellidoPaterno),
-            "ESPERANDO_APELLIDO_MATERNO" => Ok(UserState::EsperandoApellidoMaterno),
-            "ESPERANDO_EMAIL" => Ok(UserState::EsperandoEmail),
-            "ESPERANDO_CURP" => Ok(UserState::EsperandoCurp),
-            "ESPERANDO_GENERO" => Ok(UserState::EsperandoGenero),
-            "ESPERANDO_DIRECCION" => Ok(UserState::EsperandoDireccion),
-            "ESPERANDO_RECETA" => Ok(UserState::EsperandoReceta),
-            "NUEVO"  => Ok(UserState::Nuevo),
-            "ESPERANDO_NOMBRE"  => Ok(UserState::EsperandoNombre),
-            "CONFIRMANDO_NOMBRE"  => Ok(UserState::ConfirmandoNombre),
-            _ => Err(format!("Estado desconocido: {}", s)),
+        for par in std::env::var("ESTADOS_ALIAS").unwrap_or_default().split(',').filter(|p| !p.trim().is_empty()) {
+            match par.split_once('=').map(|(v, n)| (v.trim(), UserState::from_str(n.trim()))) {
+                Some((viejo, Ok(nuevo))) => { alias.insert(viejo.to_string(), nuevo); }
+                _ => eprintln!("⚠️ Alias de estado inválido en ESTADOS_ALIAS: {}", par),
+            }
         }
+        alias
+    })
+}
+
+impl UserState {
+    /// Interpreta un estado guardado aceptando también los nombres viejos
+    pub fn resolver(s: &str) -> Option<UserState> {
+        UserState::from_str(s).ok().or_else(|| alias().get(s).copied())
     }
 }
@@ -1,144 +1,594 @@
+use async_trait::async_trait;
 use sqlx::PgPool;
-use crate::whatsapp;
+use crate::database;
+use crate::whatsapp::Botones;
+use super::flow::{Contexto, StateHandler, Transicion};
 use super::states::UserState;
+use super::models::SessionContext;
+use super::input::{Accion, UserInput};
+use super::{lab, pharmacy};
 use regex::Regex;
+use std::sync::OnceLock;
 
-pub async fn enviar_bienvenida(pool: &PgPool, telefono: &str) {
+/// Saludo de "hola"/"inicio": menú principal si ya lo conocemos, registro si no.
+/// Empieza de cero, así que limpia el contexto de la sesión.
+pub async fn bienvenida(pool: &PgPool, telefono: &str) -> Transicion {
     // 1. Buscamos al usuario
-    if let Some(u) = crate::database::obtener_usuario_por_telefono(pool, telefono).await {
-        // ¿Ya tiene un nombre confirmado? (No está vacío y no es TEMP)
+    if let Some(u) = database::obtener_usuario_por_telefono(pool, telefono).await {
+        // ¿Ya tiene un nombre confirmado? (No está vacío ni es un TEMP- de las sesiones viejas)
         if !u.first_name.trim().is_empty() && !u.first_name.contains("TEMP-") {
             // USUARIO CONOCIDO: Ir al menú principal directamente
-            crate::database::cambiar_estado(pool, telefono, &UserState::Inicio.to_string()).await;
-            
             let mensaje = format!("¡Hola, *{}*! Qué gusto saludarte de nuevo en *Biotecza*.\n\n¿En qué podemos apoyarte hoy? 👇😊", u.first_name);
-            whatsapp::enviar_botones(telefono, &mensaje, vec!["🔬 Laboratorio", "💊 Medicamentos"]).await;
-            return;
+            return Transicion::a(UserState::Inicio)
+                .botones(botones_menu_principal(&mensaje))
+                .contexto(SessionContext::default());
         }
     }
 
     // USUARIO NUEVO (o sin nombre): Mandarlo al flujo de registro de Andy
-    crate::database::cambiar_estado(pool, telefono, &UserState::Nuevo.to_string()).await;
-    
-    let saludo_nuevo = "Hola, no te había visto por aquí. 👀 Mucho gusto, soy el Asistente virtual de *Biotecza*.\n\n\
-                        ¿Cuál es tu nombre? 👇🏼\n\
-                        _Escribe solo tu nombre_";
-
-    crate::database::cambiar_estado(pool, telefono, &UserState::EsperandoNombre.to_string()).await;
-    
-    whatsapp::enviar_texto(telefono, saludo_nuevo).await;
-}
-pub async fn enviar_menu_principal_con_privacidad(telefono: &str, nombre: &str) {
-    // 1. Enviamos el link de privacidad como un mensaje simple de texto
+    saludo_nuevo().contexto(SessionContext::default())
+}
+
+fn saludo_nuevo() -> Transicion {
+    let saludo = "Hola, no te había visto por aquí. 👀 Mucho gusto, soy el Asistente virtual de *Biotecza*.\n\n\
+                  ¿Cuál es tu nombre? 👇🏼\n\
+                  _Escribe solo tu nombre_";
+    Transicion::a(UserState::EsperandoNombre).texto(saludo)
+}
+
+/// Aviso de privacidad seguido del menú principal
+pub fn menu_principal_con_privacidad(nombre: &str) -> Transicion {
     let mensaje_privacidad = format!(
         "¡Mucho gusto, *{}*! Conoce aquí nuestro Aviso de Privacidad 👇\nhttps://biotecza.com/privacidad",
         nombre
     );
-    whatsapp::enviar_texto(telefono, &mensaje_privacidad).await;
-
-    // 2. Inmediatamente enviamos el menú de opciones con botones
     let mensaje_menu = "¿Qué necesitas hoy? Elige la opción que mejor se adapte a tu solicitud 👇😊";
-    let opciones = vec!["🔬 Laboratorio", "💊 Medicamentos"];
-    
-    whatsapp::enviar_botones(telefono, mensaje_menu, opciones).await;
-}
-
-pub async fn procesar_usuario(
-    pool: &PgPool,
-    telefono: &str,
-    entrada: &str,
-    estado: UserState,
-    user_id: &uuid::Uuid,
-    patient_id: &uuid::Uuid,
-) -> bool {
-    match estado {
-        UserState::ConfirmandoPedido => {
-            if entrada == "Confirmar Pedido" {
-                crate::database::cambiar_estado(pool, telefono, &UserState::EsperandoPrimerNombre.to_string()).await;
-                whatsapp::enviar_texto(telefono, "¡Excelente! ¿Cuál es tu *nombre*?").await;
-            } else {
-                enviar_bienvenida(pool, telefono).await;
+
+    Transicion::a(UserState::Inicio)
+        .texto(mensaje_privacidad)
+        .botones(botones_menu_principal(mensaje_menu))
+}
+
+/// Botones del menú principal (Laboratorio / Medicamentos)
+pub fn botones_menu_principal(texto: &str) -> Botones {
+    Botones::new(texto)
+        .boton(Accion::Laboratorio, "🔬 Laboratorio")
+        .boton(Accion::Medicamentos, "💊 Medicamentos")
+}
+
+fn botones_confirmar_nombre(nombre: &str) -> Botones {
+    Botones::new(&format!("¡Hola, *{}*! ¿Es correcto tu nombre? 👇🏼", nombre))
+        .boton(Accion::NombreCorrecto, "✅ Sí, es correcto")
+        .boton(Accion::CorregirNombre, "❌ No, corregir")
+}
+
+fn botones_genero() -> Botones {
+    Botones::new("¿Cuál es tu género?")
+        .boton(Accion::Genero("M".to_string()), "M")
+        .boton(Accion::Genero("F".to_string()), "F")
+}
+
+/// Nombre o apellido escrito como texto: solo letras, espacios, guiones, apóstrofos y puntos
+fn nombre_valido(entrada: &UserInput) -> Option<&str> {
+    let UserInput::Texto(texto) = entrada else { return None };
+    let texto = texto.trim();
+    let valido = texto.chars().any(char::is_alphabetic)
+        && texto.chars().count() <= 60
+        && texto.chars().all(|c| c.is_alphabetic() || matches!(c, ' ' | '-' | '\'' | '.'));
+    valido.then_some(texto)
+}
+
+/// CURP normalizado a mayúsculas si tiene el formato oficial de 18 caracteres
+fn curp_valido(texto: &str) -> Option<String> {
+    static CURP: OnceLock<Regex> = OnceLock::new();
+    let re = CURP.get_or_init(|| Regex::new(
+        r"^[A-Z][AEIOUX][A-Z]{2}\d{2}(0[1-9]|1[0-2])(0[1-9]|[12]\d|3[01])[HMX](AS|BC|BS|CC|CL|CM|CS|CH|DF|DG|GT|GR|HG|JC|MC|MN|MS|NT|NL|OC|PL|QT|QR|SP|SL|SR|TC|TS|TL|VZ|YN|ZS|NE)[B-DF-HJ-NP-TV-Z]{3}[A-Z\d]\d$"
+    ).unwrap());
+    let curp = texto.trim().to_uppercase();
+    re.is_match(&curp).then_some(curp)
+}
+
+/// Solo las opciones que ofrecen los botones de género (o escribirlas tal cual)
+fn genero_elegido(entrada: &UserInput) -> Option<&'static str> {
+    let genero = match entrada {
+        UserInput::Accion(Accion::Genero(g)) => g.as_str(),
+        UserInput::Texto(t) => t.trim(),
+        _ => return None,
+    };
+    match genero.to_uppercase().as_str() {
+        "M" => Some("M"),
+        "F" => Some("F"),
+        _ => None,
+    }
+}
+
+/// ¿Ya tenemos todo lo que pide un pedido (nombre completo, correo propio, CURP y género)?
+pub async fn datos_completos(ctx: &Contexto<'_>) -> bool {
+    let Some(u) = database::obtener_usuario_por_telefono(ctx.pool, ctx.telefono).await else { return false };
+    let Some(p) = database::obtener_patient_id_por_telefono(ctx.pool, ctx.telefono).await else { return false };
+
+    // El correo `<teléfono>@biotecza.com` y la CURP `TEMP-` son los que pone el registro por WhatsApp
+    !u.first_name.trim().is_empty()
+        && !u.paternal_last_name.trim().is_empty()
+        && !u.email.ends_with("@biotecza.com")
+        && p.curp.is_some_and(|c| c.len() == 18 && !c.starts_with("TEMP-"))
+        && p.gender.is_some()
+}
+
+/// Paso de entrega: si ya tiene una dirección guardada se la ofrecemos, si no se la pedimos
+pub async fn paso_direccion(ctx: &Contexto<'_>) -> Transicion {
+    match database::obtener_direccion_predeterminada(ctx.pool, ctx.patient_id).await {
+        Some(direccion) => Transicion::a(UserState::ConfirmandoDireccion).botones(botones_direccion(&direccion)),
+        None => Transicion::a(UserState::EsperandoDireccion)
+            .texto("📍 ¿Cuál es la *dirección completa*? También puedes compartir tu *ubicación*."),
+    }
+}
+
+fn botones_direccion(direccion: &str) -> Botones {
+    Botones::new(&format!("📍 ¿Enviamos tu pedido a esta dirección?\n\n{}", direccion))
+        .boton(Accion::UsarDireccion, "✅ Sí, a esa")
+        .boton(Accion::OtraDireccion, "✏️ Otra dirección")
+}
+
+fn pedir_receta() -> Transicion {
+    Transicion::a(UserState::EsperandoReceta).texto("✅ ¡Listo! Ahora envía la *foto de tu receta médica*.")
+}
+
+pub fn manejadores() -> Vec<Box<dyn StateHandler>> {
+    vec![
+        Box::new(NuevoHandler),
+        Box::new(EsperandoNombreHandler),
+        Box::new(ConfirmandoNombreHandler),
+        Box::new(InicioHandler),
+        Box::new(EsperandoPrimerNombreHandler),
+        Box::new(EsperandoApellidoPaternoHandler),
+        Box::new(EsperandoApellidoMaternoHandler),
+        Box::new(EsperandoEmailHandler),
+        Box::new(EsperandoCurpHandler),
+        Box::new(EsperandoGeneroHandler),
+        Box::new(ConfirmandoDireccionHandler),
+        Box::new(EsperandoDireccionHandler),
+        Box::new(EsperandoRecetaHandler),
+    ]
+}
+
+// --- REGISTRO ---
+
+struct NuevoHandler;
+
+#[async_trait]
+impl StateHandler for NuevoHandler {
+    fn estado(&self) -> UserState { UserState::Nuevo }
+    fn transiciones(&self) -> &'static [UserState] { &[UserState::EsperandoNombre] }
+
+    fn ayuda(&self) -> &'static str { "Estoy por presentarme y preguntarte tu nombre." }
+    fn entradas(&self) -> &'static [&'static str] { &["cualquier mensaje"] }
+
+    async fn reanudar(&self, _ctx: &Contexto<'_>) -> Transicion {
+        saludo_nuevo()
+    }
+
+    async fn manejar(&self, _ctx: &Contexto<'_>, _entrada: &UserInput) -> Transicion {
+        saludo_nuevo()
+    }
+}
+
+struct EsperandoNombreHandler;
+
+#[async_trait]
+impl StateHandler for EsperandoNombreHandler {
+    fn estado(&self) -> UserState { UserState::EsperandoNombre }
+    fn transiciones(&self) -> &'static [UserState] { &[UserState::ConfirmandoNombre] }
+
+    fn ayuda(&self) -> &'static str { "Escríbeme solo tu nombre para poder registrarte." }
+    fn entradas(&self) -> &'static [&'static str] { &["nombre (texto)"] }
+
+    async fn reanudar(&self, _ctx: &Contexto<'_>) -> Transicion {
+        Transicion::a(UserState::EsperandoNombre).texto("¿Cuál es tu nombre? 👇🏼\n_Escribe solo tu nombre_")
+    }
+
+    async fn manejar(&self, ctx: &Contexto<'_>, entrada: &UserInput) -> Transicion {
+        let Some(nombre_recibido) = nombre_valido(entrada) else {
+            return Transicion::a(UserState::EsperandoNombre).texto("✍️ Escribe solo tu nombre, con letras, por favor 👇🏼");
+        };
+
+        // El nombre queda en el contexto de la sesión hasta que lo confirme
+        let contexto = SessionContext { nombre_pendiente: Some(nombre_recibido.to_string()), ..ctx.sesion.clone() };
+
+        Transicion::a(UserState::ConfirmandoNombre)
+            .botones(botones_confirmar_nombre(nombre_recibido))
+            .contexto(contexto)
+    }
+}
+
+struct ConfirmandoNombreHandler;
+
+#[async_trait]
+impl StateHandler for ConfirmandoNombreHandler {
+    fn estado(&self) -> UserState { UserState::ConfirmandoNombre }
+    fn transiciones(&self) -> &'static [UserState] { &[UserState::Inicio, UserState::EsperandoNombre] }
+
+    fn ayuda(&self) -> &'static str { "Confirma con los botones si tu nombre es correcto o elige corregirlo." }
+    fn entradas(&self) -> &'static [&'static str] { &["✅ Sí, es correcto", "❌ No, corregir"] }
+
+    async fn reanudar(&self, ctx: &Contexto<'_>) -> Transicion {
+        match &ctx.sesion.nombre_pendiente {
+            Some(nombre) => Transicion::a(UserState::ConfirmandoNombre).botones(botones_confirmar_nombre(nombre)),
+            None => saludo_nuevo(),
+        }
+    }
+
+    async fn manejar(&self, ctx: &Contexto<'_>, entrada: &UserInput) -> Transicion {
+        if *entrada == UserInput::Accion(Accion::NombreCorrecto) {
+            // Recuperamos el nombre que guardamos en el paso anterior y ahora sí va a `users`
+            let Some(nombre) = ctx.sesion.nombre_pendiente.clone() else {
+                return saludo_nuevo();
+            };
+            database::actualizar_nombre_usuario(ctx.pool, ctx.user_id, &nombre).await;
+            menu_principal_con_privacidad(&nombre)
+                .contexto(SessionContext { nombre_pendiente: None, ..ctx.sesion.clone() })
+        } else {
+            Transicion::a(UserState::EsperandoNombre).texto("No te preocupes, ¿cómo te llamas entonces? 👇🏼")
+        }
+    }
+}
+
+// --- MENÚ PRINCIPAL ---
+
+struct InicioHandler;
+
+#[async_trait]
+impl StateHandler for InicioHandler {
+    fn estado(&self) -> UserState { UserState::Inicio }
+    fn transiciones(&self) -> &'static [UserState] {
+        &[UserState::SeleccionandoExamen, UserState::MenuFarmacia, UserState::EsperandoNombre]
+    }
+
+    fn ayuda(&self) -> &'static str { "Elige *Laboratorio* para ver nuestros estudios o *Medicamentos* para pedir en la farmacia." }
+    fn entradas(&self) -> &'static [&'static str] { &["🔬 Laboratorio", "💊 Medicamentos"] }
+
+    async fn reanudar(&self, _ctx: &Contexto<'_>) -> Transicion {
+        Transicion::a(UserState::Inicio).botones(botones_menu_principal("¿En qué podemos apoyarte hoy? 👇😊"))
+    }
+
+    async fn manejar(&self, ctx: &Contexto<'_>, entrada: &UserInput) -> Transicion {
+        match entrada {
+            UserInput::Accion(Accion::Laboratorio) => {
+                let lista = lab::lista_estudios(ctx.pool, "🔬 Estudios", "Selecciona un estudio:", 0).await;
+                Transicion::a(UserState::SeleccionandoExamen)
+                    .lista(lista)
+                    .contexto(SessionContext { pagina: 0, ..ctx.sesion.clone() })
+            }
+            UserInput::Accion(Accion::Medicamentos) => {
+                Transicion::a(UserState::MenuFarmacia)
+                    .botones(pharmacy::botones_menu_farmacia("💊 ¿Cómo quieres buscar tu medicamento?"))
             }
-            true
-        },
-
-        UserState::EsperandoPrimerNombre => {
-            // Guardar el nombre (first_name)
-            let _ = sqlx::query!("UPDATE users SET first_name = $1 WHERE user_id = $2", entrada, user_id)
-                .execute(pool).await;
-
-            crate::database::cambiar_estado(pool, telefono, &UserState::EsperandoApellidoPaterno.to_string()).await;
-            whatsapp::enviar_texto(telefono, "Gracias. ¿Cuál es tu *apellido paterno*?").await;
-            true
-        },
-
-        UserState::EsperandoApellidoPaterno => {
-            // Guardar apellido paterno en paternal_last_name
-            let _ = sqlx::query!("UPDATE users SET paternal_last_name = $1 WHERE user_id = $2", entrada, user_id)
-                .execute(pool).await;
-
-            crate::database::cambiar_estado(pool, telefono, &UserState::EsperandoApellidoMaterno.to_string()).await;
-            whatsapp::enviar_texto(telefono, "Ahora, ¿cuál es tu *apellido materno*? (o responde '-' si no aplica)").await;
-            true
-        },
-
-        UserState::EsperandoApellidoMaterno => {
-            // Si no aplica, se puede enviar '-' para omitir
-            if entrada.trim() != "-" {
-                // Guardar apellido materno en maternal_last_name
-                let _ = sqlx::query!("UPDATE users SET maternal_last_name = $1 WHERE user_id = $2", entrada, user_id)
-                    .execute(pool).await;
+            _ => bienvenida(ctx.pool, ctx.telefono).await,
+        }
+    }
+}
+
+// --- DATOS DEL PEDIDO ---
+
+struct EsperandoPrimerNombreHandler;
+
+#[async_trait]
+impl StateHandler for EsperandoPrimerNombreHandler {
+    fn estado(&self) -> UserState { UserState::EsperandoPrimerNombre }
+    fn transiciones(&self) -> &'static [UserState] { &[UserState::EsperandoApellidoPaterno] }
+
+    fn ayuda(&self) -> &'static str { "Escribe tu primer nombre tal como aparece en tu identificación." }
+    fn entradas(&self) -> &'static [&'static str] { &["nombre (texto)"] }
+
+    async fn reanudar(&self, _ctx: &Contexto<'_>) -> Transicion {
+        Transicion::a(UserState::EsperandoPrimerNombre).texto("¿Cuál es tu *nombre*?")
+    }
+
+    async fn manejar(&self, ctx: &Contexto<'_>, entrada: &UserInput) -> Transicion {
+        let Some(nombre) = nombre_valido(entrada) else {
+            return Transicion::a(UserState::EsperandoPrimerNombre)
+                .texto("❌ Escribe tu *nombre* solo con letras, tal como aparece en tu identificación:");
+        };
+        database::actualizar_primer_nombre(ctx.pool, ctx.user_id, nombre).await;
+
+        Transicion::a(UserState::EsperandoApellidoPaterno).texto("Gracias. ¿Cuál es tu *apellido paterno*?")
+    }
+}
+
+struct EsperandoApellidoPaternoHandler;
+
+#[async_trait]
+impl StateHandler for EsperandoApellidoPaternoHandler {
+    fn estado(&self) -> UserState { UserState::EsperandoApellidoPaterno }
+    fn transiciones(&self) -> &'static [UserState] { &[UserState::EsperandoApellidoMaterno] }
+
+    fn ayuda(&self) -> &'static str { "Escribe tu apellido paterno." }
+    fn entradas(&self) -> &'static [&'static str] { &["apellido (texto)"] }
+
+    async fn reanudar(&self, _ctx: &Contexto<'_>) -> Transicion {
+        Transicion::a(UserState::EsperandoApellidoPaterno).texto("¿Cuál es tu *apellido paterno*?")
+    }
+
+    async fn manejar(&self, ctx: &Contexto<'_>, entrada: &UserInput) -> Transicion {
+        let Some(apellido) = nombre_valido(entrada) else {
+            return Transicion::a(UserState::EsperandoApellidoPaterno)
+                .texto("❌ Escribe tu *apellido paterno* solo con letras:");
+        };
+        database::actualizar_apellido_paterno(ctx.pool, ctx.user_id, apellido).await;
+
+        Transicion::a(UserState::EsperandoApellidoMaterno)
+            .texto("Ahora, ¿cuál es tu *apellido materno*? (o responde '-' si no aplica)")
+    }
+}
+
+struct EsperandoApellidoMaternoHandler;
+
+#[async_trait]
+impl StateHandler for EsperandoApellidoMaternoHandler {
+    fn estado(&self) -> UserState { UserState::EsperandoApellidoMaterno }
+    fn transiciones(&self) -> &'static [UserState] { &[UserState::EsperandoEmail] }
+
+    fn ayuda(&self) -> &'static str { "Escribe tu apellido materno." }
+    fn entradas(&self) -> &'static [&'static str] { &["apellido o '-'"] }
+
+    async fn reanudar(&self, _ctx: &Contexto<'_>) -> Transicion {
+        Transicion::a(UserState::EsperandoApellidoMaterno).texto("¿Cuál es tu *apellido materno*? (o responde '-' si no aplica)")
+    }
+
+    async fn manejar(&self, ctx: &Contexto<'_>, entrada: &UserInput) -> Transicion {
+        // Si no aplica, se puede enviar '-' para omitir
+        if entrada.texto().trim() != "-" {
+            let Some(apellido) = nombre_valido(entrada) else {
+                return Transicion::a(UserState::EsperandoApellidoMaterno)
+                    .texto("❌ Escribe tu *apellido materno* solo con letras (o responde '-' si no aplica):");
+            };
+            database::actualizar_apellido_materno(ctx.pool, ctx.user_id, apellido).await;
+        }
+
+        // Obtener first_name para el saludo
+        let pregunta = match database::obtener_usuario_por_telefono(ctx.pool, ctx.telefono).await {
+            Some(u) => format!("Mucho gusto, {}. ¿Cuál es tu *correo*?", u.first_name),
+            None => "¿Cuál es tu *correo*?".to_string(),
+        };
+        Transicion::a(UserState::EsperandoEmail).texto(pregunta)
+    }
+}
+
+struct EsperandoEmailHandler;
+
+#[async_trait]
+impl StateHandler for EsperandoEmailHandler {
+    fn estado(&self) -> UserState { UserState::EsperandoEmail }
+    fn transiciones(&self) -> &'static [UserState] { &[UserState::EsperandoCurp] }
+
+    fn ayuda(&self) -> &'static str { "Escribe tu correo electrónico, por ejemplo *nombre@correo.com*." }
+    fn entradas(&self) -> &'static [&'static str] { &["correo (texto)"] }
+
+    async fn reanudar(&self, _ctx: &Contexto<'_>) -> Transicion {
+        Transicion::a(UserState::EsperandoEmail).texto("¿Cuál es tu *correo*?")
+    }
+
+    async fn manejar(&self, ctx: &Contexto<'_>, entrada: &UserInput) -> Transicion {
+        let email = entrada.texto().trim();
+        // Expresión regular simple para validar formato básico de email
+        static EMAIL: OnceLock<Regex> = OnceLock::new();
+        let re = EMAIL.get_or_init(|| Regex::new(r"(?i)^[^\s@]+@[^\s@]+\.[^\s@]+$").unwrap());
+        if re.is_match(email) {
+            database::actualizar_email_usuario(ctx.pool, ctx.user_id, email).await;
+            Transicion::a(UserState::EsperandoCurp).texto("Gracias. Ahora ingresa tu *CURP* (18 caracteres):")
+        } else {
+            Transicion::a(UserState::EsperandoEmail).texto("❌ Formato de correo inválido. Por favor ingresa un correo válido:")
+        }
+    }
+}
+
+struct EsperandoCurpHandler;
+
+#[async_trait]
+impl StateHandler for EsperandoCurpHandler {
+    fn estado(&self) -> UserState { UserState::EsperandoCurp }
+    fn transiciones(&self) -> &'static [UserState] { &[UserState::EsperandoGenero] }
+
+    fn ayuda(&self) -> &'static str { "Escribe tu CURP: son 18 letras y números, viene en tu INE o la consultas en gob.mx/curp." }
+    fn entradas(&self) -> &'static [&'static str] { &["CURP (18 caracteres)"] }
+
+    async fn reanudar(&self, _ctx: &Contexto<'_>) -> Transicion {
+        Transicion::a(UserState::EsperandoCurp).texto("Ingresa tu *CURP* (18 caracteres):")
+    }
+
+    async fn manejar(&self, ctx: &Contexto<'_>, entrada: &UserInput) -> Transicion {
+        match curp_valido(entrada.texto()) {
+            Some(curp) => {
+                database::actualizar_curp(ctx.pool, ctx.patient_id, &curp).await;
+                Transicion::a(UserState::EsperandoGenero).botones(botones_genero())
             }
+            None => Transicion::a(UserState::EsperandoCurp)
+                .texto("❌ CURP inválido. Revisa los 18 caracteres de tu INE e inténtalo de nuevo:"),
+        }
+    }
+}
+
+struct EsperandoGeneroHandler;
+
+#[async_trait]
+impl StateHandler for EsperandoGeneroHandler {
+    fn estado(&self) -> UserState { UserState::EsperandoGenero }
+    fn transiciones(&self) -> &'static [UserState] { &[UserState::ConfirmandoDireccion, UserState::EsperandoDireccion] }
+
+    fn ayuda(&self) -> &'static str { "Elige tu género con los botones *M* o *F*." }
+    fn entradas(&self) -> &'static [&'static str] { &["M", "F"] }
+
+    async fn reanudar(&self, _ctx: &Contexto<'_>) -> Transicion {
+        Transicion::a(UserState::EsperandoGenero).botones(botones_genero())
+    }
+
+    async fn manejar(&self, ctx: &Contexto<'_>, entrada: &UserInput) -> Transicion {
+        let Some(genero) = genero_elegido(entrada) else {
+            return Transicion::a(UserState::EsperandoGenero).botones(botones_genero());
+        };
+        database::actualizar_genero(ctx.pool, ctx.patient_id, genero).await;
+        paso_direccion(ctx).await
+    }
+}
 
-            crate::database::cambiar_estado(pool, telefono, &UserState::EsperandoEmail.to_string()).await;
-            // Obtener first_name para el saludo
-            if let Some(u) = crate::database::obtener_usuario_por_telefono(pool, telefono).await {
-                whatsapp::enviar_texto(telefono, &format!("Mucho gusto, {}. ¿Cuál es tu *correo*?", u.first_name)).await;
-            } else {
-                whatsapp::enviar_texto(telefono, "¿Cuál es tu *correo*?").await;
+struct ConfirmandoDireccionHandler;
+
+#[async_trait]
+impl StateHandler for ConfirmandoDireccionHandler {
+    fn estado(&self) -> UserState { UserState::ConfirmandoDireccion }
+    fn transiciones(&self) -> &'static [UserState] { &[UserState::EsperandoReceta, UserState::EsperandoDireccion] }
+
+    fn ayuda(&self) -> &'static str { "Elige si enviamos tu pedido a la dirección que tenemos guardada o a otra." }
+    fn entradas(&self) -> &'static [&'static str] { &["✅ Sí, a esa", "✏️ Otra dirección"] }
+
+    async fn reanudar(&self, ctx: &Contexto<'_>) -> Transicion {
+        paso_direccion(ctx).await
+    }
+
+    async fn manejar(&self, ctx: &Contexto<'_>, entrada: &UserInput) -> Transicion {
+        match entrada {
+            UserInput::Accion(Accion::UsarDireccion) => {
+                match database::obtener_direccion_predeterminada(ctx.pool, ctx.patient_id).await {
+                    Some(direccion) => {
+                        database::asignar_direccion_orden(ctx.pool, ctx.patient_id, &direccion).await;
+                        pedir_receta()
+                    }
+                    None => paso_direccion(ctx).await,
+                }
             }
-            true
-        },
-
-        UserState::EsperandoEmail => {
-            let email = entrada.trim();
-            // Expresión regular simple para validar formato básico de email
-            let re = Regex::new(r"(?i)^[^\s@]+@[^\s@]+\.[^\s@]+$").unwrap();
-            if re.is_match(email) {
-                crate::database::actualizar_email_usuario(pool, *user_id, email).await;
-                crate::database::cambiar_estado(pool, telefono, &UserState::EsperandoCurp.to_string()).await;
-                whatsapp::enviar_texto(telefono, "Gracias. Ahora ingresa tu *CURP* (18 caracteres):").await;
-            } else {
-                whatsapp::enviar_texto(telefono, "❌ Formato de correo inválido. Por favor ingresa un correo válido:").await;
+            UserInput::Accion(Accion::OtraDireccion) => Transicion::a(UserState::EsperandoDireccion)
+                .texto("📍 Escribe la *dirección completa* o comparte tu *ubicación*."),
+            _ => paso_direccion(ctx).await,
+        }
+    }
+}
+
+struct EsperandoDireccionHandler;
+
+#[async_trait]
+impl StateHandler for EsperandoDireccionHandler {
+    fn estado(&self) -> UserState { UserState::EsperandoDireccion }
+    fn transiciones(&self) -> &'static [UserState] { &[UserState::EsperandoReceta] }
+
+    fn ayuda(&self) -> &'static str { "Escribe tu dirección de entrega (calle, número, colonia y código postal) o compártenos tu ubicación 📍." }
+    fn entradas(&self) -> &'static [&'static str] { &["dirección (texto)", "📍 ubicación"] }
+
+    async fn reanudar(&self, _ctx: &Contexto<'_>) -> Transicion {
+        Transicion::a(UserState::EsperandoDireccion).texto("📍 ¿Cuál es la *dirección completa*? También puedes compartir tu *ubicación*.")
+    }
+
+    async fn manejar(&self, ctx: &Contexto<'_>, entrada: &UserInput) -> Transicion {
+        match entrada {
+            UserInput::Ubicacion(ubicacion) => {
+                database::guardar_ubicacion_paciente(ctx.pool, ctx.patient_id, ubicacion).await;
             }
-            true
-        },
-
-        UserState::EsperandoCurp => {
-            if entrada.len() == 18 {
-                sqlx::query!("UPDATE patients SET curp = $1 WHERE patient_id = $2", entrada, patient_id).execute(pool).await.ok();
-                crate::database::cambiar_estado(pool, telefono, &UserState::EsperandoGenero.to_string()).await;
-                whatsapp::enviar_botones(telefono, "¿Cuál es tu género?", vec!["M", "F"]).await;
-            } else {
-                whatsapp::enviar_texto(telefono, "❌ CURP inválido. Inténtalo de nuevo:").await;
+            otro if otro.texto().trim().is_empty() => {
+                return Transicion::a(UserState::EsperandoDireccion)
+                    .texto("📍 Escribe tu dirección o comparte tu *ubicación* desde el clip 📎 de WhatsApp.");
             }
-            true
-        },
-
-        UserState::EsperandoGenero => {
-            sqlx::query!("UPDATE patients SET gender = $1 WHERE patient_id = $2", entrada, patient_id).execute(pool).await.ok();
-            crate::database::cambiar_estado(pool, telefono, &UserState::EsperandoDireccion.to_string()).await;
-            whatsapp::enviar_texto(telefono, "📍 ¿Cuál es la *dirección completa*?").await;
-            true
-        },
-
-        UserState::EsperandoDireccion => {
-            crate::database::guardar_direccion_paciente(pool, *patient_id, entrada).await;
-            crate::database::cambiar_estado(pool, telefono, &UserState::EsperandoReceta.to_string()).await;
-            whatsapp::enviar_texto(telefono, "✅ ¡Listo! Ahora envía la *foto de tu receta médica*.").await;
-            true
-        },
-
-        _ => false,
+            otro => database::guardar_direccion_paciente(ctx.pool, ctx.patient_id, otro.texto()).await,
+        }
+        pedir_receta()
+    }
+}
+
+struct EsperandoRecetaHandler;
+
+#[async_trait]
+impl StateHandler for EsperandoRecetaHandler {
+    fn estado(&self) -> UserState { UserState::EsperandoReceta }
+    fn transiciones(&self) -> &'static [UserState] { &[UserState::EsperandoMetodoPago] }
+
+    fn ayuda(&self) -> &'static str { "Envíanos una foto o un PDF de tu receta médica 📎." }
+    fn entradas(&self) -> &'static [&'static str] { &["📎 foto o PDF"] }
+
+    async fn reanudar(&self, _ctx: &Contexto<'_>) -> Transicion {
+        Transicion::a(UserState::EsperandoReceta).texto("📷 Envía la *foto o PDF de tu receta médica* como archivo adjunto.")
+    }
+
+    async fn manejar(&self, ctx: &Contexto<'_>, entrada: &UserInput) -> Transicion {
+        match entrada {
+            UserInput::Media(media) => pharmacy::recibir_receta(ctx, media).await,
+            _ => Transicion::a(UserState::EsperandoReceta)
+                .texto("📷 Para continuar envía la *foto o PDF de tu receta médica* como archivo adjunto."),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::LocalStorage;
+    use crate::whatsapp::messenger::RecordingMessenger;
+    use uuid::Uuid;
+
+    fn texto(t: &str) -> UserInput {
+        UserInput::Texto(t.to_string())
+    }
+
+    #[test]
+    fn nombres_con_acentos_y_compuestos() {
+        assert_eq!(nombre_valido(&texto("  José Luis ")), Some("José Luis"));
+        assert_eq!(nombre_valido(&texto("Núñez-O'Brien")), Some("Núñez-O'Brien"));
+        assert_eq!(nombre_valido(&texto("Ma. Fernanda")), Some("Ma. Fernanda"));
+    }
+
+    #[test]
+    fn nombres_vacios_o_no_texto_se_rechazan() {
+        assert_eq!(nombre_valido(&texto("")), None);
+        assert_eq!(nombre_valido(&texto("   ")), None);
+        assert_eq!(nombre_valido(&texto("-")), None);
+        assert_eq!(nombre_valido(&texto("Luis123")), None);
+        assert_eq!(nombre_valido(&texto("👍")), None);
+        assert_eq!(nombre_valido(&UserInput::Accion(Accion::Genero("M".to_string()))), None);
+    }
+
+    /// Respuesta de `EsperandoNombre` sin base: el paso no consulta la base al validar
+    async fn responder_nombre(texto: &str) -> Transicion {
+        let pool = PgPool::connect_lazy("postgres://localhost/sin_base").unwrap();
+        let messenger = RecordingMessenger::new();
+        let storage = LocalStorage::new(std::env::temp_dir());
+        let ctx = Contexto {
+            pool: &pool,
+            messenger: &messenger,
+            storage: &storage,
+            telefono: "5215500000000",
+            user_id: Uuid::nil(),
+            patient_id: Uuid::nil(),
+            sesion: SessionContext::default(),
+        };
+        EsperandoNombreHandler.manejar(&ctx, &UserInput::Texto(texto.to_string())).await
+    }
+
+    #[tokio::test]
+    async fn esperando_nombre_rechaza_lo_que_no_es_un_nombre() {
+        for texto in ["a".repeat(61), "Luis123".to_string()] {
+            let transicion = responder_nombre(&texto).await;
+            assert_eq!(transicion.siguiente, UserState::EsperandoNombre, "{}", texto);
+            assert!(transicion.contexto.is_none(), "{}", texto);
+        }
+
+        let transicion = responder_nombre("  Ana Sofía ").await;
+        assert_eq!(transicion.siguiente, UserState::ConfirmandoNombre);
+        assert_eq!(transicion.contexto.and_then(|c| c.nombre_pendiente).as_deref(), Some("Ana Sofía"));
+    }
+
+    #[test]
+    fn curp_se_normaliza_y_valida() {
+        assert_eq!(curp_valido(" pegl900101hdfrrs09 ").as_deref(), Some("PEGL900101HDFRRS09"));
+        assert_eq!(curp_valido("PEGL900101HDFRRS0"), None);
+        assert_eq!(curp_valido("PEGL901301HDFRRS09"), None, "mes 13");
+        assert_eq!(curp_valido("PEGL900101ZDFRRS09"), None, "sexo inválido");
+        assert_eq!(curp_valido("PEGL900101HXXRRS09"), None, "entidad inexistente");
+        assert_eq!(curp_valido("123456789012345678"), None);
+    }
+
+    #[test]
+    fn genero_solo_acepta_las_opciones_ofrecidas() {
+        assert_eq!(genero_elegido(&UserInput::Accion(Accion::Genero("F".to_string()))), Some("F"));
+        assert_eq!(genero_elegido(&texto(" m ")), Some("M"));
+        assert_eq!(genero_elegido(&texto("otro")), None);
+        assert_eq!(genero_elegido(&UserInput::Accion(Accion::Genero("X".to_string()))), None);
+        assert_eq!(genero_elegido(&texto("")), None);
     }
 }
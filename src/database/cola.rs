@@ -0,0 +1,171 @@
+use sqlx::pool::PoolConnection;
+use sqlx::{PgPool, Postgres};
+
+/// Registra el `wamid` en la bitácora (`mensajes_procesados`) y guarda el mensaje en la cola durable
+/// en una sola transacción: o quedan las dos cosas o ninguna, así un fallo a la mitad no deja
+/// marcado como visto un mensaje que nunca se encoló.
+/// Devuelve el id en la cola, o `None` si el `wamid` ya estaba (reintento de Meta).
+pub async fn encolar_mensaje(pool: &PgPool, wamid: &str, telefono: &str, mensaje: serde_json::Value) -> Result<Option<i64>, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+
+    // Sin wamid no hay forma de reconocer el reintento: se encola tal cual
+    if !wamid.is_empty() {
+        let nuevo = sqlx::query(
+            "INSERT INTO mensajes_procesados (wamid, telefono) VALUES ($1, $2)
+             ON CONFLICT (wamid) DO NOTHING"
+        )
+        .bind(wamid)
+        .bind(telefono)
+        .execute(&mut *tx)
+        .await?
+        .rows_affected() == 1;
+
+        if !nuevo {
+            return Ok(None);
+        }
+    }
+
+    let id = sqlx::query_scalar::<sqlx::Postgres, i64>(
+        "INSERT INTO cola_mensajes (wamid, telefono, mensaje) VALUES ($1, $2, $3) RETURNING id"
+    )
+    .bind(wamid)
+    .bind(telefono)
+    .bind(mensaje)
+    .fetch_one(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+    Ok(Some(id))
+}
+
+/// Reclama un mensaje pendiente para procesarlo. Devuelve `None` si otro worker ya lo tomó
+/// o si todavía hay un mensaje anterior del mismo teléfono sin terminar: así cada
+/// conversación se atiende estrictamente en orden, aun con varias instancias del bot.
+pub async fn tomar_mensaje(pool: &PgPool, id: i64) -> Option<(String, serde_json::Value)> {
+    sqlx::query_as::<sqlx::Postgres, (String, serde_json::Value)>(
+        "UPDATE cola_mensajes
+         SET estado = 'procesando', intentos = intentos + 1, actualizado_en = now()
+         WHERE id = (
+             SELECT c.id FROM cola_mensajes c
+             WHERE c.id = $1 AND c.estado = 'pendiente'
+               AND NOT EXISTS (
+                   SELECT 1 FROM cola_mensajes previo
+                   WHERE previo.telefono = c.telefono
+                     AND previo.id < c.id
+                     AND previo.estado IN ('pendiente', 'procesando')
+               )
+             FOR UPDATE SKIP LOCKED
+         )
+         RETURNING telefono, mensaje"
+    )
+    .bind(id)
+    .fetch_optional(pool)
+    .await
+    .ok()
+    .flatten()
+}
+
+/// Siguiente mensaje pendiente de un teléfono, para atenderlo en cuanto termina el anterior.
+pub async fn siguiente_pendiente_del_telefono(pool: &PgPool, telefono: &str) -> Option<i64> {
+    sqlx::query_scalar::<sqlx::Postgres, i64>(
+        "SELECT id FROM cola_mensajes WHERE telefono = $1 AND estado = 'pendiente' ORDER BY id LIMIT 1"
+    )
+    .bind(telefono)
+    .fetch_optional(pool)
+    .await
+    .ok()
+    .flatten()
+}
+
+/// Toma un advisory lock de Postgres sobre el teléfono. Mientras la conexión devuelta
+/// tenga el lock, ninguna otra instancia puede procesar mensajes de esa conversación.
+pub async fn bloquear_telefono(pool: &PgPool, telefono: &str) -> Option<PoolConnection<Postgres>> {
+    let mut conn = pool.acquire().await.ok()?;
+    sqlx::query("SELECT pg_advisory_lock(hashtextextended($1, 0))")
+        .bind(telefono)
+        .execute(&mut *conn)
+        .await
+        .ok()?;
+    Some(conn)
+}
+
+/// Libera el lock del teléfono. Si no se puede liberar, la conexión se cierra
+/// (cerrar la sesión suelta el lock) en lugar de devolverla al pool con el lock tomado.
+pub async fn desbloquear_telefono(mut conn: PoolConnection<Postgres>, telefono: &str) {
+    let liberado = sqlx::query_scalar::<sqlx::Postgres, bool>("SELECT pg_advisory_unlock(hashtextextended($1, 0))")
+        .bind(telefono)
+        .fetch_one(&mut *conn)
+        .await
+        .unwrap_or(false);
+
+    if !liberado {
+        drop(conn.detach());
+    }
+}
+
+pub async fn marcar_procesado(pool: &PgPool, id: i64) {
+    let _ = sqlx::query("UPDATE cola_mensajes SET estado = 'hecho', actualizado_en = now() WHERE id = $1")
+        .bind(id)
+        .execute(pool)
+        .await;
+}
+
+/// Registra el fallo. Si aún quedan intentos el mensaje vuelve a `pendiente`, si no queda en `error`.
+pub async fn marcar_error(pool: &PgPool, id: i64, error: &str, max_intentos: i32) {
+    let _ = sqlx::query(
+        "UPDATE cola_mensajes
+         SET estado = CASE WHEN intentos >= $3 THEN 'error' ELSE 'pendiente' END,
+             ultimo_error = $2,
+             actualizado_en = now()
+         WHERE id = $1"
+    )
+    .bind(id)
+    .bind(error)
+    .bind(max_intentos)
+    .execute(pool)
+    .await;
+}
+
+/// Recupera los mensajes que se quedaron en `procesando` más de `segundos` (el worker que los tenía
+/// murió o la instancia se reinició). Vuelven a `pendiente`, salvo los que ya gastaron sus
+/// `max_intentos`: esos quedan en `error` para que un mensaje que tumba al worker no se repita sin fin.
+/// Devuelve cuántos se devolvieron a la cola y cuántos se dieron por fallidos.
+pub async fn recuperar_mensajes_atascados(pool: &PgPool, segundos: i64, max_intentos: i32) -> (u64, u64) {
+    let estados = sqlx::query_scalar::<sqlx::Postgres, String>(
+        "UPDATE cola_mensajes
+         SET estado = CASE WHEN intentos >= $2 THEN 'error' ELSE 'pendiente' END,
+             ultimo_error = CASE WHEN intentos >= $2 THEN 'Se agotaron los intentos sin terminar de procesarlo' ELSE ultimo_error END,
+             actualizado_en = now()
+         WHERE estado = 'procesando' AND actualizado_en < now() - make_interval(secs => $1::double precision)
+         RETURNING estado"
+    )
+    .bind(segundos)
+    .bind(max_intentos)
+    .fetch_all(pool)
+    .await
+    .unwrap_or_default();
+
+    let fallidos = estados.iter().filter(|e| *e == "error").count() as u64;
+    (estados.len() as u64 - fallidos, fallidos)
+}
+
+/// Ids de los mensajes pendientes, en orden de llegada.
+pub async fn obtener_pendientes(pool: &PgPool, limite: i64) -> Vec<i64> {
+    sqlx::query_scalar::<sqlx::Postgres, i64>(
+        "SELECT id FROM cola_mensajes WHERE estado = 'pendiente' ORDER BY id LIMIT $1"
+    )
+    .bind(limite)
+    .fetch_all(pool)
+    .await
+    .unwrap_or_default()
+}
+
+/// Borra los mensajes ya procesados con más de `horas` de antigüedad.
+pub async fn limpiar_cola(pool: &PgPool, horas: i64) -> u64 {
+    sqlx::query("DELETE FROM cola_mensajes WHERE estado = 'hecho' AND actualizado_en < now() - make_interval(hours => $1::int)")
+        .bind(horas)
+        .execute(pool)
+        .await
+        .map(|r| r.rows_affected())
+        .unwrap_or(0)
+}
@@ -2,7 +2,7 @@ use sqlx::PgPool;
 use crate::bot_logic::models::LabTest;
 
 pub async fn obtener_nombres_estudios(pool: &PgPool) -> Vec<String> {
-    sqlx::query!("SELECT test_name FROM lab_tests LIMIT 10")
+    sqlx::query!("SELECT test_name FROM lab_tests ORDER BY test_name")
         .fetch_all(pool).await
         .map(|rows| rows.into_iter().map(|r| r.test_name).collect())
         .unwrap_or_default()
@@ -25,3 +25,41 @@ pub async fn obtener_detalle_estudio(pool: &PgPool, nombre: &str) -> Option<LabT
         category: None,
     })
 }
+
+/// Reclama los resultados publicados de los que el paciente no ha sido avisado (igual que los
+/// pedidos listos: se marcan antes de mandar el aviso).
+/// Devuelve (id, teléfono, nombre, estudio, fecha de la toma).
+pub async fn reclamar_avisos_resultados(pool: &PgPool) -> Vec<(i64, String, String, String, String)> {
+    sqlx::query_as::<sqlx::Postgres, (i64, String, String, String, String)>(
+        "WITH reclamados AS (
+             UPDATE resultados_laboratorio SET avisado_en = now()
+             WHERE id IN (
+                 SELECT id FROM resultados_laboratorio
+                 WHERE avisado_en IS NULL
+                 ORDER BY id
+                 LIMIT 50
+                 FOR UPDATE SKIP LOCKED
+             )
+             RETURNING id, patient_id, test_id, tomado_en
+         )
+         SELECT r.id, p.whatsapp_number, COALESCE(u.first_name, ''), t.test_name, to_char(r.tomado_en, 'DD/MM/YYYY')
+         FROM reclamados r
+         JOIN patients p ON p.patient_id = r.patient_id
+         LEFT JOIN users u ON u.user_id = p.user_id
+         JOIN lab_tests t ON t.test_id = r.test_id"
+    )
+    .fetch_all(pool)
+    .await
+    .unwrap_or_else(|e| {
+        eprintln!("❌ No se pudieron reclamar los avisos de resultados: {}", e);
+        Vec::new()
+    })
+}
+
+/// El aviso no salió: se vuelve a intentar en la siguiente vuelta
+pub async fn liberar_aviso_resultados(pool: &PgPool, id: i64) {
+    let _ = sqlx::query("UPDATE resultados_laboratorio SET avisado_en = NULL WHERE id = $1")
+        .bind(id)
+        .execute(pool)
+        .await;
+}
@@ -0,0 +1,136 @@
+use sqlx::PgPool;
+use crate::bot_logic::models::MensajeFallido;
+
+/// Guarda la hora del último mensaje del paciente (la más reciente, aunque lleguen desordenados)
+pub async fn registrar_ultimo_entrante(pool: &PgPool, telefono: &str, timestamp: Option<i64>) {
+    let _ = sqlx::query(
+        "INSERT INTO ventana_conversacion (telefono, ultimo_entrante) VALUES ($1, COALESCE(to_timestamp($2), now()))
+         ON CONFLICT (telefono) DO UPDATE
+         SET ultimo_entrante = GREATEST(ventana_conversacion.ultimo_entrante, EXCLUDED.ultimo_entrante)"
+    )
+    .bind(telefono)
+    .bind(timestamp.map(|t| t as f64))
+    .execute(pool)
+    .await;
+}
+
+/// ¿El paciente nos escribió en las últimas 24 horas? (se dejan unos minutos de margen)
+pub async fn dentro_de_ventana(pool: &PgPool, telefono: &str) -> bool {
+    sqlx::query_scalar::<sqlx::Postgres, bool>(
+        "SELECT ultimo_entrante > now() - interval '24 hours' + interval '5 minutes'
+         FROM ventana_conversacion WHERE telefono = $1"
+    )
+    .bind(telefono)
+    .fetch_optional(pool)
+    .await
+    .ok()
+    .flatten()
+    .unwrap_or(false)
+}
+
+/// Borra de la bitácora los mensajes más viejos que `horas` (Meta deja de reintentar después de unos días).
+pub async fn limpiar_mensajes_procesados(pool: &PgPool, horas: i64) -> u64 {
+    sqlx::query("DELETE FROM mensajes_procesados WHERE recibido_en < now() - make_interval(hours => $1::int)")
+        .bind(horas)
+        .execute(pool)
+        .await
+        .map(|r| r.rows_affected())
+        .unwrap_or(0)
+}
+
+/// Guarda el `wamid` de un mensaje que el bot acaba de enviar para poder seguir su entrega.
+pub async fn registrar_mensaje_saliente(pool: &PgPool, wamid: &str, telefono: &str, tipo: &str, critico: bool) {
+    let _ = sqlx::query(
+        "INSERT INTO mensajes_salientes (wamid, telefono, tipo, critico) VALUES ($1, $2, $3, $4)
+         ON CONFLICT (wamid) DO NOTHING"
+    )
+    .bind(wamid)
+    .bind(telefono)
+    .bind(tipo)
+    .bind(critico)
+    .execute(pool)
+    .await;
+}
+
+/// Orden de los estados de entrega. Los callbacks pueden llegar desordenados
+/// (un `read` antes que el `delivered`), así que nunca retrocedemos de estado.
+fn rango_estado(estado: &str) -> i16 {
+    match estado {
+        "sent" => 1,
+        "delivered" => 2,
+        "read" => 3,
+        "failed" => 4,
+        _ => 0,
+    }
+}
+
+/// Registra un callback de estado. Devuelve `true` si el mensaje era crítico y falló,
+/// en cuyo caso queda marcado para seguimiento.
+pub async fn registrar_estado_mensaje(
+    pool: &PgPool,
+    wamid: &str,
+    estado: &str,
+    timestamp: i64,
+    error_code: Option<i64>,
+    error_detalle: Option<&str>,
+) -> bool {
+    let _ = sqlx::query(
+        "INSERT INTO mensajes_salientes_eventos (wamid, estado, error_code, error_detalle, ocurrido_en)
+         VALUES ($1, $2, $3, $4, to_timestamp($5))"
+    )
+    .bind(wamid)
+    .bind(estado)
+    .bind(error_code)
+    .bind(error_detalle)
+    .bind(timestamp as f64)
+    .execute(pool)
+    .await;
+
+    let critico_fallido = sqlx::query_scalar::<sqlx::Postgres, bool>(
+        "UPDATE mensajes_salientes
+         SET estado = $2,
+             rango_estado = $3,
+             error_code = COALESCE($4, error_code),
+             error_detalle = COALESCE($5, error_detalle),
+             requiere_seguimiento = requiere_seguimiento OR (critico AND $2 = 'failed'),
+             actualizado_en = now()
+         WHERE wamid = $1 AND rango_estado <= $3
+         RETURNING critico AND $2 = 'failed'"
+    )
+    .bind(wamid)
+    .bind(estado)
+    .bind(rango_estado(estado))
+    .bind(error_code)
+    .bind(error_detalle)
+    .fetch_optional(pool)
+    .await;
+
+    matches!(critico_fallido, Ok(Some(true)))
+}
+
+/// Mensajes salientes que fallaron en las últimas `horas`, los más recientes primero.
+pub async fn obtener_mensajes_fallidos(pool: &PgPool, horas: i64) -> Vec<MensajeFallido> {
+    sqlx::query_as::<sqlx::Postgres, (String, String, String, bool, bool, Option<i64>, Option<String>, i64)>(
+        "SELECT wamid, telefono, tipo, critico, requiere_seguimiento, error_code, error_detalle,
+                EXTRACT(EPOCH FROM actualizado_en)::bigint
+         FROM mensajes_salientes
+         WHERE estado = 'failed' AND actualizado_en >= now() - make_interval(hours => $1::int)
+         ORDER BY actualizado_en DESC"
+    )
+    .bind(horas)
+    .fetch_all(pool)
+    .await
+    .unwrap_or_default()
+    .into_iter()
+    .map(|(wamid, telefono, tipo, critico, requiere_seguimiento, error_code, error_detalle, fallido_en)| MensajeFallido {
+        wamid,
+        telefono,
+        tipo,
+        critico,
+        requiere_seguimiento,
+        error_code,
+        error_detalle,
+        fallido_en,
+    })
+    .collect()
+}
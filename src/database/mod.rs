@@ -2,21 +2,39 @@
 pub mod users;
 pub mod pharmacy;
 pub mod lab;
+pub mod mensajes;
+pub mod cola;
 
 // Re-exportar funciones de users
 pub use users::{
-    obtener_estado, cambiar_estado, obtener_usuario_por_telefono,
+    obtener_sesion, guardar_sesion, registrar_actividad, registrar_solicitud_agente, contar_estados_sesiones, renombrar_estado_sesiones, obtener_usuario_por_telefono,
     obtener_patient_id_por_telefono, registrar_paciente_completo, actualizar_datos_usuario,
-    actualizar_email_usuario, guardar_direccion_paciente,
+    actualizar_email_usuario, actualizar_nombre_usuario, actualizar_primer_nombre, actualizar_apellido_paterno, actualizar_apellido_materno,
+    actualizar_curp, actualizar_genero, guardar_direccion_paciente, guardar_ubicacion_paciente, guardar_receta_orden,
+    obtener_direccion_predeterminada, asignar_direccion_orden,
 };
 
 // Re-exportar tipos y funciones de pharmacy
 pub use pharmacy::{
     obtener_categorias, buscar_productos_categoria, obtener_productos_nombres_y_ids,
-    obtener_detalle_med_por_nombre, agregar_al_carrito, obtener_o_crear_orden,
-    buscar_medicamentos_similares,
+    obtener_detalle_med_por_id, agregar_al_carrito, cambiar_cantidad_carrito, quitar_del_carrito, obtener_carrito, obtener_o_crear_orden, obtener_orden_pendiente, vaciar_carrito, confirmar_orden, liberar_carritos_vencidos,
+    reclamar_avisos_pedido_listo, liberar_aviso_pedido_listo, reclamar_alertas_inventario, liberar_alerta_inventario,
+    buscar_medicamentos_similares, cargar_existencias,
     obtener_resumen_carrito,
 };
 
 // Re-exportar funciones de lab
-pub use lab::{obtener_nombres_estudios, obtener_detalle_estudio};
+pub use lab::{obtener_nombres_estudios, obtener_detalle_estudio, reclamar_avisos_resultados, liberar_aviso_resultados};
+
+// Re-exportar funciones de mensajes
+pub use mensajes::{
+    limpiar_mensajes_procesados, registrar_ultimo_entrante, dentro_de_ventana,
+    registrar_mensaje_saliente, registrar_estado_mensaje, obtener_mensajes_fallidos,
+};
+
+// Re-exportar funciones de la cola de mensajes
+pub use cola::{
+    encolar_mensaje, tomar_mensaje, siguiente_pendiente_del_telefono, marcar_procesado, marcar_error,
+    bloquear_telefono, desbloquear_telefono,
+    recuperar_mensajes_atascados, obtener_pendientes, limpiar_cola,
+};
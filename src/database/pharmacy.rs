@@ -1,34 +1,36 @@
-use sqlx::PgPool;
+use sqlx::{PgPool, Postgres, Transaction};
 use rust_decimal::Decimal;
 use uuid::Uuid;
-use crate::bot_logic::models::Medication;
+use crate::bot_logic::models::{Cart, CartItem, Confirmacion, Medication};
 
 pub async fn obtener_categorias(pool: &PgPool) -> Vec<String> {
-    sqlx::query!("SELECT DISTINCT category::text as cat FROM medications WHERE category IS NOT NULL")
+    sqlx::query!("SELECT DISTINCT category::text as cat FROM medications WHERE category IS NOT NULL ORDER BY cat")
         .fetch_all(pool).await
         .map(|rows| rows.into_iter().filter_map(|r| r.cat).collect())
         .unwrap_or_default()
 }
 
-#[allow(dead_code)]
+/// Detalle de los productos de una categoría, en el mismo orden que `obtener_productos_nombres_y_ids`
+/// para que el texto y la lista interactiva pagina por pagina muestren los mismos productos.
 pub async fn buscar_productos_categoria(pool: &PgPool, categoria: &str) -> Vec<(String, String, Option<String>, Decimal)> {
     sqlx::query_as::<sqlx::Postgres, (String, String, Option<String>, Decimal)>(
         "SELECT brand_name, active_compound, presentation, price FROM medications 
-         WHERE category::text = $1 AND stock = true LIMIT 10"
+         WHERE category::text = $1 AND existencias > reservadas ORDER BY brand_name"
     ).bind(categoria).fetch_all(pool).await.unwrap_or_default()
 }
 
-pub async fn obtener_productos_nombres_y_ids(pool: &PgPool, categoria: &str) -> Vec<String> {
-    sqlx::query!("SELECT brand_name FROM medications WHERE category::text = $1 AND stock = true", categoria)
+pub async fn obtener_productos_nombres_y_ids(pool: &PgPool, categoria: &str) -> Vec<(Uuid, String)> {
+    sqlx::query!("SELECT med_id, brand_name FROM medications WHERE category::text = $1 AND existencias > reservadas ORDER BY brand_name", categoria)
         .fetch_all(pool).await
-        .map(|rows| rows.into_iter().map(|r| r.brand_name).collect())
+        .map(|rows| rows.into_iter().map(|r| (r.med_id, r.brand_name)).collect())
         .unwrap_or_default()
 }
 
-pub async fn obtener_detalle_med_por_nombre(pool: &PgPool, nombre: &str) -> Option<Medication> {
+pub async fn obtener_detalle_med_por_id(pool: &PgPool, med_id: Uuid) -> Option<Medication> {
     sqlx::query!(
-        "SELECT med_id, brand_name, active_compound, presentation, price, stock FROM medications WHERE brand_name = $1 LIMIT 1",
-        nombre
+        r#"SELECT med_id, brand_name, active_compound, presentation, price, existencias - reservadas AS "disponibles!", max_por_pedido
+           FROM medications WHERE med_id = $1"#,
+        med_id
     )
     .fetch_optional(pool)
     .await
@@ -41,21 +43,153 @@ pub async fn obtener_detalle_med_por_nombre(pool: &PgPool, nombre: &str) -> Opti
         presentation: row.presentation,
         price: row.price,
         category: None,
-        stock: row.stock.unwrap_or(false),
+        disponibles: row.disponibles,
+        max_por_pedido: row.max_por_pedido,
     })
 }
 
-pub async fn agregar_al_carrito(pool: &PgPool, order_id: Uuid, med_id: Uuid, precio: Decimal) {
-    let _ = sqlx::query!(
-        "INSERT INTO medication_items (order_id, med_id, quantity, unit_price) 
-         VALUES ($1, $2, 1, $3)",
-        order_id, med_id, precio
-    ).execute(pool).await;
+/// Agrega piezas del medicamento: si ya estaba en el carrito, se suman a su renglón.
+/// Las piezas quedan reservadas en la misma transacción. Devuelve la cantidad que quedó en el
+/// carrito, o 0 si no alcanzan las existencias o hubo un error.
+pub async fn agregar_al_carrito(pool: &PgPool, order_id: Uuid, med_id: Uuid, precio: Decimal, cantidad: i32) -> i32 {
+    let resultado: Result<i32, sqlx::Error> = async {
+        let mut tx = pool.begin().await?;
+        let en_carrito = sqlx::query_scalar::<sqlx::Postgres, i32>(
+            "INSERT INTO medication_items (order_id, med_id, quantity, unit_price)
+             VALUES ($1, $2, $4, $3)
+             ON CONFLICT (order_id, med_id) DO UPDATE SET quantity = medication_items.quantity + EXCLUDED.quantity
+             RETURNING quantity"
+        )
+        .bind(order_id)
+        .bind(med_id)
+        .bind(precio)
+        .bind(cantidad)
+        .fetch_one(&mut *tx)
+        .await?;
 
-    let _ = sqlx::query!(
-        "UPDATE orders SET total_amount = total_amount + $1 WHERE order_id = $2",
-        precio, order_id
-    ).execute(pool).await;
+        // Sin commit la transacción se descarta y el renglón queda como estaba
+        if !reservar(&mut tx, med_id, cantidad).await? {
+            println!("⚠️ Sin existencias para agregar {} de {} al carrito {}", cantidad, med_id, order_id);
+            return Ok(0);
+        }
+        recalcular_total(&mut tx, order_id).await?;
+        tx.commit().await?;
+        Ok(en_carrito)
+    }.await;
+
+    resultado.unwrap_or_else(|e| {
+        eprintln!("❌ No se pudo agregar {} al carrito {}: {}", med_id, order_id, e);
+        0
+    })
+}
+
+/// Suma (o resta, con `delta` negativo) piezas de un renglón; si llega a cero se quita.
+/// Devuelve la cantidad que quedó (la misma si no alcanzan las existencias),
+/// `None` si el medicamento no estaba en el carrito.
+pub async fn cambiar_cantidad_carrito(pool: &PgPool, order_id: Uuid, med_id: Uuid, delta: i32) -> Option<i32> {
+    let resultado: Result<Option<i32>, sqlx::Error> = async {
+        let mut tx = pool.begin().await?;
+        let actual = sqlx::query_scalar::<sqlx::Postgres, i32>(
+            "SELECT quantity FROM medication_items WHERE order_id = $1 AND med_id = $2 FOR UPDATE"
+        )
+        .bind(order_id)
+        .bind(med_id)
+        .fetch_optional(&mut *tx)
+        .await?;
+        let Some(actual) = actual else { return Ok(None) };
+
+        let cantidad = (actual + delta).max(0);
+        if !reservar(&mut tx, med_id, cantidad - actual).await? {
+            println!("⚠️ Sin existencias para sumar {} de {} al carrito {}", delta, med_id, order_id);
+            return Ok(Some(actual));
+        }
+
+        if cantidad == 0 {
+            sqlx::query("DELETE FROM medication_items WHERE order_id = $1 AND med_id = $2")
+                .bind(order_id)
+                .bind(med_id)
+                .execute(&mut *tx)
+                .await?;
+        } else {
+            sqlx::query("UPDATE medication_items SET quantity = $3 WHERE order_id = $1 AND med_id = $2")
+                .bind(order_id)
+                .bind(med_id)
+                .bind(cantidad)
+                .execute(&mut *tx)
+                .await?;
+        }
+        recalcular_total(&mut tx, order_id).await?;
+        tx.commit().await?;
+        Ok(Some(cantidad))
+    }.await;
+
+    resultado.unwrap_or_else(|e| {
+        eprintln!("❌ No se pudo cambiar la cantidad de {} en el carrito {}: {}", med_id, order_id, e);
+        None
+    })
+}
+
+/// Quita el renglón completo de un medicamento y libera sus piezas
+pub async fn quitar_del_carrito(pool: &PgPool, order_id: Uuid, med_id: Uuid) {
+    let resultado: Result<(), sqlx::Error> = async {
+        let mut tx = pool.begin().await?;
+        quitar_renglones(&mut tx, order_id, Some(med_id)).await?;
+        recalcular_total(&mut tx, order_id).await?;
+        tx.commit().await
+    }.await;
+
+    if let Err(e) = resultado {
+        eprintln!("❌ No se pudo quitar {} del carrito {}: {}", med_id, order_id, e);
+    }
+}
+
+/// Reserva (o libera, con `piezas` negativo) existencias del medicamento.
+/// El `UPDATE` bloquea el renglón, así que dos carritos no pueden llevarse la última pieza.
+/// Devuelve `false` si no hay suficientes libres.
+async fn reservar(tx: &mut Transaction<'_, Postgres>, med_id: Uuid, piezas: i32) -> Result<bool, sqlx::Error> {
+    let resultado = sqlx::query(
+        "UPDATE medications SET reservadas = GREATEST(reservadas + $2, 0)
+         WHERE med_id = $1 AND existencias - reservadas >= $2"
+    )
+    .bind(med_id)
+    .bind(piezas)
+    .execute(&mut **tx)
+    .await?;
+    Ok(resultado.rows_affected() == 1)
+}
+
+/// Borra los renglones de la orden (o solo el del medicamento) y libera sus piezas en la misma sentencia
+async fn quitar_renglones(tx: &mut Transaction<'_, Postgres>, order_id: Uuid, med_id: Option<Uuid>) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "WITH quitados AS (
+             DELETE FROM medication_items
+             WHERE order_id = $1 AND ($2::uuid IS NULL OR med_id = $2)
+             RETURNING med_id, quantity
+         )
+         UPDATE medications m SET reservadas = GREATEST(m.reservadas - q.quantity, 0)
+         FROM quitados q
+         WHERE m.med_id = q.med_id"
+    )
+    .bind(order_id)
+    .bind(med_id)
+    .execute(&mut **tx)
+    .await?;
+    Ok(())
+}
+
+/// El total de la orden siempre sale de sus renglones, dentro de la misma transacción que los cambia.
+/// También marca la hora del cambio para saber cuándo vence el carrito.
+async fn recalcular_total(tx: &mut Transaction<'_, Postgres>, order_id: Uuid) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "UPDATE orders SET total_amount = COALESCE((
+             SELECT SUM(quantity * unit_price) FROM medication_items WHERE order_id = $1
+         ), 0), carrito_actualizado_en = now()
+         WHERE order_id = $1"
+    )
+    .bind(order_id)
+    .execute(&mut **tx)
+    .await?;
+    Ok(())
 }
 
 pub async fn obtener_o_crear_orden(pool: &PgPool, patient_id: Uuid) -> Uuid {
@@ -79,6 +213,269 @@ pub async fn obtener_o_crear_orden(pool: &PgPool, patient_id: Uuid) -> Uuid {
     }
 }
 
+/// Orden pendiente del paciente, sin crear una nueva si no tiene
+pub async fn obtener_orden_pendiente(pool: &PgPool, patient_id: Uuid) -> Option<Uuid> {
+    sqlx::query_scalar::<sqlx::Postgres, Uuid>(
+        "SELECT order_id FROM orders WHERE patient_id = $1 AND p_status = 'pendiente' LIMIT 1"
+    )
+    .bind(patient_id)
+    .fetch_optional(pool)
+    .await
+    .ok()
+    .flatten()
+}
+
+/// Quita todos los productos de la orden, libera sus piezas y deja el total en cero (la orden se reutiliza)
+pub async fn vaciar_carrito(pool: &PgPool, order_id: Uuid) {
+    let resultado: Result<(), sqlx::Error> = async {
+        let mut tx = pool.begin().await?;
+        quitar_renglones(&mut tx, order_id, None).await?;
+        recalcular_total(&mut tx, order_id).await?;
+        tx.commit().await
+    }.await;
+
+    if let Err(e) = resultado {
+        eprintln!("❌ No se pudo vaciar el carrito {}: {}", order_id, e);
+    }
+}
+
+/// Cierra la orden pendiente con el método de pago elegido y descuenta sus piezas de las existencias.
+/// Antes, en la misma transacción, revisa que tenga renglones, que sus piezas sigan apartadas y que
+/// el total coincida: el carrito pudo vencer o cambiar mientras el paciente elegía cómo pagar.
+pub async fn confirmar_orden(pool: &PgPool, order_id: Uuid, metodo_pago: &str) -> Confirmacion {
+    let resultado: Result<Confirmacion, sqlx::Error> = async {
+        let mut tx = pool.begin().await?;
+        let total_guardado = sqlx::query_scalar::<sqlx::Postgres, Decimal>(
+            "SELECT total_amount FROM orders WHERE order_id = $1 AND p_status = 'pendiente' FOR UPDATE"
+        )
+        .bind(order_id)
+        .fetch_optional(&mut *tx)
+        .await?;
+        let Some(total_guardado) = total_guardado else { return Ok(Confirmacion::NoConfirmada) };
+
+        // (medicamento, piezas, existencias, reservadas, subtotal)
+        let renglones = sqlx::query_as::<sqlx::Postgres, (String, i32, i32, i32, Decimal)>(
+            "SELECT m.brand_name, mi.quantity, m.existencias, m.reservadas, mi.quantity * mi.unit_price
+             FROM medication_items mi
+             JOIN medications m ON m.med_id = mi.med_id
+             WHERE mi.order_id = $1
+             ORDER BY m.brand_name
+             FOR UPDATE OF m"
+        )
+        .bind(order_id)
+        .fetch_all(&mut *tx)
+        .await?;
+        if renglones.is_empty() {
+            return Ok(Confirmacion::CarritoVacio);
+        }
+
+        let sin_existencias: Vec<String> = renglones.iter()
+            .filter(|(_, piezas, existencias, reservadas, _)| piezas > existencias || piezas > reservadas)
+            .map(|(nombre, ..)| nombre.clone())
+            .collect();
+        if !sin_existencias.is_empty() {
+            return Ok(Confirmacion::SinExistencias(sin_existencias));
+        }
+
+        let total: Decimal = renglones.iter().map(|(.., subtotal)| *subtotal).sum();
+        if total != total_guardado {
+            recalcular_total(&mut tx, order_id).await?;
+            tx.commit().await?;
+            return Ok(Confirmacion::TotalCambio(total));
+        }
+
+        let (numero, total, direccion) = sqlx::query_as::<sqlx::Postgres, (i64, Decimal, String)>(
+            "WITH confirmada AS (
+                 UPDATE orders SET p_method = $2, p_status = 'confirmada', confirmada_en = now()
+                 WHERE order_id = $1
+                 RETURNING order_id, numero_pedido, total_amount
+             )
+             SELECT c.numero_pedido, c.total_amount, COALESCE(mo.delivery_address, '')
+             FROM confirmada c
+             LEFT JOIN medication_orders mo ON mo.order_id = c.order_id"
+        )
+        .bind(order_id)
+        .bind(metodo_pago)
+        .fetch_one(&mut *tx)
+        .await?;
+
+        // Las piezas ya estaban reservadas: se vuelven venta. Lo que quede en el mínimo o menos abre una
+        // alerta, que `reclamar_alertas_inventario` le hace llegar al equipo de compras.
+        sqlx::query(
+            "WITH vendidas AS (
+                 UPDATE medications m
+                 SET existencias = m.existencias - mi.quantity, reservadas = GREATEST(m.reservadas - mi.quantity, 0)
+                 FROM medication_items mi
+                 WHERE mi.order_id = $1 AND mi.med_id = m.med_id
+                 RETURNING m.med_id, m.existencias, m.stock_minimo
+             )
+             INSERT INTO alertas_inventario (med_id, existencias)
+             SELECT med_id, existencias FROM vendidas WHERE existencias <= stock_minimo
+             ON CONFLICT (med_id) WHERE atendida_en IS NULL DO UPDATE SET existencias = EXCLUDED.existencias"
+        )
+        .bind(order_id)
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+        Ok(Confirmacion::Confirmada(numero, total, direccion))
+    }.await;
+
+    resultado.unwrap_or_else(|e| {
+        eprintln!("❌ No se pudo confirmar la orden {}: {}", order_id, e);
+        Confirmacion::NoConfirmada
+    })
+}
+
+/// Vacía los carritos pendientes que no cambian desde hace `horas` y libera sus piezas.
+/// Bloquea primero las órdenes y luego los medicamentos, en el mismo orden que `confirmar_orden`,
+/// y se salta los carritos que se están confirmando en ese momento.
+/// Devuelve cuántos carritos se vaciaron.
+pub async fn liberar_carritos_vencidos(pool: &PgPool, horas: i64) -> u64 {
+    let resultado: Result<u64, sqlx::Error> = async {
+        let mut tx = pool.begin().await?;
+        let vencidos = sqlx::query_scalar::<sqlx::Postgres, Uuid>(
+            "SELECT order_id FROM orders
+             WHERE p_status = 'pendiente'
+               AND carrito_actualizado_en < now() - make_interval(hours => $1::int)
+               AND EXISTS (SELECT 1 FROM medication_items mi WHERE mi.order_id = orders.order_id)
+             FOR UPDATE SKIP LOCKED"
+        )
+        .bind(horas)
+        .fetch_all(&mut *tx)
+        .await?;
+        if vencidos.is_empty() {
+            return Ok(0);
+        }
+
+        sqlx::query(
+            "SELECT m.med_id FROM medications m
+             WHERE m.med_id IN (SELECT med_id FROM medication_items WHERE order_id = ANY($1))
+             ORDER BY m.brand_name
+             FOR UPDATE"
+        )
+        .bind(&vencidos)
+        .execute(&mut *tx)
+        .await?;
+
+        sqlx::query(
+            "WITH quitados AS (
+                 DELETE FROM medication_items WHERE order_id = ANY($1)
+                 RETURNING med_id, quantity
+             )
+             UPDATE medications m SET reservadas = GREATEST(m.reservadas - q.piezas, 0)
+             FROM (SELECT med_id, SUM(quantity) AS piezas FROM quitados GROUP BY med_id) q
+             WHERE m.med_id = q.med_id"
+        )
+        .bind(&vencidos)
+        .execute(&mut *tx)
+        .await?;
+
+        let vaciados = sqlx::query("UPDATE orders SET total_amount = 0 WHERE order_id = ANY($1)")
+            .bind(&vencidos)
+            .execute(&mut *tx)
+            .await?
+            .rows_affected();
+        tx.commit().await?;
+        Ok(vaciados)
+    }.await;
+
+    resultado.unwrap_or_else(|e| {
+        eprintln!("❌ No se pudieron liberar los carritos vencidos: {}", e);
+        0
+    })
+}
+
+/// Reclama los pedidos que la farmacia marcó como `listo` y cuyo paciente no ha sido avisado:
+/// quedan marcados de una vez para que otra instancia no mande el mismo aviso.
+/// Devuelve (order_id, teléfono, nombre, número de pedido, total).
+pub async fn reclamar_avisos_pedido_listo(pool: &PgPool) -> Vec<(Uuid, String, String, i64, Decimal)> {
+    sqlx::query_as::<sqlx::Postgres, (Uuid, String, String, i64, Decimal)>(
+        "WITH reclamados AS (
+             UPDATE orders SET aviso_listo_en = now()
+             WHERE order_id IN (
+                 SELECT order_id FROM orders
+                 WHERE p_status = 'listo' AND aviso_listo_en IS NULL
+                 LIMIT 50
+                 FOR UPDATE SKIP LOCKED
+             )
+             RETURNING order_id, patient_id, numero_pedido, total_amount
+         )
+         SELECT r.order_id, p.whatsapp_number, COALESCE(u.first_name, ''), r.numero_pedido, r.total_amount
+         FROM reclamados r
+         JOIN patients p ON p.patient_id = r.patient_id
+         LEFT JOIN users u ON u.user_id = p.user_id"
+    )
+    .fetch_all(pool)
+    .await
+    .unwrap_or_else(|e| {
+        eprintln!("❌ No se pudieron reclamar los avisos de pedido listo: {}", e);
+        Vec::new()
+    })
+}
+
+/// El aviso no salió: se vuelve a intentar en la siguiente vuelta
+pub async fn liberar_aviso_pedido_listo(pool: &PgPool, order_id: Uuid) {
+    let _ = sqlx::query("UPDATE orders SET aviso_listo_en = NULL WHERE order_id = $1")
+        .bind(order_id)
+        .execute(pool)
+        .await;
+}
+
+/// Alertas de inventario que todavía no se avisan, marcadas como avisadas en el mismo paso.
+/// Devuelve (id, medicamento, existencias, mínimo).
+pub async fn reclamar_alertas_inventario(pool: &PgPool) -> Vec<(i64, String, i32, i32)> {
+    sqlx::query_as::<sqlx::Postgres, (i64, String, i32, i32)>(
+        "WITH reclamadas AS (
+             UPDATE alertas_inventario SET avisada_en = now()
+             WHERE id IN (
+                 SELECT id FROM alertas_inventario
+                 WHERE avisada_en IS NULL AND atendida_en IS NULL
+                 LIMIT 50
+                 FOR UPDATE SKIP LOCKED
+             )
+             RETURNING id, med_id, existencias
+         )
+         SELECT r.id, m.brand_name, r.existencias, m.stock_minimo
+         FROM reclamadas r
+         JOIN medications m ON m.med_id = r.med_id"
+    )
+    .fetch_all(pool)
+    .await
+    .unwrap_or_else(|e| {
+        eprintln!("❌ No se pudieron reclamar las alertas de inventario: {}", e);
+        Vec::new()
+    })
+}
+
+/// El aviso de la alerta no salió: se vuelve a intentar en la siguiente vuelta
+pub async fn liberar_alerta_inventario(pool: &PgPool, id: i64) {
+    let _ = sqlx::query("UPDATE alertas_inventario SET avisada_en = NULL WHERE id = $1")
+        .bind(id)
+        .execute(pool)
+        .await;
+}
+
+/// Carrito de la orden, un renglón por medicamento ordenado por nombre
+pub async fn obtener_carrito(pool: &PgPool, order_id: Uuid) -> Cart {
+    let renglones = sqlx::query_as::<sqlx::Postgres, (Uuid, String, i32, Decimal)>(
+        "SELECT mi.med_id, m.brand_name, mi.quantity, mi.unit_price
+         FROM medication_items mi
+         JOIN medications m ON mi.med_id = m.med_id
+         WHERE mi.order_id = $1
+         ORDER BY m.brand_name"
+    )
+    .bind(order_id)
+    .fetch_all(pool)
+    .await
+    .unwrap_or_default();
+
+    renglones.into_iter().fold(Cart::new(), |mut carrito, (med_id, nombre, cantidad, precio)| {
+        carrito.add_item(CartItem::new(med_id, nombre, cantidad, precio));
+        carrito
+    })
+}
+
 pub async fn obtener_resumen_carrito(pool: &PgPool, order_id: Uuid) -> Vec<(String, i32, Decimal)> {
     sqlx::query_as::<sqlx::Postgres, (String, i32, Decimal)>(
         "SELECT m.brand_name, mi.quantity, mi.unit_price 
@@ -93,16 +490,16 @@ pub async fn obtener_resumen_carrito(pool: &PgPool, order_id: Uuid) -> Vec<(Stri
 }
 
 /// Busca medicamentos por nombre de patente o compuesto activo.
-/// Devuelve hasta 10 resultados con (brand_name, active_compound, presentation, price).
-pub async fn buscar_medicamentos_similares(pool: &PgPool, query: &str) -> Vec<(String, String, Option<String>, Decimal)> {
+/// Devuelve hasta 30 resultados, que la lista pagina con "Ver más", con (med_id, brand_name, active_compound, presentation, price).
+pub async fn buscar_medicamentos_similares(pool: &PgPool, query: &str) -> Vec<(Uuid, String, String, Option<String>, Decimal)> {
     // Definimos un umbral de similitud (0.0 a 1.0). 0.3 es el estándar de Postgres.
     // Cuanto más bajo, más "tolerante" a errores, pero menos preciso.
     
-    sqlx::query_as::<sqlx::Postgres, (String, String, Option<String>, Decimal)>(
+    sqlx::query_as::<sqlx::Postgres, (Uuid, String, String, Option<String>, Decimal)>(
         r#"
-        SELECT brand_name, active_compound, presentation, price 
+        SELECT med_id, brand_name, active_compound, presentation, price 
         FROM medications 
-        WHERE stock = true 
+        WHERE existencias > reservadas
           AND (
                brand_name % $1 
                OR active_compound % $1 
@@ -111,11 +508,33 @@ pub async fn buscar_medicamentos_similares(pool: &PgPool, query: &str) -> Vec<(S
         ORDER BY 
             similarity(brand_name, $1) DESC, 
             brand_name ASC
-        LIMIT 10
+        LIMIT 30
         "#
     )
     .bind(query)
     .fetch_all(pool)
     .await
     .unwrap_or_default()
-}
\ No newline at end of file
+}
+/// Carga el conteo físico de inventario: `existencias` de cada medicamento queda con lo contado.
+/// Todo o nada: si algún medicamento no existe o tiene más piezas reservadas en carritos que las
+/// contadas, no se cambia nada y se devuelven esos ids.
+pub async fn cargar_existencias(pool: &PgPool, conteo: &[(Uuid, i32)]) -> Result<Vec<Uuid>, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    let mut rechazados = Vec::new();
+    for (med_id, existencias) in conteo {
+        let cambiados = sqlx::query("UPDATE medications SET existencias = $2 WHERE med_id = $1 AND reservadas <= $2")
+            .bind(med_id)
+            .bind(existencias)
+            .execute(&mut *tx)
+            .await?
+            .rows_affected();
+        if cambiados == 0 {
+            rechazados.push(*med_id);
+        }
+    }
+    if rechazados.is_empty() {
+        tx.commit().await?;
+    }
+    Ok(rechazados)
+}
@@ -1,6 +1,7 @@
 use sqlx::PgPool;
 use uuid::Uuid;
-use crate::bot_logic::models::{User, Patient};
+use crate::bot_logic::models::{User, Patient, SessionContext, UserSession};
+use crate::whatsapp::payload::LocationPayload;
 
 /// Contexto ligero para consultas relacionadas con un teléfono de usuario.
 /// Mantiene en cache el `user_id` para evitar consultas repetidas a la DB.
@@ -43,22 +44,104 @@ impl<'a> UserContext<'a> {
     }
 }
 
-pub async fn obtener_estado(pool: &PgPool, telefono: &str) -> String {
-    let resultado = sqlx::query_scalar!("SELECT fn_obtener_o_crear_estado($1)", telefono)
-        .fetch_one(pool)
-        .await;
+/// Estado, contexto y última actividad de la sesión, leídos en una sola consulta para que sean consistentes
+pub async fn obtener_sesion(pool: &PgPool, telefono: &str) -> UserSession {
+    let resultado = sqlx::query_as::<sqlx::Postgres, (Option<String>, Option<serde_json::Value>, i64)>(
+        "SELECT fn_obtener_o_crear_estado($1),
+                (SELECT contexto FROM sesiones_contexto WHERE telefono = $1),
+                EXTRACT(EPOCH FROM COALESCE(
+                    (SELECT ultima_actividad FROM sesiones_contexto WHERE telefono = $1), now()
+                ))::bigint"
+    )
+    .bind(telefono)
+    .fetch_one(pool)
+    .await;
 
-    match resultado {
-        Ok(Some(estado)) => estado,
-        _ => "INICIO".to_string(), // Si hay error o es NULL, vuelve al inicio
+    let (estado, contexto, ultima_actualizacion) = match resultado {
+        Ok((estado, contexto, ultima)) => (
+            estado.unwrap_or_else(|| "INICIO".to_string()),
+            // Un contexto que ya no se puede leer (cambió el formato) se descarta
+            contexto.and_then(|c| serde_json::from_value(c).ok()).unwrap_or_default(),
+            ultima,
+        ),
+        Err(_) => ("INICIO".to_string(), SessionContext::default(), 0), // Si hay error, vuelve al inicio
+    };
+
+    UserSession { telefono: telefono.to_string(), estado, ultima_actualizacion, contexto }
+}
+
+/// Marca que el paciente acaba de escribir (la expiración de la sesión cuenta desde aquí)
+pub async fn registrar_actividad(pool: &PgPool, telefono: &str) {
+    let _ = sqlx::query(
+        "INSERT INTO sesiones_contexto (telefono) VALUES ($1)
+         ON CONFLICT (telefono) DO UPDATE SET ultima_actividad = now()"
+    )
+    .bind(telefono)
+    .execute(pool)
+    .await;
+}
+
+/// Guarda el estado y el contexto de la sesión en la misma transacción
+pub async fn guardar_sesion(pool: &PgPool, telefono: &str, nuevo_estado: &str, contexto: &SessionContext) {
+    let contexto_json = serde_json::to_value(contexto).unwrap_or_default();
+
+    let resultado: Result<(), sqlx::Error> = async {
+        let mut tx = pool.begin().await?;
+        sqlx::query("SELECT fn_actualizar_estado_sesion($1, $2)")
+            .bind(telefono)
+            .bind(nuevo_estado)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query(
+            "INSERT INTO sesiones_contexto (telefono, contexto) VALUES ($1, $2)
+             ON CONFLICT (telefono) DO UPDATE SET contexto = EXCLUDED.contexto, actualizado_en = now()"
+        )
+        .bind(telefono)
+        .bind(contexto_json)
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await
+    }.await;
+
+    if let Err(e) = resultado {
+        eprintln!("❌ No se pudo guardar la sesión de {}: {}", telefono, e);
     }
 }
 
-pub async fn cambiar_estado(pool: &PgPool, telefono: &str, nuevo_estado: &str) {
-    // Solo llamamos a la función. No nos importa el resultado (VOID)
-    let _ = sqlx::query!("SELECT fn_actualizar_estado_sesion($1, $2)", telefono, nuevo_estado)
+/// Estados distintos guardados en `sesiones`, con cuántas sesiones hay en cada uno
+pub async fn contar_estados_sesiones(pool: &PgPool) -> Vec<(String, i64)> {
+    sqlx::query_as::<sqlx::Postgres, (String, i64)>(
+        "SELECT estado, COUNT(*) FROM sesiones GROUP BY estado ORDER BY estado"
+    )
+    .fetch_all(pool)
+    .await
+    .unwrap_or_default()
+}
+
+/// Pasa todas las sesiones de un estado viejo al nuevo. Devuelve cuántas cambiaron.
+pub async fn renombrar_estado_sesiones(pool: &PgPool, viejo: &str, nuevo: &str) -> u64 {
+    sqlx::query("UPDATE sesiones SET estado = $2 WHERE estado = $1")
+        .bind(viejo)
+        .bind(nuevo)
         .execute(pool)
-        .await;
+        .await
+        .map(|r| r.rows_affected())
+        .unwrap_or_default()
+}
+
+/// Deja registrada la petición de hablar con una persona.
+/// Devuelve `false` si el paciente ya tenía una solicitud abierta.
+pub async fn registrar_solicitud_agente(pool: &PgPool, telefono: &str, estado: &str) -> bool {
+    sqlx::query(
+        "INSERT INTO solicitudes_agente (telefono, estado) VALUES ($1, $2)
+         ON CONFLICT (telefono) WHERE atendida_en IS NULL DO NOTHING"
+    )
+    .bind(telefono)
+    .bind(estado)
+    .execute(pool)
+    .await
+    .map(|r| r.rows_affected() == 1)
+    .unwrap_or(false)
 }
 
 pub async fn obtener_usuario_por_telefono(pool: &PgPool, telefono: &str) -> Option<User> {
@@ -181,24 +264,52 @@ pub async fn actualizar_email_usuario(pool: &PgPool, user_id: Uuid, email: &str)
     ).execute(pool).await;
 }
 
-pub async fn actualizar_nombre_temporal(pool: &PgPool, user_id: Uuid, nombre: &str) {
+pub async fn actualizar_nombre_usuario(pool: &PgPool, user_id: Uuid, nombre: &str) {
     let _ = sqlx::query!(
         "UPDATE users SET first_name = $1 WHERE user_id = $2",
-        format!("TEMP-{}", nombre), user_id
+        nombre, user_id
     ).execute(pool).await;
 }
 
-pub async fn obtener_nombre_temporal(pool: &PgPool, user_id: Uuid) -> Option<String> {
-    let res = sqlx::query!(
-        "SELECT first_name FROM users WHERE user_id = $1",
-        user_id
-    )
-    .fetch_optional(pool)
-    .await
-    .ok()??;
+/// Primer nombre que el paciente escribió durante el registro del pedido
+pub async fn actualizar_primer_nombre(pool: &PgPool, user_id: Uuid, nombre: &str) {
+    let _ = sqlx::query("UPDATE users SET first_name = $1 WHERE user_id = $2")
+        .bind(nombre)
+        .bind(user_id)
+        .execute(pool)
+        .await;
+}
+
+pub async fn actualizar_apellido_paterno(pool: &PgPool, user_id: Uuid, apellido: &str) {
+    let _ = sqlx::query("UPDATE users SET paternal_last_name = $1 WHERE user_id = $2")
+        .bind(apellido)
+        .bind(user_id)
+        .execute(pool)
+        .await;
+}
+
+pub async fn actualizar_apellido_materno(pool: &PgPool, user_id: Uuid, apellido: &str) {
+    let _ = sqlx::query("UPDATE users SET maternal_last_name = $1 WHERE user_id = $2")
+        .bind(apellido)
+        .bind(user_id)
+        .execute(pool)
+        .await;
+}
 
-    // Si el nombre existe, le quitamos el "TEMP-" para mostrarlo bonito
-    res.first_name.map(|n| n.replace("TEMP-", ""))
+pub async fn actualizar_curp(pool: &PgPool, patient_id: Uuid, curp: &str) {
+    let _ = sqlx::query("UPDATE patients SET curp = $1 WHERE patient_id = $2")
+        .bind(curp)
+        .bind(patient_id)
+        .execute(pool)
+        .await;
+}
+
+pub async fn actualizar_genero(pool: &PgPool, patient_id: Uuid, genero: &str) {
+    let _ = sqlx::query("UPDATE patients SET gender = $1 WHERE patient_id = $2")
+        .bind(genero)
+        .bind(patient_id)
+        .execute(pool)
+        .await;
 }
 
 #[allow(dead_code)]
@@ -210,26 +321,126 @@ pub async fn actualizar_datos_clinicos(pool: &PgPool, patient_id: Uuid, curp: &s
 }
 
 pub async fn guardar_direccion_paciente(pool: &PgPool, patient_id: Uuid, direccion: &str) {
-    let _ = sqlx::query!(
-        "INSERT INTO patient_addresses (patient_id, address_label, full_address, is_default) 
-         VALUES ($1, 'WhatsApp Delivery', $2, true)
-         ON CONFLICT DO NOTHING",
-        patient_id, direccion
-    ).execute(pool).await;
+    guardar_direccion_predeterminada(pool, patient_id, direccion, None, None, None).await;
+    asignar_direccion_orden(pool, patient_id, direccion).await;
+}
 
-    let _ = sqlx::query!(
+/// Igual que `guardar_direccion_paciente`, pero con las coordenadas del pin que compartió el paciente
+pub async fn guardar_ubicacion_paciente(pool: &PgPool, patient_id: Uuid, ubicacion: &LocationPayload) {
+    let direccion = ubicacion.address.clone()
+        .or_else(|| ubicacion.name.clone())
+        .unwrap_or_else(|| format!("{:.6}, {:.6}", ubicacion.latitude, ubicacion.longitude));
+
+    guardar_direccion_predeterminada(
+        pool, patient_id, &direccion,
+        Some(ubicacion.latitude), Some(ubicacion.longitude), ubicacion.name.as_deref(),
+    ).await;
+    asignar_direccion_orden(pool, patient_id, &direccion_con_mapa(&direccion, ubicacion.latitude, ubicacion.longitude)).await;
+}
+
+/// Texto de entrega con la liga al mapa, para que el repartidor llegue al pin
+fn direccion_con_mapa(direccion: &str, latitud: f64, longitud: f64) -> String {
+    format!("{} (https://maps.google.com/?q={},{})", direccion, latitud, longitud)
+}
+
+/// Solo una dirección predeterminada por paciente: la última que nos dio. Si ya la teníamos guardada
+/// se vuelve a marcar esa fila; si no, se inserta. Todo en una transacción: si no queda ninguna fila
+/// predeterminada no se pierde la anterior.
+async fn guardar_direccion_predeterminada(
+    pool: &PgPool,
+    patient_id: Uuid,
+    direccion: &str,
+    latitud: Option<f64>,
+    longitud: Option<f64>,
+    lugar: Option<&str>,
+) {
+    let resultado: Result<bool, sqlx::Error> = async {
+        let mut tx = pool.begin().await?;
+        sqlx::query("UPDATE patient_addresses SET is_default = false WHERE patient_id = $1 AND is_default")
+            .bind(patient_id)
+            .execute(&mut *tx)
+            .await?;
+
+        let mut marcadas = sqlx::query(
+            "UPDATE patient_addresses
+             SET is_default = true, latitude = $3, longitude = $4, location_name = $5
+             WHERE patient_id = $1 AND full_address = $2"
+        )
+        .bind(patient_id)
+        .bind(direccion)
+        .bind(latitud)
+        .bind(longitud)
+        .bind(lugar)
+        .execute(&mut *tx)
+        .await?
+        .rows_affected();
+
+        if marcadas == 0 {
+            marcadas = sqlx::query(
+                "INSERT INTO patient_addresses (patient_id, address_label, full_address, is_default, latitude, longitude, location_name)
+                 VALUES ($1, 'WhatsApp Delivery', $2, true, $3, $4, $5)
+                 ON CONFLICT DO NOTHING"
+            )
+            .bind(patient_id)
+            .bind(direccion)
+            .bind(latitud)
+            .bind(longitud)
+            .bind(lugar)
+            .execute(&mut *tx)
+            .await?
+            .rows_affected();
+        }
+
+        if marcadas == 0 {
+            tx.rollback().await?;
+            return Ok(false);
+        }
+        tx.commit().await?;
+        Ok(true)
+    }.await;
+
+    match resultado {
+        Ok(true) => {}
+        Ok(false) => eprintln!("⚠️ No se pudo guardar la dirección de {}; se conserva la predeterminada anterior", patient_id),
+        Err(e) => eprintln!("❌ No se pudo guardar la dirección de {}: {}", patient_id, e),
+    }
+}
+
+/// Dirección de entrega guardada como predeterminada (con la liga al mapa si fue una ubicación)
+pub async fn obtener_direccion_predeterminada(pool: &PgPool, patient_id: Uuid) -> Option<String> {
+    let fila = sqlx::query_as::<sqlx::Postgres, (String, Option<f64>, Option<f64>)>(
+        "SELECT full_address, latitude, longitude FROM patient_addresses
+         WHERE patient_id = $1 AND is_default AND COALESCE(full_address, '') <> ''
+         LIMIT 1"
+    )
+    .bind(patient_id)
+    .fetch_optional(pool)
+    .await
+    .ok()
+    .flatten()?;
+
+    Some(match fila {
+        (direccion, Some(latitud), Some(longitud)) => direccion_con_mapa(&direccion, latitud, longitud),
+        (direccion, _, _) => direccion,
+    })
+}
+
+/// Pone la dirección de entrega en la orden pendiente del paciente
+pub async fn asignar_direccion_orden(pool: &PgPool, patient_id: Uuid, direccion: &str) {
+    let _ = sqlx::query(
         "UPDATE medication_orders 
          SET delivery_address = $1 
          FROM orders 
          WHERE medication_orders.order_id = orders.order_id 
          AND orders.patient_id = $2 
-         AND orders.p_status = 'pendiente'",
-        direccion, patient_id
-    ).execute(pool).await;
+         AND orders.p_status = 'pendiente'"
+    )
+    .bind(direccion)
+    .bind(patient_id)
+    .execute(pool).await;
 }
 
-#[allow(dead_code)]
-pub async fn guardar_receta_orden(pool: &PgPool, patient_id: Uuid, media_id: &str) {
+pub async fn guardar_receta_orden(pool: &PgPool, patient_id: Uuid, ubicacion: &str) {
     let _ = sqlx::query!(
         "UPDATE medication_orders 
          SET prescription_url = $1 
@@ -237,6 +448,6 @@ pub async fn guardar_receta_orden(pool: &PgPool, patient_id: Uuid, media_id: &st
          WHERE medication_orders.order_id = orders.order_id 
          AND orders.patient_id = $2 
          AND orders.p_status = 'pendiente'",
-        media_id, patient_id
+        ubicacion, patient_id
     ).execute(pool).await;
 }
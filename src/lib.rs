@@ -0,0 +1,6 @@
+// Módulos compartidos por el servidor y el simulador
+pub mod app;
+pub mod bot_logic;
+pub mod database;
+pub mod storage;
+pub mod whatsapp;
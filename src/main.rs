@@ -1,16 +1,30 @@
-﻿mod bot_logic;
-mod database;
-mod whatsapp;
-
+﻿use biotecza_bot::{app, bot_logic, database, storage, whatsapp};
 use axum::{
     routing::{get, post},
     Router,
 };
 use sqlx::PgPool;
+use app::AppState;
 use std::net::SocketAddr;
+use std::sync::Arc;
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    // `biotecza_bot diagrama [dot|mermaid]`: imprime el flujo de conversación sin levantar el servidor
+    let args: Vec<String> = std::env::args().collect();
+    if args.get(1).map(String::as_str) == Some("diagrama") {
+        let formato = match args.get(2) {
+            Some(f) => f.parse::<bot_logic::diagrama::Formato>()?,
+            None => bot_logic::diagrama::Formato::Dot,
+        };
+        let flujo = bot_logic::flow::flujo();
+        for advertencia in bot_logic::diagrama::advertencias(flujo) {
+            eprintln!("⚠️ {}", advertencia);
+        }
+        println!("{}", bot_logic::diagrama::generar(flujo, formato));
+        return Ok(());
+    }
+
     dotenvy::dotenv().ok();
     let database_url = std::env::var("DATABASE_URL")?;
     
@@ -18,10 +32,96 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let pool = PgPool::connect(&database_url).await?;
     println!("✅ Biotecza DB conectada");
 
+    sqlx::migrate!("./migrations").run(&pool).await?;
+
+    // `biotecza_bot existencias <conteo.csv>`: carga el conteo físico de inventario y termina
+    if args.get(1).map(String::as_str) == Some("existencias") {
+        let archivo = args.get(2).ok_or("Uso: biotecza_bot existencias <conteo.csv>")?;
+        let conteo = bot_logic::inventario::leer_conteo(&std::fs::read_to_string(archivo)?)?;
+        let rechazados = database::cargar_existencias(&pool, &conteo).await?;
+        if !rechazados.is_empty() {
+            for med_id in &rechazados {
+                eprintln!("❌ {}: no existe o tiene más piezas reservadas que las contadas", med_id);
+            }
+            return Err("No se cargó el conteo; corrige esos renglones y vuelve a intentar".into());
+        }
+        println!("📦 Existencias cargadas para {} medicamentos", conteo.len());
+        return Ok(());
+    }
+
+    // Cada estado con su manejador, alcanzable y con salida
+    bot_logic::flow::flujo().validar().map_err(|errores| errores.join("\n"))?;
+    println!("✅ Flujo de conversación validado");
+
+    // Sesiones guardadas con estados que ya no existen
+    bot_logic::sesion::migrar_estados(&pool).await;
+
+    // Transporte hacia Meta, con seguimiento de entregas
+    let cliente = whatsapp::WhatsAppClient::new(whatsapp::WhatsAppConfig::desde_env()).con_seguimiento(pool.clone());
+    let messenger = Arc::new(whatsapp::MetaMessenger::new(cliente));
+
+    // Mantenimiento periódico: bitácora de mensajes procesados, entregas fallidas y carritos olvidados
+    let horas_ttl: i64 = std::env::var("MENSAJES_TTL_HORAS").ok().and_then(|h| h.parse().ok()).unwrap_or(168);
+    let horas_carrito: i64 = std::env::var("CARRITO_EXPIRA_HORAS").ok().and_then(|h| h.parse().ok()).unwrap_or(24);
+    let pool_limpieza = pool.clone();
+    tokio::spawn(async move {
+        let mut intervalo = tokio::time::interval(std::time::Duration::from_secs(3600));
+        loop {
+            intervalo.tick().await;
+            let borrados = database::limpiar_mensajes_procesados(&pool_limpieza, horas_ttl).await
+                + database::limpiar_cola(&pool_limpieza, horas_ttl).await;
+            if borrados > 0 {
+                println!("🧹 {} mensajes procesados expirados eliminados", borrados);
+            }
+
+            // Las piezas de carritos sin movimiento vuelven a estar disponibles
+            let carritos = database::liberar_carritos_vencidos(&pool_limpieza, horas_carrito).await;
+            if carritos > 0 {
+                println!("🛒 {} carritos vencidos vaciados y sus piezas liberadas", carritos);
+            }
+
+            // Reporte de mensajes críticos que no llegaron al paciente
+            for fallido in database::obtener_mensajes_fallidos(&pool_limpieza, 1).await {
+                if fallido.requiere_seguimiento {
+                    eprintln!(
+                        "🚨 Seguimiento pendiente: {} ({}) falló con código {:?}: {}",
+                        fallido.telefono, fallido.wamid, fallido.error_code,
+                        fallido.error_detalle.unwrap_or_default()
+                    );
+                }
+            }
+        }
+    });
+
+    // Avisos de pedidos listos y resultados de laboratorio publicados, y alertas de inventario para compras
+    let pool_avisos = pool.clone();
+    let messenger_avisos = messenger.clone();
+    let telefono_compras = std::env::var("COMPRAS_TELEFONO").ok().filter(|t| !t.trim().is_empty());
+    tokio::spawn(async move {
+        let mut intervalo = tokio::time::interval(std::time::Duration::from_secs(60));
+        loop {
+            intervalo.tick().await;
+            let enviados = bot_logic::notificaciones::enviar_avisos_pendientes(&pool_avisos, messenger_avisos.as_ref()).await;
+            if enviados > 0 {
+                println!("📨 {} avisos de pedidos listos y resultados enviados", enviados);
+            }
+            bot_logic::notificaciones::avisar_alertas_inventario(
+                &pool_avisos, messenger_avisos.as_ref(), telefono_compras.as_deref(),
+            ).await;
+        }
+    });
+
+    // Workers que procesan los mensajes encolados por el webhook
+    let storage = Arc::new(storage::LocalStorage::desde_env());
+    let cola = whatsapp::cola::iniciar_workers(
+        pool.clone(), messenger, storage, whatsapp::cola::ConfigCola::desde_env(),
+    ).await;
+    let estado = AppState { pool, cola, webhook: Arc::new(whatsapp::webhook::ConfigWebhook::desde_env()) };
+
     let app = Router::new()
         .route("/webhook", get(whatsapp::handle_verify_webhook))
         .route("/webhook", post(whatsapp::handle_recibir_mensaje))
-        .with_state(pool);
+        .with_state(estado);
 
     let addr = SocketAddr::from(([0, 0, 0, 0], 3000));
     println!("🚀 Servidor Biotecza corriendo en http://{}", addr);
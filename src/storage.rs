@@ -0,0 +1,37 @@
+use async_trait::async_trait;
+use std::path::PathBuf;
+
+/// Backend donde se guardan los archivos que mandan los pacientes (recetas).
+/// Devuelve la ubicación final, que es lo que se guarda en la orden.
+#[async_trait]
+pub trait MediaStorage: Send + Sync {
+    async fn guardar(&self, ruta: &str, contenido: &[u8], mime_type: &str) -> Result<String, String>;
+}
+
+/// Almacenamiento en el sistema de archivos local (opción por defecto)
+pub struct LocalStorage {
+    pub raiz: PathBuf,
+}
+
+impl LocalStorage {
+    pub fn new(raiz: impl Into<PathBuf>) -> Self {
+        LocalStorage { raiz: raiz.into() }
+    }
+
+    /// Usa `MEDIA_DIR` o `./media` si no está definida
+    pub fn desde_env() -> Self {
+        LocalStorage::new(std::env::var("MEDIA_DIR").unwrap_or_else(|_| "./media".to_string()))
+    }
+}
+
+#[async_trait]
+impl MediaStorage for LocalStorage {
+    async fn guardar(&self, ruta: &str, contenido: &[u8], _mime_type: &str) -> Result<String, String> {
+        let destino = self.raiz.join(ruta);
+        if let Some(dir) = destino.parent() {
+            tokio::fs::create_dir_all(dir).await.map_err(|e| e.to_string())?;
+        }
+        tokio::fs::write(&destino, contenido).await.map_err(|e| e.to_string())?;
+        Ok(destino.to_string_lossy().to_string())
+    }
+}
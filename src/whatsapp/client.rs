@@ -1,48 +1,487 @@
 use serde_json::json;
 use reqwest::Client;
+use sqlx::PgPool;
+use std::fmt;
+use std::time::Duration;
+use crate::database;
+use super::media::MediaDescargada;
+use super::messenger::TipoMedia;
+use super::interactive::{Botones, Lista};
+use super::templates::Plantilla;
 
-pub async fn enviar_texto(telefono: &str, texto: &str) {
-    llamar_meta(json!({
-        "messaging_product": "whatsapp", "to": telefono,
-        "type": "text", "text": { "body": texto }
-    })).await;
+/// Configuración de la Cloud API. La URL base se puede cambiar para apuntar a un mock local.
+#[derive(Debug, Clone)]
+pub struct WhatsAppConfig {
+    pub token: String,
+    pub phone_number_id: String,
+    pub base_url: String,
+    pub max_reintentos: u32,
+    pub espera_base: Duration,
 }
 
-pub async fn enviar_botones(telefono: &str, texto: &str, botones: Vec<&str>) {
-    let buttons_json: Vec<serde_json::Value> = botones.iter().map(|&b| {
-        json!({ "type": "reply", "reply": { "id": b, "title": b } })
-    }).collect();
+impl WhatsAppConfig {
+    pub fn desde_env() -> Self {
+        WhatsAppConfig {
+            token: std::env::var("WHATSAPP_TOKEN").unwrap_or_default(),
+            phone_number_id: std::env::var("PHONE_NUMBER_ID").unwrap_or_default(),
+            base_url: std::env::var("GRAPH_API_URL").unwrap_or_else(|_| "https://graph.facebook.com/v21.0".to_string()),
+            max_reintentos: std::env::var("WHATSAPP_MAX_REINTENTOS").ok().and_then(|v| v.parse().ok()).unwrap_or(3),
+            espera_base: Duration::from_millis(500),
+        }
+    }
+}
+
+/// wamid que Meta asigna a cada mensaje aceptado
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MessageId(pub String);
+
+/// Error de la Graph API tal como viene en `{"error": {...}}`
+#[derive(Debug, Clone)]
+pub struct GraphApiError {
+    pub http_status: u16,
+    pub code: i64,
+    pub subcode: Option<i64>,
+    pub message: String,
+    pub tipo: String,
+    pub fbtrace_id: Option<String>,
+}
+
+#[derive(Debug)]
+pub enum SendError {
+    /// No se pudo abrir la conexión: la petición nunca llegó a Meta
+    Conexion(String),
+    /// Falla de red o de transporte ya enviada la petición (Meta pudo haberla procesado)
+    Http(String),
+    /// Meta rechazó el mensaje
+    Api(GraphApiError),
+    /// Respuesta exitosa pero sin el formato esperado
+    Respuesta(String),
+    /// El mensaje rebasa los límites de Meta y ni siquiera se intentó enviar
+    Invalido(String),
+}
 
-    llamar_meta(json!({
-        "messaging_product": "whatsapp", "to": telefono,
-        "type": "interactive",
-        "interactive": {
-            "type": "button",
-            "body": { "text": texto },
-            "action": { "buttons": buttons_json }
+impl SendError {
+    /// Errores temporales que vale la pena reintentar con backoff. Los envíos son POST sin
+    /// idempotencia: solo se reintenta cuando es seguro que Meta no aceptó el mensaje
+    /// (no hubo conexión, 429/5xx explícito o un límite de envío), nunca un corte a medio camino.
+    pub fn es_reintentable(&self) -> bool {
+        match self {
+            SendError::Conexion(_) => true,
+            SendError::Api(e) => {
+                e.http_status == 429 || e.http_status >= 500 || matches!(
+                    e.code,
+                    4               // Límite de llamadas de la app
+                    | 80007         // Límite de la cuenta de WhatsApp Business
+                    | 130429        // Límite de throughput de la Cloud API
+                    | 131056        // Demasiados mensajes al mismo destinatario
+                )
+            }
+            SendError::Http(_) | SendError::Respuesta(_) | SendError::Invalido(_) => false,
         }
-    })).await;
+    }
 }
 
-pub async fn enviar_lista(telefono: &str, titulo: &str, cuerpo: &str, boton: &str, opciones: Vec<String>) {
-    let rows: Vec<serde_json::Value> = opciones.iter().map(|op| {
-        json!({ "id": op, "title": op }) 
-    }).collect();
+/// Separa las fallas de conexión (reintentables) del resto de errores de transporte
+fn error_de_red(e: reqwest::Error) -> SendError {
+    if e.is_connect() {
+        SendError::Conexion(e.to_string())
+    } else {
+        SendError::Http(e.to_string())
+    }
+}
 
-    llamar_meta(json!({
-        "messaging_product": "whatsapp", "to": telefono, "type": "interactive",
-        "interactive": {
-            "type": "list",
-            "header": { "type": "text", "text": titulo },
-            "body": { "text": cuerpo },
-            "action": { "button": boton, "sections": [{ "title": "Opciones", "rows": rows }] }
+impl fmt::Display for SendError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SendError::Conexion(e) => write!(f, "sin conexión: {}", e),
+            SendError::Http(e) => write!(f, "error de red: {}", e),
+            SendError::Api(e) => write!(f, "Graph API {} (código {}): {}", e.http_status, e.code, e.message),
+            SendError::Respuesta(e) => write!(f, "respuesta inesperada: {}", e),
+            SendError::Invalido(e) => write!(f, "mensaje inválido: {}", e),
         }
-    })).await;
+    }
 }
 
-async fn llamar_meta(body: serde_json::Value) {
-    let token = std::env::var("WHATSAPP_TOKEN").unwrap_or_default();
-    let phone_id = std::env::var("PHONE_NUMBER_ID").unwrap_or_default();
-    let url = format!("https://graph.facebook.com/v21.0/{}/messages", phone_id);
-    let _ = Client::new().post(url).bearer_auth(token).json(&body).send().await;
+impl std::error::Error for SendError {}
+
+/// Cliente de la Cloud API con un pool de conexiones compartido
+pub struct WhatsAppClient {
+    http: Client,
+    config: WhatsAppConfig,
+    // Pool usado para guardar el wamid de cada mensaje enviado (seguimiento de entregas)
+    seguimiento: Option<PgPool>,
+}
+
+impl WhatsAppClient {
+    pub fn new(config: WhatsAppConfig) -> Self {
+        WhatsAppClient { http: Client::new(), config, seguimiento: None }
+    }
+
+    /// Activa el registro de mensajes salientes en `mensajes_salientes`.
+    pub fn con_seguimiento(mut self, pool: PgPool) -> Self {
+        self.seguimiento = Some(pool);
+        self
+    }
+
+    pub async fn enviar_texto(&self, telefono: &str, texto: &str) -> Result<MessageId, SendError> {
+        self.enviar(telefono, json!({
+            "messaging_product": "whatsapp", "to": telefono,
+            "type": "text", "text": { "body": texto }
+        }), false).await
+    }
+
+    /// Igual que `enviar_texto`, pero si la entrega falla el mensaje queda marcado para seguimiento
+    /// (p. ej. la confirmación de un pedido).
+    pub async fn enviar_texto_critico(&self, telefono: &str, texto: &str) -> Result<MessageId, SendError> {
+        self.enviar(telefono, json!({
+            "messaging_product": "whatsapp", "to": telefono,
+            "type": "text", "text": { "body": texto }
+        }), true).await
+    }
+
+    pub async fn enviar_botones(&self, telefono: &str, botones: &Botones) -> Result<MessageId, SendError> {
+        botones.validar().map_err(SendError::Invalido)?;
+        self.enviar(telefono, json!({
+            "messaging_product": "whatsapp", "to": telefono,
+            "type": "interactive",
+            "interactive": botones.a_json()
+        }), false).await
+    }
+
+    pub async fn enviar_lista(&self, telefono: &str, lista: &Lista) -> Result<MessageId, SendError> {
+        lista.validar().map_err(SendError::Invalido)?;
+        self.enviar(telefono, json!({
+            "messaging_product": "whatsapp", "to": telefono, "type": "interactive",
+            "interactive": lista.a_json()
+        }), false).await
+    }
+
+    /// Plantilla aprobada. Es la única forma de escribirle a un paciente fuera de la ventana de 24 horas.
+    pub async fn enviar_plantilla(&self, telefono: &str, plantilla: &Plantilla) -> Result<MessageId, SendError> {
+        plantilla.validar().map_err(SendError::Invalido)?;
+        self.enviar(telefono, json!({
+            "messaging_product": "whatsapp", "to": telefono, "type": "template",
+            "template": plantilla.a_json()
+        }), false).await
+    }
+
+    pub async fn enviar_media(&self, telefono: &str, tipo: TipoMedia, link: &str, caption: Option<&str>) -> Result<MessageId, SendError> {
+        let mut media = json!({ "link": link });
+        if let Some(c) = caption {
+            media["caption"] = json!(c);
+        }
+
+        let mut body = json!({
+            "messaging_product": "whatsapp", "to": telefono,
+            "type": tipo.como_str()
+        });
+        body[tipo.como_str()] = media;
+
+        self.enviar(telefono, body, false).await
+    }
+
+    /// Descarga un adjunto en dos pasos: `GET /{media_id}` devuelve una URL temporal
+    /// y luego se baja el archivo de esa URL, ambos con el token del negocio.
+    pub async fn descargar_media(&self, media_id: &str) -> Result<MediaDescargada, SendError> {
+        let respuesta = self.http
+            .get(format!("{}/{}", self.config.base_url, media_id))
+            .bearer_auth(&self.config.token)
+            .send().await
+            .map_err(error_de_red)?;
+        let info = leer_respuesta(respuesta).await?;
+
+        let url = info["url"].as_str()
+            .ok_or_else(|| SendError::Respuesta("la respuesta de Meta no trae la URL del archivo".to_string()))?;
+        let mime_type = info["mime_type"].as_str().unwrap_or("application/octet-stream").to_string();
+
+        let contenido = self.http
+            .get(url)
+            .bearer_auth(&self.config.token)
+            .send().await
+            .and_then(|r| r.error_for_status())
+            .map_err(error_de_red)?
+            .bytes().await
+            .map_err(error_de_red)?;
+
+        Ok(MediaDescargada { contenido: contenido.to_vec(), mime_type })
+    }
+
+    /// Envía el mensaje reintentando con backoff exponencial los errores temporales.
+    async fn enviar(&self, telefono: &str, body: serde_json::Value, critico: bool) -> Result<MessageId, SendError> {
+        let mut intento = 0;
+        loop {
+            match self.enviar_una_vez(&body).await {
+                Ok(id) => {
+                    if let Some(pool) = &self.seguimiento {
+                        let tipo = body["type"].as_str().unwrap_or("desconocido");
+                        database::registrar_mensaje_saliente(pool, &id.0, telefono, tipo, critico).await;
+                    }
+                    return Ok(id);
+                }
+                Err(e) if e.es_reintentable() && intento < self.config.max_reintentos => {
+                    let espera = self.config.espera_base * 2u32.pow(intento);
+                    eprintln!("⏳ Envío a {} falló ({}), reintento en {:?}", telefono, e, espera);
+                    tokio::time::sleep(espera).await;
+                    intento += 1;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    async fn enviar_una_vez(&self, body: &serde_json::Value) -> Result<MessageId, SendError> {
+        let url = format!("{}/{}/messages", self.config.base_url, self.config.phone_number_id);
+        let respuesta = self.http
+            .post(url)
+            .bearer_auth(&self.config.token)
+            .json(body)
+            .send().await
+            .map_err(error_de_red)?;
+
+        let json = leer_respuesta(respuesta).await?;
+        json["messages"][0]["id"].as_str()
+            .map(|id| MessageId(id.to_string()))
+            .ok_or_else(|| SendError::Respuesta(json.to_string()))
+    }
+}
+
+/// Devuelve el JSON de una respuesta exitosa o decodifica el error de la Graph API
+async fn leer_respuesta(respuesta: reqwest::Response) -> Result<serde_json::Value, SendError> {
+    let status = respuesta.status();
+    let cuerpo = respuesta.text().await.map_err(|e| SendError::Http(e.to_string()))?;
+    let json: serde_json::Value = serde_json::from_str(&cuerpo).unwrap_or_default();
+
+    if status.is_success() {
+        return Ok(json);
+    }
+
+    // Un 5xx de un proxy puede no traer JSON; el status basta para decidir si se reintenta
+    let error = &json["error"];
+    Err(SendError::Api(GraphApiError {
+        http_status: status.as_u16(),
+        code: error["code"].as_i64().unwrap_or_default(),
+        subcode: error["error_subcode"].as_i64(),
+        message: error["message"].as_str().map(str::to_string).unwrap_or(cuerpo),
+        tipo: error["type"].as_str().unwrap_or_default().to_string(),
+        fbtrace_id: error["fbtrace_id"].as_str().map(str::to_string),
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::extract::{Path, State};
+    use axum::http::{HeaderMap, StatusCode};
+    use axum::response::IntoResponse;
+    use axum::routing::{get, post};
+    use axum::{Json, Router};
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Instant;
+
+    const TOKEN: &str = "token-de-prueba";
+
+    /// Levanta la Graph API de mentira en un puerto libre de localhost y devuelve su URL base
+    async fn mock(app: Router) -> String {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let direccion = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        format!("http://{}", direccion)
+    }
+
+    fn cliente(base_url: &str) -> WhatsAppClient {
+        WhatsAppClient::new(WhatsAppConfig {
+            token: TOKEN.to_string(),
+            phone_number_id: "106540352242922".to_string(),
+            base_url: base_url.to_string(),
+            max_reintentos: 2,
+            espera_base: Duration::from_millis(1),
+        })
+    }
+
+    fn autorizado(headers: &HeaderMap) -> bool {
+        headers.get("authorization").and_then(|h| h.to_str().ok()) == Some(&format!("Bearer {}", TOKEN))
+    }
+
+    /// `GET /{media_id}` contesta la URL temporal (en el mismo mock) y `GET /archivos/{id}` el binario
+    fn graph_media() -> Router {
+        Router::new()
+            .route("/:media_id", get(|Path(id): Path<String>, headers: HeaderMap| async move {
+                if !autorizado(&headers) {
+                    return (StatusCode::UNAUTHORIZED, Json(json!({}))).into_response();
+                }
+                if id != "1234567890" {
+                    let error = json!({ "error": {
+                        "message": "Unsupported get request. Object with ID does not exist",
+                        "type": "GraphMethodException", "code": 100, "error_subcode": 33,
+                        "fbtrace_id": "AbCdEf"
+                    }});
+                    return (StatusCode::BAD_REQUEST, Json(error)).into_response();
+                }
+                let host = headers.get("host").and_then(|h| h.to_str().ok()).unwrap_or_default().to_string();
+                Json(json!({
+                    "messaging_product": "whatsapp",
+                    "url": format!("http://{}/archivos/{}", host, id),
+                    "mime_type": "image/jpeg",
+                    "id": id
+                })).into_response()
+            }))
+            .route("/archivos/:media_id", get(|headers: HeaderMap| async move {
+                if !autorizado(&headers) {
+                    return (StatusCode::UNAUTHORIZED, Vec::new());
+                }
+                (StatusCode::OK, vec![0xFF, 0xD8, 0xFF, 0xE0, 0x00, 0x10])
+            }))
+    }
+
+    #[tokio::test]
+    async fn descarga_media_en_dos_pasos() {
+        let base = mock(graph_media()).await;
+        let media = cliente(&base).descargar_media("1234567890").await.unwrap();
+        assert_eq!(media.mime_type, "image/jpeg");
+        assert_eq!(media.contenido, vec![0xFF, 0xD8, 0xFF, 0xE0, 0x00, 0x10]);
+    }
+
+    #[tokio::test]
+    async fn descarga_media_sin_token_falla() {
+        let base = mock(graph_media()).await;
+        let mut sin_token = cliente(&base);
+        sin_token.config.token = "otro".to_string();
+        match sin_token.descargar_media("1234567890").await {
+            Err(SendError::Api(e)) => assert_eq!(e.http_status, 401),
+            otro => panic!("se esperaba un 401: {:?}", otro.map(|m| m.mime_type)),
+        }
+    }
+
+    #[tokio::test]
+    async fn descarga_media_inexistente_decodifica_el_error() {
+        let base = mock(graph_media()).await;
+        match cliente(&base).descargar_media("999").await {
+            Err(SendError::Api(e)) => {
+                assert_eq!(e.http_status, 400);
+                assert_eq!(e.code, 100);
+                assert_eq!(e.subcode, Some(33));
+                assert_eq!(e.tipo, "GraphMethodException");
+                assert_eq!(e.fbtrace_id.as_deref(), Some("AbCdEf"));
+            }
+            otro => panic!("se esperaba un error de la Graph API: {:?}", otro.map(|m| m.mime_type)),
+        }
+    }
+
+    type Respuestas = Arc<Vec<(StatusCode, &'static str)>>;
+
+    /// `POST /{phone_number_id}/messages` contesta en orden las respuestas dadas (la última se repite)
+    /// y cuenta las llamadas
+    fn graph_mensajes(respuestas: Vec<(StatusCode, &'static str)>) -> (Router, Arc<AtomicUsize>) {
+        let llamadas = Arc::new(AtomicUsize::new(0));
+        let app = Router::new()
+            .route("/106540352242922/messages", post(
+                |State((respuestas, llamadas)): State<(Respuestas, Arc<AtomicUsize>)>| async move {
+                    let n = llamadas.fetch_add(1, Ordering::SeqCst);
+                    let (status, cuerpo) = respuestas[n.min(respuestas.len() - 1)];
+                    (status, [("content-type", "application/json")], cuerpo)
+                }
+            ))
+            .with_state((Arc::new(respuestas), llamadas.clone()));
+        (app, llamadas)
+    }
+
+    const ACEPTADO: &str = r#"{"messaging_product":"whatsapp","contacts":[{"input":"5215512345678","wa_id":"5215512345678"}],"messages":[{"id":"wamid.HBgNNTIxNTUxMjM0NTY3OBUCABEYEjA="}]}"#;
+    const LIMITE: &str = r#"{"error":{"message":"(#130429) Rate limit hit","type":"OAuthException","code":130429,"fbtrace_id":"Az8or2yhqkZfEZ-_4Qn_Bam"}}"#;
+    const FUERA_DE_VENTANA: &str = r#"{"error":{"message":"(#131047) Re-engagement message","type":"OAuthException","code":131047,"error_data":{"messaging_product":"whatsapp","details":"Message failed to send because more than 24 hours have passed since the customer last replied to this number."},"error_subcode":2494010,"fbtrace_id":"A9lQw2kyq2Z1qkOt2FjhCyB"}}"#;
+
+    #[tokio::test]
+    async fn envio_aceptado_devuelve_el_wamid() {
+        let (app, llamadas) = graph_mensajes(vec![(StatusCode::OK, ACEPTADO)]);
+        let base = mock(app).await;
+        let id = cliente(&base).enviar_texto("5215512345678", "hola").await.unwrap();
+        assert_eq!(id.0, "wamid.HBgNNTIxNTUxMjM0NTY3OBUCABEYEjA=");
+        assert_eq!(llamadas.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn decodifica_el_error_de_la_graph_api_sin_reintentar() {
+        let (app, llamadas) = graph_mensajes(vec![(StatusCode::BAD_REQUEST, FUERA_DE_VENTANA)]);
+        let base = mock(app).await;
+        match cliente(&base).enviar_texto("5215512345678", "hola").await {
+            Err(SendError::Api(e)) => {
+                assert_eq!(e.http_status, 400);
+                assert_eq!(e.code, 131047);
+                assert_eq!(e.subcode, Some(2494010));
+                assert_eq!(e.tipo, "OAuthException");
+                assert_eq!(e.message, "(#131047) Re-engagement message");
+                assert_eq!(e.fbtrace_id.as_deref(), Some("A9lQw2kyq2Z1qkOt2FjhCyB"));
+            }
+            otro => panic!("se esperaba un error de la Graph API: {:?}", otro),
+        }
+        assert_eq!(llamadas.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn reintenta_5xx_y_limites_hasta_que_meta_acepta() {
+        let (app, llamadas) = graph_mensajes(vec![
+            (StatusCode::SERVICE_UNAVAILABLE, "<html>upstream error</html>"),
+            (StatusCode::BAD_REQUEST, LIMITE),
+            (StatusCode::OK, ACEPTADO),
+        ]);
+        let base = mock(app).await;
+        assert!(cliente(&base).enviar_texto("5215512345678", "hola").await.is_ok());
+        assert_eq!(llamadas.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn reintenta_429() {
+        let (app, llamadas) = graph_mensajes(vec![(StatusCode::TOO_MANY_REQUESTS, "{}"), (StatusCode::OK, ACEPTADO)]);
+        let base = mock(app).await;
+        assert!(cliente(&base).enviar_texto("5215512345678", "hola").await.is_ok());
+        assert_eq!(llamadas.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn agota_los_reintentos_con_backoff_exponencial() {
+        let (app, llamadas) = graph_mensajes(vec![(StatusCode::INTERNAL_SERVER_ERROR, "{}")]);
+        let base = mock(app).await;
+        let mut cliente = cliente(&base);
+        cliente.config.espera_base = Duration::from_millis(20);
+
+        let inicio = Instant::now();
+        match cliente.enviar_texto("5215512345678", "hola").await {
+            // Un 5xx de un proxy sin JSON conserva el cuerpo como mensaje
+            Err(SendError::Api(e)) => assert_eq!((e.http_status, e.message.as_str()), (500, "{}")),
+            otro => panic!("se esperaba el último 500: {:?}", otro),
+        }
+        // Primer intento + 2 reintentos, esperando 20 ms y luego 40 ms
+        assert_eq!(llamadas.load(Ordering::SeqCst), 3);
+        assert!(inicio.elapsed() >= Duration::from_millis(60));
+    }
+
+    #[tokio::test]
+    async fn reintenta_si_no_hay_conexion() {
+        // Puerto que se acaba de liberar: nadie escucha
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base = format!("http://{}", listener.local_addr().unwrap());
+        drop(listener);
+
+        let mut cliente = cliente(&base);
+        cliente.config.espera_base = Duration::from_millis(20);
+        let inicio = Instant::now();
+        assert!(matches!(cliente.enviar_texto("5215512345678", "hola").await, Err(SendError::Conexion(_))));
+        assert!(inicio.elapsed() >= Duration::from_millis(60));
+    }
+
+    #[test]
+    fn solo_se_reintenta_lo_que_meta_no_acepto() {
+        let api = |http_status, code| SendError::Api(GraphApiError {
+            http_status, code, subcode: None, message: String::new(), tipo: String::new(), fbtrace_id: None,
+        });
+        assert!(SendError::Conexion("connection refused".to_string()).es_reintentable());
+        assert!(!SendError::Http("connection reset".to_string()).es_reintentable());
+        assert!(api(429, 0).es_reintentable());
+        assert!(api(503, 0).es_reintentable());
+        assert!(api(400, 131056).es_reintentable());
+        assert!(!api(400, 131047).es_reintentable());
+        assert!(!api(401, 190).es_reintentable());
+        assert!(!SendError::Respuesta("{}".to_string()).es_reintentable());
+        assert!(!SendError::Invalido("título largo".to_string()).es_reintentable());
+    }
 }
@@ -0,0 +1,159 @@
+use sqlx::PgPool;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{mpsc, Mutex};
+use crate::{bot_logic, database};
+use crate::storage::MediaStorage;
+use super::messenger::Messenger;
+use super::payload::InboundMessage;
+
+/// Configuración de los workers que drenan `cola_mensajes`
+#[derive(Debug, Clone)]
+pub struct ConfigCola {
+    pub workers: usize,
+    pub max_intentos: i32,
+    // Tiempo tras el cual un mensaje en `procesando` se considera abandonado
+    pub timeout_segundos: i64,
+}
+
+impl ConfigCola {
+    pub fn desde_env() -> Self {
+        let leer = |var: &str, defecto: i64| {
+            std::env::var(var).ok().and_then(|v| v.parse().ok()).unwrap_or(defecto)
+        };
+        ConfigCola {
+            workers: leer("COLA_WORKERS", 4).max(1) as usize,
+            max_intentos: leer("COLA_MAX_INTENTOS", 3) as i32,
+            timeout_segundos: leer("COLA_TIMEOUT_SEGUNDOS", 120),
+        }
+    }
+}
+
+/// Arranca el pool de workers y el barrido periódico de la cola.
+/// Devuelve el canal por el que el webhook avisa de mensajes recién encolados.
+pub async fn iniciar_workers(
+    pool: PgPool,
+    messenger: Arc<dyn Messenger>,
+    storage: Arc<dyn MediaStorage>,
+    config: ConfigCola,
+) -> mpsc::Sender<i64> {
+    let (tx, rx) = mpsc::channel::<i64>(1024);
+    let rx = Arc::new(Mutex::new(rx));
+
+    for _ in 0..config.workers {
+        let pool = pool.clone();
+        let messenger = messenger.clone();
+        let storage = storage.clone();
+        let rx = rx.clone();
+        let max_intentos = config.max_intentos;
+        tokio::spawn(async move {
+            loop {
+                let id = match rx.lock().await.recv().await {
+                    Some(id) => id,
+                    None => break,
+                };
+                procesar_de_cola(&pool, &messenger, &storage, id, max_intentos).await;
+            }
+        });
+    }
+
+    // Barrido: retoma lo pendiente al arrancar (recuperación tras caída) y cada pocos segundos
+    // vuelve a avisar de lo que no entró al canal o quedó atascado.
+    let tx_barrido = tx.clone();
+    tokio::spawn(async move {
+        let mut intervalo = tokio::time::interval(Duration::from_secs(15));
+        loop {
+            intervalo.tick().await;
+            let (recuperados, fallidos) =
+                database::recuperar_mensajes_atascados(&pool, config.timeout_segundos, config.max_intentos).await;
+            if recuperados > 0 {
+                println!("♻️ {} mensajes atascados devueltos a la cola", recuperados);
+            }
+            if fallidos > 0 {
+                eprintln!("❌ {} mensajes atascados agotaron sus intentos y quedaron en error", fallidos);
+            }
+            for id in database::obtener_pendientes(&pool, 500).await {
+                if tx_barrido.try_send(id).is_err() {
+                    break;
+                }
+            }
+        }
+    });
+
+    tx
+}
+
+async fn procesar_de_cola(
+    pool: &PgPool,
+    messenger: &Arc<dyn Messenger>,
+    storage: &Arc<dyn MediaStorage>,
+    id: i64,
+    max_intentos: i32,
+) {
+    let mut siguiente = Some(id);
+
+    while let Some(id) = siguiente {
+        // Otro worker (u otra instancia) pudo haberlo tomado ya, o hay un mensaje
+        // anterior del mismo teléfono en curso: quien lo atienda seguirá con este.
+        let Some((telefono, mensaje)) = database::tomar_mensaje(pool, id).await else {
+            return;
+        };
+
+        if !procesar_mensaje(pool, messenger, storage, id, &telefono, mensaje, max_intentos).await {
+            // El reintento lo hará el barrido, no este ciclo
+            return;
+        }
+
+        // Los mensajes del mismo teléfono que esperaban a este se atienden en orden
+        siguiente = database::siguiente_pendiente_del_telefono(pool, &telefono).await;
+    }
+}
+
+/// Ejecuta el bot para un mensaje ya reclamado. Devuelve `true` si terminó bien.
+async fn procesar_mensaje(
+    pool: &PgPool,
+    messenger: &Arc<dyn Messenger>,
+    storage: &Arc<dyn MediaStorage>,
+    id: i64,
+    telefono: &str,
+    mensaje: serde_json::Value,
+    max_intentos: i32,
+) -> bool {
+    let msg: InboundMessage = match serde_json::from_value(mensaje) {
+        Ok(m) => m,
+        Err(e) => {
+            database::marcar_error(pool, id, &e.to_string(), 0).await;
+            return false;
+        }
+    };
+
+    // Un solo mensaje a la vez por conversación, aunque haya varias instancias del bot
+    let Some(lock) = database::bloquear_telefono(pool, telefono).await else {
+        database::marcar_error(pool, id, "No se pudo tomar el lock del teléfono", max_intentos).await;
+        return false;
+    };
+
+    // Se ejecuta en su propia tarea para que un panic no tumbe al worker ni deje el lock tomado
+    let pool_tarea = pool.clone();
+    let messenger_tarea = messenger.clone();
+    let storage_tarea = storage.clone();
+    let tel_tarea = telefono.to_string();
+    let resultado = tokio::spawn(async move {
+        let entrada = bot_logic::UserInput::desde_mensaje(&msg);
+        bot_logic::procesar(&pool_tarea, messenger_tarea.as_ref(), storage_tarea.as_ref(), &tel_tarea, &entrada).await
+    }).await;
+
+    database::desbloquear_telefono(lock, telefono).await;
+
+    match resultado {
+        Ok(()) => {
+            database::marcar_procesado(pool, id).await;
+            true
+        }
+        Err(e) => {
+            eprintln!("❌ Falló el procesamiento del mensaje {} de la cola: {}", id, e);
+            database::marcar_error(pool, id, &e.to_string(), max_intentos).await;
+            false
+        }
+    }
+}
@@ -0,0 +1,395 @@
+use serde_json::json;
+use std::collections::HashSet;
+use std::ops::Range;
+
+// Límites de los mensajes interactivos de la Cloud API. Meta rechaza el mensaje completo
+// si alguno se rebasa, así que los validamos antes de enviar.
+pub const MAX_BOTONES: usize = 3;
+pub const MAX_TITULO_BOTON: usize = 20;
+pub const MAX_FILAS_LISTA: usize = 10;
+pub const MAX_TITULO_FILA: usize = 24;
+pub const MAX_DESCRIPCION_FILA: usize = 72;
+pub const MAX_TEXTO_BOTON_LISTA: usize = 20;
+pub const MAX_ENCABEZADO: usize = 60;
+pub const MAX_CUERPO_BOTONES: usize = 1024;
+pub const MAX_CUERPO_LISTA: usize = 4096;
+pub const MAX_PIE: usize = 60;
+pub const MAX_ID: usize = 200;
+
+/// Recorta un texto a `max` caracteres (no bytes), marcando el corte con "…"
+pub fn truncar(texto: &str, max: usize) -> String {
+    if texto.chars().count() <= max {
+        return texto.to_string();
+    }
+    let mut recortado: String = texto.chars().take(max.saturating_sub(1)).collect();
+    recortado.push('…');
+    recortado
+}
+
+/// Error si `texto` está vacío o rebasa `max` caracteres
+fn revisar_largo(campo: &str, texto: &str, max: usize) -> Result<(), String> {
+    let largo = texto.chars().count();
+    if largo == 0 {
+        return Err(format!("{} está vacío", campo));
+    }
+    if largo > max {
+        return Err(format!("{} rebasa {} caracteres ({})", campo, max, largo));
+    }
+    Ok(())
+}
+
+/// Meta rechaza el mensaje si dos opciones comparten id
+fn revisar_ids_unicos<'a>(ids: impl Iterator<Item = &'a str>) -> Result<(), String> {
+    let mut vistos = HashSet::new();
+    match ids.into_iter().find(|id| !vistos.insert(*id)) {
+        Some(id) => Err(format!("el id '{}' está repetido", id)),
+        None => Ok(()),
+    }
+}
+
+/// Posiciones que tocan en la `pagina` (desde 0) de `total` opciones y si queda otra página después.
+/// Si todo cabe en un mensaje va completo; si no, van de 9 en 9 y la décima fila es "Ver más".
+/// El detalle en texto de un listado usa el mismo rango que su lista interactiva.
+pub fn rango_pagina(total: usize, pagina: usize) -> (Range<usize>, bool) {
+    if total <= MAX_FILAS_LISTA && pagina == 0 {
+        return (0..total, false);
+    }
+    let por_pagina = MAX_FILAS_LISTA - 1;
+    let inicio = (pagina * por_pagina).min(total);
+    let fin = (inicio + por_pagina).min(total);
+    (inicio..fin, fin < total)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Boton {
+    pub id: String,
+    pub titulo: String,
+}
+
+/// Mensaje con hasta 3 botones de respuesta rápida
+#[derive(Debug, Clone, PartialEq)]
+pub struct Botones {
+    pub texto: String,
+    pub pie: Option<String>,
+    pub botones: Vec<Boton>,
+}
+
+impl Botones {
+    /// El texto puede traer lo que escribió el paciente (su nombre, su dirección), así que se
+    /// recorta a 1024 caracteres; si no, Meta rechazaría el mensaje completo y no llegaría nada.
+    pub fn new(texto: &str) -> Self {
+        Botones { texto: truncar(texto, MAX_CUERPO_BOTONES), pie: None, botones: Vec::new() }
+    }
+
+    /// Texto chico y gris debajo del cuerpo
+    pub fn pie(mut self, pie: &str) -> Self {
+        self.pie = Some(truncar(pie, MAX_PIE));
+        self
+    }
+
+    /// Agrega un botón; el título se recorta a 20 caracteres, el id conserva el valor completo
+    pub fn boton(mut self, id: impl ToString, titulo: &str) -> Self {
+        self.botones.push(Boton { id: id.to_string(), titulo: truncar(titulo, MAX_TITULO_BOTON) });
+        self
+    }
+
+    pub fn validar(&self) -> Result<(), String> {
+        if self.botones.is_empty() || self.botones.len() > MAX_BOTONES {
+            return Err(format!("se requieren de 1 a {} botones, hay {}", MAX_BOTONES, self.botones.len()));
+        }
+        revisar_largo("el texto", &self.texto, MAX_CUERPO_BOTONES)?;
+        if let Some(pie) = &self.pie {
+            revisar_largo("el pie", pie, MAX_PIE)?;
+        }
+        for b in &self.botones {
+            revisar_largo(&format!("el título del botón '{}'", b.titulo), &b.titulo, MAX_TITULO_BOTON)?;
+            revisar_largo(&format!("el id del botón '{}'", b.titulo), &b.id, MAX_ID)?;
+        }
+        revisar_ids_unicos(self.botones.iter().map(|b| b.id.as_str()))
+    }
+
+    pub fn a_json(&self) -> serde_json::Value {
+        let buttons: Vec<serde_json::Value> = self.botones.iter().map(|b| {
+            json!({ "type": "reply", "reply": { "id": b.id, "title": b.titulo } })
+        }).collect();
+
+        let mut interactive = json!({
+            "type": "button",
+            "body": { "text": self.texto },
+            "action": { "buttons": buttons }
+        });
+        if let Some(pie) = &self.pie {
+            interactive["footer"] = json!({ "text": pie });
+        }
+        interactive
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Fila {
+    pub id: String,
+    pub titulo: String,
+    pub descripcion: Option<String>,
+}
+
+impl Fila {
+    /// Si el título no cabe en 24 caracteres se recorta y el texto completo va en la descripción.
+    pub fn new(id: impl ToString, titulo: &str) -> Self {
+        let recortado = truncar(titulo, MAX_TITULO_FILA);
+        let descripcion = (recortado != titulo).then(|| truncar(titulo, MAX_DESCRIPCION_FILA));
+        Fila { id: id.to_string(), titulo: recortado, descripcion }
+    }
+}
+
+/// Mensaje de lista (una sección, hasta 10 filas)
+#[derive(Debug, Clone, PartialEq)]
+pub struct Lista {
+    pub titulo: String,
+    pub cuerpo: String,
+    pub pie: Option<String>,
+    pub boton: String,
+    pub filas: Vec<Fila>,
+}
+
+impl Lista {
+    /// Encabezado, cuerpo y botón se recortan a lo que acepta Meta, igual que en `Botones::new`
+    pub fn new(titulo: &str, cuerpo: &str, boton: &str) -> Self {
+        Lista {
+            titulo: truncar(titulo, MAX_ENCABEZADO),
+            cuerpo: truncar(cuerpo, MAX_CUERPO_LISTA),
+            pie: None,
+            boton: truncar(boton, MAX_TEXTO_BOTON_LISTA),
+            filas: Vec::new(),
+        }
+    }
+
+    pub fn pie(mut self, pie: &str) -> Self {
+        self.pie = Some(truncar(pie, MAX_PIE));
+        self
+    }
+
+    pub fn fila(mut self, id: impl ToString, titulo: &str) -> Self {
+        self.filas.push(Fila::new(id, titulo));
+        self
+    }
+
+    /// Deja solo las filas de la `pagina` indicada (empezando en 0). Si quedan más,
+    /// la última fila es "Ver más" con el id que arma `id_siguiente` para la página siguiente.
+    pub fn paginar(mut self, pagina: usize, id_siguiente: impl Fn(usize) -> String) -> Self {
+        let (rango, hay_mas) = rango_pagina(self.filas.len(), pagina);
+        let mut filas: Vec<Fila> = self.filas.drain(rango).collect();
+        if hay_mas {
+            filas.push(Fila {
+                id: id_siguiente(pagina + 1),
+                titulo: "➡️ Ver más".to_string(),
+                descripcion: Some(format!("Página {}", pagina + 2)),
+            });
+        }
+        self.filas = filas;
+        self
+    }
+
+    pub fn validar(&self) -> Result<(), String> {
+        if self.filas.is_empty() || self.filas.len() > MAX_FILAS_LISTA {
+            return Err(format!("se requieren de 1 a {} filas, hay {}", MAX_FILAS_LISTA, self.filas.len()));
+        }
+        revisar_largo("el encabezado", &self.titulo, MAX_ENCABEZADO)?;
+        revisar_largo("el texto", &self.cuerpo, MAX_CUERPO_LISTA)?;
+        revisar_largo("el botón de la lista", &self.boton, MAX_TEXTO_BOTON_LISTA)?;
+        if let Some(pie) = &self.pie {
+            revisar_largo("el pie", pie, MAX_PIE)?;
+        }
+        for f in &self.filas {
+            revisar_largo(&format!("el título de la fila '{}'", f.titulo), &f.titulo, MAX_TITULO_FILA)?;
+            revisar_largo(&format!("el id de la fila '{}'", f.titulo), &f.id, MAX_ID)?;
+            if let Some(d) = &f.descripcion {
+                revisar_largo(&format!("la descripción de la fila '{}'", f.titulo), d, MAX_DESCRIPCION_FILA)?;
+            }
+        }
+        revisar_ids_unicos(self.filas.iter().map(|f| f.id.as_str()))
+    }
+
+    pub fn a_json(&self) -> serde_json::Value {
+        let rows: Vec<serde_json::Value> = self.filas.iter().map(|f| {
+            let mut row = json!({ "id": f.id, "title": f.titulo });
+            if let Some(d) = &f.descripcion {
+                row["description"] = json!(d);
+            }
+            row
+        }).collect();
+
+        let mut interactive = json!({
+            "type": "list",
+            "header": { "type": "text", "text": self.titulo },
+            "body": { "text": self.cuerpo },
+            "action": { "button": self.boton, "sections": [{ "title": "Opciones", "rows": rows }] }
+        });
+        if let Some(pie) = &self.pie {
+            interactive["footer"] = json!({ "text": pie });
+        }
+        interactive
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lista_de(n: usize) -> Lista {
+        (1..=n).fold(Lista::new("💊 Analgésicos", "Añadir al carrito:", "Añadir"), |l, i| {
+            l.fila(format!("add_med:{}", i), &format!("Medicamento {}", i))
+        })
+    }
+
+    fn ids(lista: &Lista) -> Vec<&str> {
+        lista.filas.iter().map(|f| f.id.as_str()).collect()
+    }
+
+    #[test]
+    fn truncar_cuenta_caracteres_no_bytes() {
+        assert_eq!(truncar("Análisis clínicos", 30), "Análisis clínicos");
+        assert_eq!(truncar("Ñoño ñoño ñoño", 5), "Ñoño…");
+        assert_eq!(truncar("Ñoño ñoño ñoño", 5).chars().count(), 5);
+    }
+
+    #[test]
+    fn botones_recorta_titulos_y_conserva_el_id() {
+        let botones = Botones::new("¿Qué deseas hacer?")
+            .boton("carrito:finalizar", "Finalizar pedido y pagar ahora")
+            .pie("Biotecza");
+        assert_eq!(botones.botones[0].titulo.chars().count(), MAX_TITULO_BOTON);
+        assert_eq!(botones.botones[0].id, "carrito:finalizar");
+        assert!(botones.validar().is_ok());
+        assert_eq!(botones.a_json()["footer"]["text"], "Biotecza");
+        assert_eq!(botones.a_json()["action"]["buttons"][0]["reply"]["id"], "carrito:finalizar");
+    }
+
+    #[test]
+    fn cuerpo_con_texto_del_paciente_se_recorta_y_se_puede_enviar() {
+        let direccion = "Calle ".repeat(300);
+        let botones = Botones::new(&format!("📍 ¿Es correcta esta dirección?\n{}", direccion))
+            .boton("direccion:usar", "Usar esta")
+            .boton("direccion:otra", "Otra dirección");
+        assert_eq!(botones.texto.chars().count(), MAX_CUERPO_BOTONES);
+        assert!(botones.texto.ends_with('…'));
+        assert!(botones.validar().is_ok());
+
+        let lista = Lista::new("Estudios", &"x".repeat(MAX_CUERPO_LISTA + 10), "Ver").fila("a:1", "Uno");
+        assert_eq!(lista.cuerpo.chars().count(), MAX_CUERPO_LISTA);
+        assert!(lista.validar().is_ok());
+    }
+
+    #[test]
+    fn fila_larga_pasa_el_nombre_completo_a_la_descripcion() {
+        let fila = Fila::new("add_med:1", "Paracetamol con cafeína 500 mg");
+        assert_eq!(fila.titulo.chars().count(), MAX_TITULO_FILA);
+        assert_eq!(fila.descripcion.as_deref(), Some("Paracetamol con cafeína 500 mg"));
+        assert_eq!(Fila::new("add_med:2", "Tempra").descripcion, None);
+    }
+
+    #[test]
+    fn validar_botones() {
+        let base = Botones::new("Texto").boton("a:1", "Uno");
+        assert!(base.validar().is_ok());
+
+        assert!(Botones::new("Texto").validar().is_err());
+        let cuatro = (1..=4).fold(Botones::new("Texto"), |b, i| b.boton(format!("a:{}", i), "Opción"));
+        assert!(cuatro.validar().is_err());
+
+        let mut sin_texto = base.clone();
+        sin_texto.texto = String::new();
+        assert!(sin_texto.validar().is_err());
+
+        let mut cuerpo_largo = base.clone();
+        cuerpo_largo.texto = "x".repeat(MAX_CUERPO_BOTONES + 1);
+        assert!(cuerpo_largo.validar().is_err());
+
+        let mut pie_largo = base.clone();
+        pie_largo.pie = Some("x".repeat(MAX_PIE + 1));
+        assert!(pie_largo.validar().is_err());
+
+        let mut titulo_largo = base.clone();
+        titulo_largo.botones[0].titulo = "x".repeat(MAX_TITULO_BOTON + 1);
+        assert!(titulo_largo.validar().is_err());
+
+        let mut id_largo = base.clone();
+        id_largo.botones[0].id = "x".repeat(MAX_ID + 1);
+        assert!(id_largo.validar().is_err());
+
+        let repetidos = base.clone().boton("a:1", "Otra vez");
+        assert!(repetidos.validar().unwrap_err().contains("repetido"));
+    }
+
+    #[test]
+    fn validar_lista() {
+        assert!(lista_de(10).validar().is_ok());
+        assert!(lista_de(0).validar().is_err());
+        assert!(lista_de(11).validar().is_err());
+
+        let mut encabezado_largo = lista_de(1);
+        encabezado_largo.titulo = "x".repeat(MAX_ENCABEZADO + 1);
+        assert!(encabezado_largo.validar().is_err());
+
+        let mut cuerpo_largo = lista_de(1);
+        cuerpo_largo.cuerpo = "x".repeat(MAX_CUERPO_LISTA + 1);
+        assert!(cuerpo_largo.validar().is_err());
+
+        let mut boton_vacio = lista_de(1);
+        boton_vacio.boton = String::new();
+        assert!(boton_vacio.validar().is_err());
+
+        let mut pie_largo = lista_de(1);
+        pie_largo.pie = Some("x".repeat(MAX_PIE + 1));
+        assert!(pie_largo.validar().is_err());
+
+        let mut titulo_largo = lista_de(1);
+        titulo_largo.filas[0].titulo = "x".repeat(MAX_TITULO_FILA + 1);
+        assert!(titulo_largo.validar().is_err());
+
+        let mut descripcion_larga = lista_de(1);
+        descripcion_larga.filas[0].descripcion = Some("x".repeat(MAX_DESCRIPCION_FILA + 1));
+        assert!(descripcion_larga.validar().is_err());
+
+        let repetidos = lista_de(2).fila("add_med:1", "Duplicado");
+        assert!(repetidos.validar().unwrap_err().contains("repetido"));
+    }
+
+    #[test]
+    fn paginar_deja_completa_una_lista_que_cabe() {
+        let lista = lista_de(10).paginar(0, |p| format!("ver_mas:{}:", p));
+        assert_eq!(lista.filas.len(), 10);
+        assert!(lista.filas.iter().all(|f| !f.id.starts_with("ver_mas")));
+    }
+
+    #[test]
+    fn paginar_de_nueve_en_nueve_con_ver_mas() {
+        let primera = lista_de(20).paginar(0, |p| format!("ver_mas:{}:Analgésicos", p));
+        assert_eq!(primera.filas.len(), 10);
+        assert_eq!(ids(&primera)[8], "add_med:9");
+        assert_eq!(ids(&primera)[9], "ver_mas:1:Analgésicos");
+        assert!(primera.validar().is_ok());
+
+        let segunda = lista_de(20).paginar(1, |p| format!("ver_mas:{}:Analgésicos", p));
+        assert_eq!(ids(&segunda)[0], "add_med:10");
+        assert_eq!(ids(&segunda)[9], "ver_mas:2:Analgésicos");
+
+        // La última página no trae "Ver más"
+        let tercera = lista_de(20).paginar(2, |p| format!("ver_mas:{}:Analgésicos", p));
+        assert_eq!(ids(&tercera), vec!["add_med:19", "add_med:20"]);
+    }
+
+    #[test]
+    fn paginar_mas_alla_del_final_deja_la_lista_vacia() {
+        assert!(lista_de(20).paginar(5, |p| format!("ver_mas:{}:", p)).filas.is_empty());
+        assert!(lista_de(5).paginar(1, |p| format!("ver_mas:{}:", p)).filas.is_empty());
+    }
+
+    #[test]
+    fn rango_pagina_coincide_con_paginar() {
+        assert_eq!(rango_pagina(10, 0), (0..10, false));
+        assert_eq!(rango_pagina(11, 0), (0..9, true));
+        assert_eq!(rango_pagina(11, 1), (9..11, false));
+        assert_eq!(rango_pagina(18, 1), (9..18, false));
+        assert_eq!(rango_pagina(3, 2), (3..3, false));
+    }
+}
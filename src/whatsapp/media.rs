@@ -0,0 +1,16 @@
+/// Archivo descargado de los servidores de Meta
+pub struct MediaDescargada {
+    pub contenido: Vec<u8>,
+    pub mime_type: String,
+}
+
+/// Extensión de archivo a partir del tipo MIME
+pub fn extension_para(mime_type: &str) -> &'static str {
+    match mime_type.split(';').next().unwrap_or("").trim() {
+        "image/jpeg" => "jpg",
+        "image/png" => "png",
+        "image/webp" => "webp",
+        "application/pdf" => "pdf",
+        _ => "bin",
+    }
+}
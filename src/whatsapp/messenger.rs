@@ -0,0 +1,180 @@
+use async_trait::async_trait;
+use std::sync::Mutex;
+use super::client::{MessageId, WhatsAppClient};
+use super::media::MediaDescargada;
+use super::interactive::{Botones, Lista};
+use super::templates::Plantilla;
+
+/// Tipo de archivo saliente
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TipoMedia {
+    Imagen,
+    Documento,
+}
+
+impl TipoMedia {
+    pub fn como_str(&self) -> &'static str {
+        match self {
+            TipoMedia::Imagen => "image",
+            TipoMedia::Documento => "document",
+        }
+    }
+}
+
+/// Transporte de mensajes que usa la lógica del bot. La implementación real habla con Meta;
+/// `RecordingMessenger` guarda todo en memoria para pruebas y simulaciones.
+#[async_trait]
+pub trait Messenger: Send + Sync {
+    async fn enviar_texto(&self, telefono: &str, texto: &str) -> Option<MessageId>;
+
+    /// Texto cuya falta de entrega debe quedar marcada para seguimiento (p. ej. confirmación de pedido)
+    async fn enviar_texto_critico(&self, telefono: &str, texto: &str) -> Option<MessageId> {
+        self.enviar_texto(telefono, texto).await
+    }
+
+    async fn enviar_botones(&self, telefono: &str, botones: Botones) -> Option<MessageId>;
+
+    async fn enviar_lista(&self, telefono: &str, lista: Lista) -> Option<MessageId>;
+
+    async fn enviar_plantilla(&self, telefono: &str, plantilla: Plantilla) -> Option<MessageId>;
+
+    async fn enviar_media(&self, telefono: &str, tipo: TipoMedia, link: &str, caption: Option<&str>) -> Option<MessageId>;
+
+    /// Descarga un archivo que mandó el paciente
+    async fn descargar_media(&self, media_id: &str) -> Result<MediaDescargada, String>;
+}
+
+/// Implementación sobre la Cloud API de Meta. Los errores se reportan en el log.
+pub struct MetaMessenger {
+    cliente: WhatsAppClient,
+}
+
+impl MetaMessenger {
+    pub fn new(cliente: WhatsAppClient) -> Self {
+        MetaMessenger { cliente }
+    }
+}
+
+fn reportar<E: std::fmt::Display>(telefono: &str, resultado: Result<MessageId, E>) -> Option<MessageId> {
+    resultado
+        .map_err(|e| eprintln!("❌ No se pudo enviar el mensaje a {}: {}", telefono, e))
+        .ok()
+}
+
+#[async_trait]
+impl Messenger for MetaMessenger {
+    async fn enviar_texto(&self, telefono: &str, texto: &str) -> Option<MessageId> {
+        reportar(telefono, self.cliente.enviar_texto(telefono, texto).await)
+    }
+
+    async fn enviar_texto_critico(&self, telefono: &str, texto: &str) -> Option<MessageId> {
+        reportar(telefono, self.cliente.enviar_texto_critico(telefono, texto).await)
+    }
+
+    async fn enviar_botones(&self, telefono: &str, botones: Botones) -> Option<MessageId> {
+        reportar(telefono, self.cliente.enviar_botones(telefono, &botones).await)
+    }
+
+    async fn enviar_lista(&self, telefono: &str, lista: Lista) -> Option<MessageId> {
+        reportar(telefono, self.cliente.enviar_lista(telefono, &lista).await)
+    }
+
+    async fn enviar_plantilla(&self, telefono: &str, plantilla: Plantilla) -> Option<MessageId> {
+        reportar(telefono, self.cliente.enviar_plantilla(telefono, &plantilla).await)
+    }
+
+    async fn enviar_media(&self, telefono: &str, tipo: TipoMedia, link: &str, caption: Option<&str>) -> Option<MessageId> {
+        reportar(telefono, self.cliente.enviar_media(telefono, tipo, link, caption).await)
+    }
+
+    async fn descargar_media(&self, media_id: &str) -> Result<MediaDescargada, String> {
+        self.cliente.descargar_media(media_id).await.map_err(|e| e.to_string())
+    }
+}
+
+/// Mensaje capturado por `RecordingMessenger`
+#[derive(Debug, Clone, PartialEq)]
+pub enum MensajeEnviado {
+    Texto { telefono: String, texto: String },
+    Botones { telefono: String, botones: Botones },
+    Lista { telefono: String, lista: Lista },
+    Plantilla { telefono: String, plantilla: Plantilla },
+    Media { telefono: String, tipo: TipoMedia, link: String, caption: Option<String> },
+}
+
+/// Transporte en memoria: no envía nada, solo registra lo que el bot habría mandado.
+#[derive(Default)]
+pub struct RecordingMessenger {
+    enviados: Mutex<Vec<MensajeEnviado>>,
+}
+
+impl RecordingMessenger {
+    pub fn new() -> Self {
+        RecordingMessenger::default()
+    }
+
+    /// Copia de todo lo enviado hasta ahora
+    pub fn mensajes(&self) -> Vec<MensajeEnviado> {
+        self.enviados.lock().unwrap().clone()
+    }
+
+    /// Devuelve lo enviado y vacía el registro
+    pub fn tomar(&self) -> Vec<MensajeEnviado> {
+        std::mem::take(&mut *self.enviados.lock().unwrap())
+    }
+
+    fn registrar(&self, mensaje: MensajeEnviado) -> Option<MessageId> {
+        let mut enviados = self.enviados.lock().unwrap();
+        enviados.push(mensaje);
+        Some(MessageId(format!("wamid.simulado.{}", enviados.len())))
+    }
+}
+
+#[async_trait]
+impl Messenger for RecordingMessenger {
+    async fn enviar_texto(&self, telefono: &str, texto: &str) -> Option<MessageId> {
+        self.registrar(MensajeEnviado::Texto { telefono: telefono.to_string(), texto: texto.to_string() })
+    }
+
+    async fn enviar_botones(&self, telefono: &str, botones: Botones) -> Option<MessageId> {
+        // Se valida igual que con Meta para que las pruebas detecten mensajes que se rechazarían
+        if let Err(e) = botones.validar() {
+            eprintln!("❌ Botones inválidos para {}: {}", telefono, e);
+            return None;
+        }
+        self.registrar(MensajeEnviado::Botones { telefono: telefono.to_string(), botones })
+    }
+
+    async fn enviar_lista(&self, telefono: &str, lista: Lista) -> Option<MessageId> {
+        if let Err(e) = lista.validar() {
+            eprintln!("❌ Lista inválida para {}: {}", telefono, e);
+            return None;
+        }
+        self.registrar(MensajeEnviado::Lista { telefono: telefono.to_string(), lista })
+    }
+
+    async fn enviar_plantilla(&self, telefono: &str, plantilla: Plantilla) -> Option<MessageId> {
+        if let Err(e) = plantilla.validar() {
+            eprintln!("❌ Plantilla inválida para {}: {}", telefono, e);
+            return None;
+        }
+        self.registrar(MensajeEnviado::Plantilla { telefono: telefono.to_string(), plantilla })
+    }
+
+    async fn enviar_media(&self, telefono: &str, tipo: TipoMedia, link: &str, caption: Option<&str>) -> Option<MessageId> {
+        self.registrar(MensajeEnviado::Media {
+            telefono: telefono.to_string(),
+            tipo,
+            link: link.to_string(),
+            caption: caption.map(str::to_string),
+        })
+    }
+
+    async fn descargar_media(&self, media_id: &str) -> Result<MediaDescargada, String> {
+        // Archivo ficticio para que los flujos de receta puedan completarse sin Meta
+        Ok(MediaDescargada {
+            contenido: media_id.as_bytes().to_vec(),
+            mime_type: "image/jpeg".to_string(),
+        })
+    }
+}
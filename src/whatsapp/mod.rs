@@ -1,9 +1,19 @@
 // Módulos
 pub mod client;
 pub mod webhook;
+pub mod signature;
+pub mod payload;
+pub mod cola;
+pub mod media;
+pub mod messenger;
+pub mod interactive;
+pub mod templates;
 
-// Re-exportar funciones de client
-pub use client::{enviar_texto, enviar_botones, enviar_lista};
+// Re-exportar el cliente y el transporte de mensajes
+pub use client::{WhatsAppClient, WhatsAppConfig};
+pub use messenger::{Messenger, MetaMessenger};
+pub use interactive::{Botones, Lista};
+pub use templates::{Plantilla, ParametroPlantilla};
 
 // Re-exportar funciones manejadoras de webhook
 pub use webhook::{handle_verify_webhook, handle_recibir_mensaje};
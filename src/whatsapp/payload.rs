@@ -0,0 +1,270 @@
+use serde::{Deserialize, Serialize};
+
+// Estructuras tipadas del webhook de la Cloud API de WhatsApp.
+// Todos los campos opcionales usan `default` y serde ignora los campos
+// desconocidos, así que nuevos campos de Meta no rompen el parseo.
+// También se serializan para guardarlos en la cola de procesamiento.
+
+/// Sobre completo que Meta envía en cada POST: entries → changes → value
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct WebhookPayload {
+    #[serde(default)]
+    pub object: String,
+    #[serde(default)]
+    pub entry: Vec<Entry>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct Entry {
+    #[serde(default)]
+    pub id: String,
+    #[serde(default)]
+    pub changes: Vec<Change>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct Change {
+    #[serde(default)]
+    pub field: String,
+    #[serde(default)]
+    pub value: ChangeValue,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct ChangeValue {
+    #[serde(default)]
+    pub messaging_product: Option<String>,
+    #[serde(default)]
+    pub metadata: Option<Metadata>,
+    #[serde(default)]
+    pub contacts: Vec<Contact>,
+    #[serde(default)]
+    pub messages: Vec<InboundMessage>,
+    #[serde(default)]
+    pub statuses: Vec<Status>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct Metadata {
+    #[serde(default)]
+    pub display_phone_number: String,
+    #[serde(default)]
+    pub phone_number_id: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct Contact {
+    #[serde(default)]
+    pub wa_id: String,
+    #[serde(default)]
+    pub profile: Option<Profile>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct Profile {
+    #[serde(default)]
+    pub name: String,
+}
+
+/// Mensaje entrante del paciente
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct InboundMessage {
+    #[serde(default)]
+    pub from: String,
+    #[serde(default)]
+    pub id: String,
+    #[serde(default)]
+    pub timestamp: String,
+    #[serde(rename = "type", default)]
+    pub tipo: String,
+    #[serde(default)]
+    pub text: Option<TextBody>,
+    #[serde(default)]
+    pub interactive: Option<Interactive>,
+    #[serde(default)]
+    pub button: Option<ButtonMessage>,
+    #[serde(default)]
+    pub image: Option<MediaPayload>,
+    #[serde(default)]
+    pub document: Option<MediaPayload>,
+    #[serde(default)]
+    pub sticker: Option<MediaPayload>,
+    #[serde(default)]
+    pub location: Option<LocationPayload>,
+    #[serde(default)]
+    pub contacts: Vec<SharedContact>,
+    #[serde(default)]
+    pub reaction: Option<ReactionPayload>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct TextBody {
+    #[serde(default)]
+    pub body: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct Interactive {
+    #[serde(rename = "type", default)]
+    pub tipo: String,
+    #[serde(default)]
+    pub button_reply: Option<Reply>,
+    #[serde(default)]
+    pub list_reply: Option<Reply>,
+}
+
+/// Respuesta a un botón o a una fila de lista
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct Reply {
+    #[serde(default)]
+    pub id: String,
+    #[serde(default)]
+    pub title: String,
+    #[serde(default)]
+    pub description: Option<String>,
+}
+
+/// Respuesta rápida de un botón de plantilla
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct ButtonMessage {
+    #[serde(default)]
+    pub payload: String,
+    #[serde(default)]
+    pub text: String,
+}
+
+/// Imagen o documento adjunto. Meta solo manda el id; el archivo se descarga aparte.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Default)]
+pub struct MediaPayload {
+    #[serde(default)]
+    pub id: String,
+    #[serde(default)]
+    pub mime_type: String,
+    #[serde(default)]
+    pub sha256: Option<String>,
+    #[serde(default)]
+    pub caption: Option<String>,
+    #[serde(default)]
+    pub filename: Option<String>,
+}
+
+/// Ubicación compartida desde el mapa de WhatsApp. `name`/`address` solo vienen si eligió un lugar.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Default)]
+pub struct LocationPayload {
+    #[serde(default)]
+    pub latitude: f64,
+    #[serde(default)]
+    pub longitude: f64,
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(default)]
+    pub address: Option<String>,
+}
+
+/// Tarjeta de contacto compartida
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Default)]
+pub struct SharedContact {
+    #[serde(default)]
+    pub name: ContactName,
+    #[serde(default)]
+    pub phones: Vec<ContactPhone>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Default)]
+pub struct ContactName {
+    #[serde(default)]
+    pub formatted_name: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Default)]
+pub struct ContactPhone {
+    #[serde(default)]
+    pub phone: String,
+    #[serde(default)]
+    pub wa_id: Option<String>,
+}
+
+/// Reacción con emoji a uno de nuestros mensajes. Un `emoji` vacío significa que la quitó.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Default)]
+pub struct ReactionPayload {
+    #[serde(default)]
+    pub message_id: String,
+    #[serde(default)]
+    pub emoji: String,
+}
+
+/// Callback de estado de un mensaje saliente (sent/delivered/read/failed)
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct Status {
+    #[serde(default)]
+    pub id: String,
+    #[serde(default)]
+    pub status: String,
+    #[serde(default)]
+    pub timestamp: String,
+    #[serde(default)]
+    pub recipient_id: String,
+    #[serde(default)]
+    pub errors: Vec<StatusError>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct StatusError {
+    #[serde(default)]
+    pub code: i64,
+    #[serde(default)]
+    pub title: String,
+    #[serde(default)]
+    pub message: Option<String>,
+}
+
+impl WebhookPayload {
+    /// Todos los mensajes del lote, en el orden en que Meta los envió
+    pub fn mensajes(&self) -> impl Iterator<Item = &InboundMessage> {
+        self.entry.iter()
+            .flat_map(|e| e.changes.iter())
+            .flat_map(|c| c.value.messages.iter())
+    }
+
+    /// Todos los callbacks de estado (sent/delivered/read/failed) del lote
+    pub fn estados(&self) -> impl Iterator<Item = &Status> {
+        self.entry.iter()
+            .flat_map(|e| e.changes.iter())
+            .flat_map(|c| c.value.statuses.iter())
+    }
+}
+
+impl InboundMessage {
+    /// Obtener el texto (ya sea que escribió, picó un botón o eligió de una lista).
+    /// De botones y listas se usa el id, que guarda el valor completo aunque el título se haya recortado.
+    pub fn texto(&self) -> String {
+        // ¿Es texto simple?
+        if let Some(t) = &self.text {
+            return t.body.clone();
+        }
+
+        // ¿Es una interacción (botón o lista)?
+        if let Some(i) = &self.interactive {
+            // Caso: Botón normal (Max 3)
+            if let Some(b) = &i.button_reply {
+                return b.id.clone();
+            }
+            // Caso: List Message (Categorías)
+            if let Some(l) = &i.list_reply {
+                return l.id.clone();
+            }
+        }
+
+        // Caso: Botón de respuesta rápida de una plantilla
+        if let Some(b) = &self.button {
+            return b.text.clone();
+        }
+
+        String::new()
+    }
+
+    /// Archivo adjunto (foto o documento), si el mensaje trae uno
+    pub fn media(&self) -> Option<&MediaPayload> {
+        self.image.as_ref().or(self.document.as_ref())
+    }
+}
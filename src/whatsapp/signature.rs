@@ -0,0 +1,78 @@
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Verifica el header `X-Hub-Signature-256` que Meta envía con cada POST del webhook.
+/// El header tiene la forma `sha256=<hex>` y es el HMAC-SHA256 del cuerpo crudo
+/// usando el App Secret de la aplicación.
+pub fn verificar_firma(secreto: &str, header: Option<&str>, cuerpo: &[u8]) -> bool {
+    if secreto.is_empty() {
+        return false;
+    }
+
+    let firma_hex = match header.and_then(|h| h.strip_prefix("sha256=")) {
+        Some(f) => f.trim(),
+        None => return false,
+    };
+
+    let firma = match hex::decode(firma_hex) {
+        Ok(f) => f,
+        Err(_) => return false,
+    };
+
+    let mut mac = match HmacSha256::new_from_slice(secreto.as_bytes()) {
+        Ok(m) => m,
+        Err(_) => return false,
+    };
+    mac.update(cuerpo);
+
+    // `verify_slice` compara en tiempo constante
+    mac.verify_slice(&firma).is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::verificar_firma;
+
+    // POST real de Meta (mensaje de texto) y su firma con el secreto de prueba
+    const CUERPO: &[u8] = include_bytes!("../../tests/muestras/mensaje_texto.json");
+    const SECRETO: &str = "secreto-de-prueba";
+    const FIRMA: &str = "sha256=e63dec002fa26e10bedc670567995134ff0321eb8d0db0c796514a45619edfd2";
+
+    #[test]
+    fn firma_valida() {
+        assert!(verificar_firma(SECRETO, Some(FIRMA), CUERPO));
+    }
+
+    #[test]
+    fn cuerpo_alterado() {
+        let alterado = String::from_utf8_lossy(CUERPO).replace("hola", "hola!");
+        assert!(!verificar_firma(SECRETO, Some(FIRMA), alterado.as_bytes()));
+    }
+
+    #[test]
+    fn sin_header() {
+        assert!(!verificar_firma(SECRETO, None, CUERPO));
+    }
+
+    #[test]
+    fn prefijo_distinto() {
+        let sha1 = FIRMA.replacen("sha256=", "sha1=", 1);
+        assert!(!verificar_firma(SECRETO, Some(&sha1), CUERPO));
+        let sin_prefijo = FIRMA.trim_start_matches("sha256=");
+        assert!(!verificar_firma(SECRETO, Some(sin_prefijo), CUERPO));
+    }
+
+    #[test]
+    fn hex_invalido() {
+        assert!(!verificar_firma(SECRETO, Some("sha256=no-es-hex"), CUERPO));
+        // Hex válido pero de otra longitud
+        assert!(!verificar_firma(SECRETO, Some("sha256=e63dec00"), CUERPO));
+    }
+
+    #[test]
+    fn secreto_vacio_rechaza_todo() {
+        assert!(!verificar_firma("", Some(FIRMA), CUERPO));
+    }
+}
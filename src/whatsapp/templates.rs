@@ -0,0 +1,213 @@
+use rust_decimal::prelude::ToPrimitive;
+use rust_decimal::Decimal;
+use serde_json::json;
+
+/// Parámetro de una plantilla. Meta usa `fallback_value` cuando no puede localizar
+/// la moneda o la fecha en el idioma del paciente.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ParametroPlantilla {
+    Texto(String),
+    /// Monto en milésimas (p. ej. $150.50 MXN → 150500)
+    Moneda { fallback: String, codigo: String, monto_1000: i64 },
+    FechaHora { fallback: String },
+}
+
+impl ParametroPlantilla {
+    pub fn texto(valor: impl ToString) -> Self {
+        ParametroPlantilla::Texto(valor.to_string())
+    }
+
+    /// Pesos mexicanos a partir de un `Decimal`
+    pub fn pesos(monto: Decimal) -> Self {
+        let milesimas = (monto * Decimal::from(1000)).trunc();
+        ParametroPlantilla::Moneda {
+            fallback: format!("${}", monto.round_dp(2)),
+            codigo: "MXN".to_string(),
+            monto_1000: milesimas.to_i64().unwrap_or_default(),
+        }
+    }
+
+    pub fn fecha(fallback: impl ToString) -> Self {
+        ParametroPlantilla::FechaHora { fallback: fallback.to_string() }
+    }
+
+    pub fn a_json(&self) -> serde_json::Value {
+        match self {
+            ParametroPlantilla::Texto(t) => json!({ "type": "text", "text": t }),
+            ParametroPlantilla::Moneda { fallback, codigo, monto_1000 } => json!({
+                "type": "currency",
+                "currency": { "fallback_value": fallback, "code": codigo, "amount_1000": monto_1000 }
+            }),
+            ParametroPlantilla::FechaHora { fallback } => json!({
+                "type": "date_time",
+                "date_time": { "fallback_value": fallback }
+            }),
+        }
+    }
+}
+
+/// Plantilla lista para enviar: nombre, idioma y los parámetros de cada componente
+#[derive(Debug, Clone, PartialEq)]
+pub struct Plantilla {
+    pub nombre: String,
+    pub idioma: String,
+    pub encabezado: Vec<ParametroPlantilla>,
+    pub cuerpo: Vec<ParametroPlantilla>,
+    /// Parámetro dinámico de cada botón de URL, por índice del botón
+    pub botones: Vec<(usize, ParametroPlantilla)>,
+}
+
+impl Plantilla {
+    pub fn new(nombre: &str, idioma: &str) -> Self {
+        Plantilla {
+            nombre: nombre.to_string(),
+            idioma: idioma.to_string(),
+            encabezado: Vec::new(),
+            cuerpo: Vec::new(),
+            botones: Vec::new(),
+        }
+    }
+
+    pub fn encabezado(mut self, parametro: ParametroPlantilla) -> Self {
+        self.encabezado.push(parametro);
+        self
+    }
+
+    pub fn cuerpo(mut self, parametro: ParametroPlantilla) -> Self {
+        self.cuerpo.push(parametro);
+        self
+    }
+
+    pub fn boton_url(mut self, indice: usize, sufijo: &str) -> Self {
+        self.botones.push((indice, ParametroPlantilla::texto(sufijo)));
+        self
+    }
+
+    /// Revisa que la plantilla esté en nuestro registro y que traiga los parámetros que Meta espera.
+    /// Un conteo distinto hace que Meta rechace el envío con el error 132000.
+    pub fn validar(&self) -> Result<(), String> {
+        let aprobada = buscar_aprobada(&self.nombre, &self.idioma)
+            .ok_or_else(|| format!("la plantilla '{}' ({}) no está en el registro de aprobadas", self.nombre, self.idioma))?;
+
+        if self.encabezado.len() != aprobada.encabezado {
+            return Err(format!("'{}' espera {} parámetros de encabezado, hay {}", self.nombre, aprobada.encabezado, self.encabezado.len()));
+        }
+        if self.cuerpo.len() != aprobada.cuerpo {
+            return Err(format!("'{}' espera {} parámetros de cuerpo, hay {}", self.nombre, aprobada.cuerpo, self.cuerpo.len()));
+        }
+        if self.botones.len() != aprobada.botones_url {
+            return Err(format!("'{}' espera {} botones de URL, hay {}", self.nombre, aprobada.botones_url, self.botones.len()));
+        }
+        Ok(())
+    }
+
+    pub fn a_json(&self) -> serde_json::Value {
+        let mut componentes = Vec::new();
+        if !self.encabezado.is_empty() {
+            let params: Vec<serde_json::Value> = self.encabezado.iter().map(|p| p.a_json()).collect();
+            componentes.push(json!({ "type": "header", "parameters": params }));
+        }
+        if !self.cuerpo.is_empty() {
+            let params: Vec<serde_json::Value> = self.cuerpo.iter().map(|p| p.a_json()).collect();
+            componentes.push(json!({ "type": "body", "parameters": params }));
+        }
+        for (indice, parametro) in &self.botones {
+            componentes.push(json!({
+                "type": "button", "sub_type": "url", "index": indice.to_string(),
+                "parameters": [parametro.a_json()]
+            }));
+        }
+
+        json!({
+            "name": self.nombre,
+            "language": { "code": self.idioma },
+            "components": componentes
+        })
+    }
+}
+
+/// Plantilla aprobada en el administrador de WhatsApp y cuántos parámetros lleva cada parte
+#[derive(Debug, Clone, Copy)]
+pub struct PlantillaAprobada {
+    pub nombre: &'static str,
+    pub idioma: &'static str,
+    pub encabezado: usize,
+    pub cuerpo: usize,
+    pub botones_url: usize,
+}
+
+// Mantener sincronizado con las plantillas aprobadas en Meta
+pub const PLANTILLAS_APROBADAS: &[PlantillaAprobada] = &[
+    // "Hola {{1}}, tu pedido {{2}} por {{3}} está listo para entrega."
+    PlantillaAprobada { nombre: "pedido_listo", idioma: "es_MX", encabezado: 0, cuerpo: 3, botones_url: 0 },
+    // "Hola {{1}}, los resultados de tu estudio {{2}} del {{3}} ya están disponibles."
+    PlantillaAprobada { nombre: "resultados_laboratorio", idioma: "es_MX", encabezado: 0, cuerpo: 3, botones_url: 0 },
+    // "Quedan {{2}} piezas de {{1}} (mínimo {{3}}). Hay que resurtir."
+    PlantillaAprobada { nombre: "alerta_inventario", idioma: "es_MX", encabezado: 0, cuerpo: 3, botones_url: 0 },
+    // "Hola {{1}}, ¿seguimos con tu pedido? Escríbenos para continuar."
+    PlantillaAprobada { nombre: "retomar_conversacion", idioma: "es_MX", encabezado: 0, cuerpo: 1, botones_url: 0 },
+];
+
+pub fn buscar_aprobada(nombre: &str, idioma: &str) -> Option<&'static PlantillaAprobada> {
+    PLANTILLAS_APROBADAS.iter().find(|p| p.nombre == nombre && p.idioma == idioma)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn con_cuerpo(nombre: &str, parametros: usize) -> Plantilla {
+        (0..parametros).fold(Plantilla::new(nombre, "es_MX"), |p, i| p.cuerpo(ParametroPlantilla::texto(i)))
+    }
+
+    #[test]
+    fn cada_aprobada_valida_con_su_conteo() {
+        for aprobada in PLANTILLAS_APROBADAS {
+            let mut plantilla = Plantilla::new(aprobada.nombre, aprobada.idioma);
+            for _ in 0..aprobada.encabezado {
+                plantilla = plantilla.encabezado(ParametroPlantilla::texto("x"));
+            }
+            for _ in 0..aprobada.cuerpo {
+                plantilla = plantilla.cuerpo(ParametroPlantilla::texto("x"));
+            }
+            for i in 0..aprobada.botones_url {
+                plantilla = plantilla.boton_url(i, "x");
+            }
+            assert_eq!(plantilla.validar(), Ok(()), "{}", aprobada.nombre);
+        }
+    }
+
+    #[test]
+    fn faltan_o_sobran_parametros_de_cuerpo() {
+        assert!(con_cuerpo("pedido_listo", 3).validar().is_ok());
+        assert!(con_cuerpo("pedido_listo", 2).validar().unwrap_err().contains("espera 3 parámetros de cuerpo, hay 2"));
+        assert!(con_cuerpo("pedido_listo", 4).validar().unwrap_err().contains("espera 3 parámetros de cuerpo, hay 4"));
+        assert!(con_cuerpo("retomar_conversacion", 0).validar().is_err());
+    }
+
+    #[test]
+    fn encabezado_y_botones_de_mas() {
+        let con_encabezado = con_cuerpo("pedido_listo", 3).encabezado(ParametroPlantilla::texto("x"));
+        assert!(con_encabezado.validar().unwrap_err().contains("encabezado"));
+
+        let con_boton = con_cuerpo("pedido_listo", 3).boton_url(0, "abc");
+        assert!(con_boton.validar().unwrap_err().contains("botones de URL"));
+    }
+
+    #[test]
+    fn plantilla_o_idioma_sin_aprobar() {
+        assert!(con_cuerpo("promocion", 0).validar().unwrap_err().contains("no está en el registro"));
+        let en_ingles = (0..3).fold(Plantilla::new("pedido_listo", "en_US"), |p, i| p.cuerpo(ParametroPlantilla::texto(i)));
+        assert!(en_ingles.validar().is_err());
+    }
+
+    #[test]
+    fn a_json_arma_los_componentes() {
+        let json = con_cuerpo("retomar_conversacion", 1).boton_url(0, "pedido/12").a_json();
+        assert_eq!(json["name"], "retomar_conversacion");
+        assert_eq!(json["language"]["code"], "es_MX");
+        assert_eq!(json["components"][0]["type"], "body");
+        assert_eq!(json["components"][1]["sub_type"], "url");
+        assert_eq!(json["components"][1]["index"], "0");
+    }
+}
@@ -1,8 +1,12 @@
 use serde::Deserialize;
 use sqlx::PgPool;
-use crate::bot_logic;
+use crate::app::AppState;
+use crate::database;
+use axum::body::Bytes;
 use axum::extract::{Query, State};
-use axum::Json;
+use axum::http::{HeaderMap, StatusCode};
+use super::signature::verificar_firma;
+use super::payload::{Status, WebhookPayload};
 
 #[derive(Deserialize)]
 pub struct VerifyQuery {
@@ -14,67 +18,128 @@ pub struct VerifyQuery {
     pub challenge: String,
 }
 
-pub async fn verificar_webhook(params: VerifyQuery) -> String {
-    let token_esperado = std::env::var("VERIFY_TOKEN").unwrap_or_default();
-    if params.mode == "subscribe" && params.verify_token == token_esperado {
+/// Secretos del webhook, leídos una sola vez al arrancar
+#[derive(Debug, Clone, Default)]
+pub struct ConfigWebhook {
+    /// App Secret con el que Meta firma cada POST
+    pub app_secret: String,
+    /// Token que Meta manda al suscribir el webhook
+    pub verify_token: String,
+}
+
+impl ConfigWebhook {
+    pub fn desde_env() -> Self {
+        let config = ConfigWebhook {
+            app_secret: std::env::var("APP_SECRET").unwrap_or_default(),
+            verify_token: std::env::var("VERIFY_TOKEN").unwrap_or_default(),
+        };
+        if config.app_secret.is_empty() {
+            eprintln!("⚠️ APP_SECRET vacío: se rechazarán todos los POST del webhook");
+        }
+        config
+    }
+}
+
+pub async fn verificar_webhook(config: &ConfigWebhook, params: VerifyQuery) -> String {
+    if params.mode == "subscribe" && params.verify_token == config.verify_token {
         return params.challenge;
     }
     "Token inválido".to_string()
 }
 
-pub async fn recibir_mensaje(pool: &PgPool, payload: serde_json::Value) -> String {
-    // 1. Extraer el mensaje del JSON gigante de Meta
-    if let Some(msg) = payload["entry"][0]["changes"][0]["value"]["messages"][0].as_object() {
-        let telefono = msg["from"].as_str().unwrap_or("");
-        
-        // 2. Limpieza de número (El famoso "1" de México)
-        let mut tel_limpio = telefono.to_string();
-        if tel_limpio.starts_with("521") {
-            tel_limpio = format!("52{}", &tel_limpio[3..]);
+/// Encola los mensajes del POST. Si la base falla se responde 5xx para que Meta reintente.
+pub async fn recibir_mensaje(app: &AppState, payload: WebhookPayload) -> Result<String, StatusCode> {
+    // Meta puede agrupar varios mensajes en un solo POST: los encolamos todos, en orden
+    for msg in payload.mensajes() {
+        let tel_limpio = normalizar_telefono(&msg.from);
+        if tel_limpio.is_empty() {
+            continue;
         }
 
-        // 3. Obtener el texto (ya sea que escribió, picó un botón o eligió de una lista)
-        let texto_usuario = extraer_texto(msg);
+        // Bitácora de wamid y cola van juntas: un reintento de Meta se acusa pero no se vuelve a encolar.
+        // Si la base falla respondemos 5xx y Meta reintenta; lo que sí quedó encolado se reconoce como duplicado.
+        let mensaje = serde_json::to_value(msg).unwrap_or_default();
+        let id = database::encolar_mensaje(&app.pool, &msg.id, &tel_limpio, mensaje).await.map_err(|e| {
+            eprintln!("❌ No se pudo encolar el mensaje {}: {}", msg.id, e);
+            StatusCode::SERVICE_UNAVAILABLE
+        })?;
+        let Some(id) = id else {
+            println!("↩️ Mensaje duplicado ignorado: {}", msg.id);
+            continue;
+        };
 
-        if !tel_limpio.is_empty() {
-            // 4. Delegar todo al cerebro del bot
-            bot_logic::procesar(pool, &tel_limpio, &texto_usuario).await;
-        }
+        // La ventana de 24 horas cuenta desde el mensaje del paciente, no desde que nos llegó
+        database::registrar_ultimo_entrante(&app.pool, &tel_limpio, msg.timestamp.parse().ok()).await;
+
+        // El procesamiento real lo hacen los workers de la cola.
+        // Si el canal está lleno el barrido periódico lo recoge de la tabla
+        let _ = app.cola.try_send(id);
+    }
+
+    // Callbacks de entrega de nuestros mensajes salientes, fuera del camino de la respuesta
+    let estados: Vec<Status> = payload.estados().cloned().collect();
+    if !estados.is_empty() {
+        let pool = app.pool.clone();
+        tokio::spawn(async move {
+            for estado in &estados {
+                procesar_estado(&pool, estado).await;
+            }
+        });
     }
 
-    "EVENT_RECEIVED".to_string()
+    Ok("EVENT_RECEIVED".to_string())
 }
 
-fn extraer_texto(msg: &serde_json::Map<String, serde_json::Value>) -> String {
-    // ¿Es texto simple?
-    if let Some(t) = msg.get(&"text".to_string()) {
-        return t["body"].as_str().unwrap_or("").to_string();
+async fn procesar_estado(pool: &PgPool, estado: &Status) {
+    let timestamp = estado.timestamp.parse::<i64>().unwrap_or_default();
+    let error = estado.errors.first();
+    let detalle = error.map(|e| e.message.clone().unwrap_or_else(|| e.title.clone()));
+
+    let critico_fallido = database::registrar_estado_mensaje(
+        pool,
+        &estado.id,
+        &estado.status,
+        timestamp,
+        error.map(|e| e.code),
+        detalle.as_deref(),
+    ).await;
+
+    if critico_fallido {
+        eprintln!(
+            "🚨 Mensaje crítico {} para {} no entregado ({}). Requiere seguimiento.",
+            estado.id, estado.recipient_id, detalle.unwrap_or_default()
+        );
     }
-    
-    // ¿Es una interacción (botón o lista)?
-    if let Some(i) = msg.get(&"interactive".to_string()) {
-        // Caso: Botón normal (Max 3)
-        if let Some(b) = i.get(&"button_reply".to_string()) {
-            return b["title"].as_str().unwrap_or("").to_string();
-        }
-        // Caso: List Message (Categorías)
-        if let Some(l) = i.get(&"list_reply".to_string()) {
-            return l["title"].as_str().unwrap_or("").to_string();
-        }
+}
+
+/// Limpieza de número (El famoso "1" de México)
+pub fn normalizar_telefono(telefono: &str) -> String {
+    let mut tel_limpio = telefono.to_string();
+    if tel_limpio.starts_with("521") {
+        tel_limpio = format!("52{}", &tel_limpio[3..]);
     }
-    
-    String::new()
+    tel_limpio
 }
 
 // --- MANEJADORES AXUM ---
 
-pub async fn handle_verify_webhook(Query(params): Query<VerifyQuery>) -> String {
-    verificar_webhook(params).await
+pub async fn handle_verify_webhook(State(app): State<AppState>, Query(params): Query<VerifyQuery>) -> String {
+    verificar_webhook(&app.webhook, params).await
 }
 
 pub async fn handle_recibir_mensaje(
-    State(pool): State<PgPool>,
-    Json(payload): Json<serde_json::Value>,
-) -> String {
-    recibir_mensaje(&pool, payload).await
+    State(app): State<AppState>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<String, StatusCode> {
+    // Validar la firma de Meta antes de tocar la base de datos
+    let firma = headers.get("x-hub-signature-256").and_then(|h| h.to_str().ok());
+
+    if !verificar_firma(&app.webhook.app_secret, firma, &body) {
+        eprintln!("⚠️ Webhook rechazado: firma X-Hub-Signature-256 inválida o ausente");
+        return Err(StatusCode::UNAUTHORIZED);
+    }
+
+    let payload: WebhookPayload = serde_json::from_slice(&body).map_err(|_| StatusCode::BAD_REQUEST)?;
+    recibir_mensaje(&app, payload).await
 }
@@ -0,0 +1,783 @@
+//! Conversaciones de referencia ("golden"): cada guion en `tests/golden/*.json` manda mensajes al bot
+//! y revisa las respuestas, el estado de la sesión y lo que quedó guardado en la base.
+//!
+//! Cada guion corre en una copia desechable de la base que apunta `TEST_DATABASE_URL`
+//! (`CREATE DATABASE ... TEMPLATE`). Esa base solo necesita el esquema de Biotecza que está en
+//! `tests/plantilla.sql`; las migraciones se corren sobre la copia, el catálogo se vacía y cada
+//! guion siembra el suyo. Postgres no copia una plantilla con conexiones abiertas.
+//!
+//! Necesitan Postgres, así que `cargo test` no las corre. Para correrlas:
+//!
+//! ```text
+//! createdb biotecza_plantilla && psql -d biotecza_plantilla -f tests/plantilla.sql
+//! TEST_DATABASE_URL=postgres://localhost/biotecza_plantilla cargo test --test golden -- --ignored
+//! ```
+
+use biotecza_bot::app::AppState;
+use biotecza_bot::bot_logic::{self, UserInput};
+use biotecza_bot::database;
+use biotecza_bot::storage::LocalStorage;
+use biotecza_bot::whatsapp::cola::{iniciar_workers, ConfigCola};
+use biotecza_bot::whatsapp::messenger::{MensajeEnviado, RecordingMessenger};
+use biotecza_bot::whatsapp::payload::{LocationPayload, MediaPayload};
+use biotecza_bot::whatsapp::webhook::{recibir_mensaje, ConfigWebhook};
+use rust_decimal::Decimal;
+use serde::Deserialize;
+use sqlx::postgres::{PgConnectOptions, PgPool};
+use sqlx::{ConnectOptions, Connection, Executor};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Guion {
+    #[allow(dead_code)]
+    descripcion: String,
+    telefono: String,
+    /// SQL que se corre antes de empezar (catálogo, usuarios existentes…)
+    #[serde(default)]
+    semilla: Vec<String>,
+    pasos: Vec<Paso>,
+    estado_final: String,
+    #[serde(default)]
+    efectos: Efectos,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Paso {
+    entrada: Entrada,
+    respuestas: Vec<Esperado>,
+    /// Estado de la sesión después de este mensaje
+    estado: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
+enum Entrada {
+    Texto(String),
+    /// Id de botón o fila, tal como lo manda Meta
+    Boton(String),
+    /// Toca la opción con este título en el último mensaje interactivo
+    Elige(String),
+    /// Toca la opción con este título en el n-ésimo mensaje interactivo (desde 1) de la última respuesta,
+    /// cuando varios mensajes traen los mismos botones (p. ej. ➕ en cada renglón del carrito)
+    EligeEn { mensaje: usize, titulo: String },
+    Ubicacion { latitud: f64, longitud: f64, nombre: Option<String> },
+    /// Archivo adjunto con este mime type
+    Archivo(String),
+}
+
+/// Mensaje que se espera del bot. Sin `botones` ni `lista` debe ser texto.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Esperado {
+    /// Fragmento que debe aparecer en el cuerpo del mensaje
+    texto: Option<String>,
+    /// Títulos exactos de los botones
+    botones: Option<Vec<String>>,
+    /// Títulos exactos de las filas de la lista
+    lista: Option<Vec<String>>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+struct Efectos {
+    nombre: Option<String>,
+    apellido_paterno: Option<String>,
+    apellido_materno: Option<String>,
+    email: Option<String>,
+    curp: Option<String>,
+    genero: Option<String>,
+    /// Productos de la última orden: (nombre, cantidad)
+    carrito: Option<Vec<(String, i32)>>,
+    /// Fragmento de la dirección de entrega de la última orden
+    direccion: Option<String>,
+    receta: Option<bool>,
+    /// `total_amount` de la última orden
+    total: Option<Decimal>,
+    /// `p_status` y `p_method` de la última orden
+    estado_orden: Option<String>,
+    metodo_pago: Option<String>,
+    /// Catálogo completo: (nombre, existencias, reservadas)
+    inventario: Option<Vec<(String, i32, i32)>>,
+    /// Medicamentos con una alerta de inventario abierta
+    alertas: Option<Vec<String>>,
+    /// Direcciones guardadas del paciente: (dirección, predeterminada)
+    direcciones: Option<Vec<(String, bool)>>,
+}
+
+/// Base desechable copiada de la plantilla; se borra al terminar
+struct BaseDesechable {
+    admin: PgConnectOptions,
+    nombre: String,
+    pool: PgPool,
+}
+
+impl BaseDesechable {
+    async fn crear(url: &str) -> BaseDesechable {
+        let plantilla = PgConnectOptions::from_str(url).expect("TEST_DATABASE_URL inválida");
+        let origen = plantilla.get_database().unwrap_or("postgres").to_string();
+        let admin = plantilla.clone().database("postgres");
+        let nombre = format!("golden_{}", uuid::Uuid::new_v4().simple());
+
+        let mut conexion = admin.connect().await.expect("no se pudo conectar a postgres");
+        conexion
+            .execute(format!("CREATE DATABASE {} TEMPLATE {}", nombre, origen).as_str())
+            .await
+            .expect("no se pudo copiar la base de pruebas");
+        let _ = conexion.close().await;
+
+        let pool = PgPool::connect_with(plantilla.database(&nombre)).await.expect("no se pudo abrir la copia");
+        sqlx::migrate!("./migrations").run(&pool).await.expect("migraciones");
+        // El catálogo lo pone cada guion para que las listas no dependan de la plantilla
+        pool.execute("TRUNCATE medications, lab_tests CASCADE").await.expect("vaciar catálogo");
+
+        BaseDesechable { admin, nombre, pool }
+    }
+
+    async fn borrar(self) {
+        self.pool.close().await;
+        if let Ok(mut conexion) = self.admin.connect().await {
+            let _ = conexion.execute(format!("DROP DATABASE IF EXISTS {} WITH (FORCE)", self.nombre).as_str()).await;
+        }
+    }
+}
+
+/// Opción de un mensaje interactivo: (id, título)
+type Opcion = (String, String);
+
+/// Una prueba golden que se pidió con `--ignored` y no tiene base falla en lugar de pasar en blanco
+fn url_de_pruebas() -> String {
+    std::env::var("TEST_DATABASE_URL")
+        .expect("TEST_DATABASE_URL no está definida (ver tests/plantilla.sql)")
+}
+
+async fn correr(archivo: &str) {
+    let url = url_de_pruebas();
+
+    let ruta = format!("{}/tests/golden/{}", env!("CARGO_MANIFEST_DIR"), archivo);
+    let guion: Guion = serde_json::from_str(&std::fs::read_to_string(&ruta).expect("guion"))
+        .unwrap_or_else(|e| panic!("{}: {}", archivo, e));
+
+    let base = BaseDesechable::crear(&url).await;
+    let resultado = ejecutar(&base.pool, &guion).await;
+    base.borrar().await;
+
+    if let Err(error) = resultado {
+        panic!("{}: {}", archivo, error);
+    }
+}
+
+async fn ejecutar(pool: &PgPool, guion: &Guion) -> Result<(), String> {
+    for sql in &guion.semilla {
+        sqlx::query(sql).execute(pool).await.map_err(|e| format!("semilla '{}': {}", sql, e))?;
+    }
+
+    let messenger = RecordingMessenger::new();
+    let storage = LocalStorage::new(std::env::temp_dir().join(format!("golden_{}", guion.telefono)));
+    let telefono = guion.telefono.as_str();
+    let mut interactivos: Vec<Vec<Opcion>> = Vec::new();
+
+    for (i, paso) in guion.pasos.iter().enumerate() {
+        let n = i + 1;
+        let entrada = match &paso.entrada {
+            Entrada::Texto(t) => UserInput::Texto(t.clone()),
+            Entrada::Boton(id) => UserInput::desde_respuesta(id, id),
+            Entrada::Elige(titulo) => {
+                let opciones = interactivos.last().cloned().unwrap_or_default();
+                let (id, titulo) = opciones.iter().find(|(_, t)| t == titulo)
+                    .ok_or_else(|| format!("paso {}: no hay opción '{}' en {:?}", n, titulo, opciones))?;
+                UserInput::desde_respuesta(id, titulo)
+            }
+            Entrada::EligeEn { mensaje, titulo } => {
+                let opciones = mensaje.checked_sub(1).and_then(|i| interactivos.get(i))
+                    .ok_or_else(|| format!("paso {}: la última respuesta no tiene {} mensajes interactivos", n, mensaje))?;
+                let (id, titulo) = opciones.iter().find(|(_, t)| t == titulo)
+                    .ok_or_else(|| format!("paso {}: no hay opción '{}' en {:?}", n, titulo, opciones))?;
+                UserInput::desde_respuesta(id, titulo)
+            }
+            Entrada::Ubicacion { latitud, longitud, nombre } => UserInput::Ubicacion(LocationPayload {
+                latitude: *latitud,
+                longitude: *longitud,
+                name: nombre.clone(),
+                address: None,
+            }),
+            Entrada::Archivo(mime) => UserInput::Media(MediaPayload {
+                id: format!("golden-{}", n),
+                mime_type: mime.clone(),
+                ..Default::default()
+            }),
+        };
+
+        bot_logic::procesar(pool, &messenger, &storage, telefono, &entrada).await;
+
+        let enviados = messenger.tomar();
+        if enviados.len() != paso.respuestas.len() {
+            return Err(format!(
+                "paso {}: se esperaban {} mensajes y llegaron {}:\n{:#?}",
+                n, paso.respuestas.len(), enviados.len(), enviados
+            ));
+        }
+        let mut nuevos = Vec::new();
+        for (enviado, esperado) in enviados.iter().zip(&paso.respuestas) {
+            comparar(enviado, esperado).map_err(|e| format!("paso {}: {}\n{:#?}", n, e, enviado))?;
+            nuevos.extend(opciones_de(enviado));
+        }
+        // Las opciones que se pueden tocar son las de la última respuesta que trajo alguna
+        if !nuevos.is_empty() {
+            interactivos = nuevos;
+        }
+
+        if let Some(estado) = &paso.estado {
+            let actual = database::obtener_sesion(pool, telefono).await.estado;
+            if &actual != estado {
+                return Err(format!("paso {}: estado {} en lugar de {}", n, actual, estado));
+            }
+        }
+    }
+
+    let final_ = database::obtener_sesion(pool, telefono).await.estado;
+    if final_ != guion.estado_final {
+        return Err(format!("estado final {} en lugar de {}", final_, guion.estado_final));
+    }
+
+    revisar_efectos(pool, telefono, &guion.efectos).await
+}
+
+fn comparar(enviado: &MensajeEnviado, esperado: &Esperado) -> Result<(), String> {
+    let (cuerpo, titulos) = match (enviado, &esperado.botones, &esperado.lista) {
+        (MensajeEnviado::Texto { texto, .. }, None, None) => (texto.clone(), None),
+        (MensajeEnviado::Botones { botones, .. }, Some(b), None) => {
+            (botones.texto.clone(), Some((b, botones.botones.iter().map(|b| b.titulo.clone()).collect::<Vec<_>>())))
+        }
+        (MensajeEnviado::Lista { lista, .. }, None, Some(l)) => {
+            (lista.cuerpo.clone(), Some((l, lista.filas.iter().map(|f| f.titulo.clone()).collect::<Vec<_>>())))
+        }
+        _ => return Err("tipo de mensaje distinto al esperado".to_string()),
+    };
+
+    if let Some(fragmento) = &esperado.texto
+        && !cuerpo.contains(fragmento.as_str())
+    {
+        return Err(format!("el mensaje no contiene '{}'", fragmento));
+    }
+    if let Some((esperados, reales)) = titulos
+        && esperados != &reales
+    {
+        return Err(format!("opciones {:?} en lugar de {:?}", reales, esperados));
+    }
+    Ok(())
+}
+
+fn opciones_de(enviado: &MensajeEnviado) -> Option<Vec<Opcion>> {
+    match enviado {
+        MensajeEnviado::Botones { botones, .. } => {
+            Some(botones.botones.iter().map(|b| (b.id.clone(), b.titulo.clone())).collect())
+        }
+        MensajeEnviado::Lista { lista, .. } => {
+            Some(lista.filas.iter().map(|f| (f.id.clone(), f.titulo.clone())).collect())
+        }
+        _ => None,
+    }
+}
+
+async fn revisar_efectos(pool: &PgPool, telefono: &str, efectos: &Efectos) -> Result<(), String> {
+    let usuario = database::obtener_usuario_por_telefono(pool, telefono).await;
+    let campos = [
+        ("nombre", &efectos.nombre, usuario.as_ref().map(|u| u.first_name.clone())),
+        ("apellido_paterno", &efectos.apellido_paterno, usuario.as_ref().map(|u| u.paternal_last_name.clone())),
+        ("apellido_materno", &efectos.apellido_materno, usuario.as_ref().map(|u| u.maternal_last_name.clone())),
+        ("email", &efectos.email, usuario.as_ref().map(|u| u.email.clone())),
+    ];
+    for (campo, esperado, real) in campos {
+        if let Some(esperado) = esperado
+            && real.as_ref() != Some(esperado)
+        {
+            return Err(format!("{}: {:?} en lugar de {:?}", campo, real, esperado));
+        }
+    }
+
+    let paciente = sqlx::query_as::<sqlx::Postgres, (uuid::Uuid, String, Option<String>)>(
+        "SELECT patient_id, curp, gender FROM patients WHERE whatsapp_number = $1"
+    )
+    .bind(telefono)
+    .fetch_optional(pool)
+    .await
+    .map_err(|e| e.to_string())?;
+
+    if let Some(curp) = &efectos.curp
+        && paciente.as_ref().map(|p| &p.1) != Some(curp)
+    {
+        return Err(format!("curp: {:?} en lugar de {}", paciente.map(|p| p.1), curp));
+    }
+    if let Some(genero) = &efectos.genero
+        && paciente.as_ref().and_then(|p| p.2.as_ref()) != Some(genero)
+    {
+        return Err(format!("genero: {:?} en lugar de {}", paciente.and_then(|p| p.2), genero));
+    }
+
+    // La última orden, esté pendiente o ya confirmada
+    let ultima = match &paciente {
+        Some((patient_id, _, _)) => sqlx::query_as::<sqlx::Postgres, (uuid::Uuid, String, Option<String>, Decimal)>(
+            "SELECT order_id, p_status, p_method, total_amount FROM orders WHERE patient_id = $1 ORDER BY numero_pedido DESC LIMIT 1"
+        )
+        .bind(patient_id)
+        .fetch_optional(pool)
+        .await
+        .map_err(|e| e.to_string())?,
+        None => None,
+    };
+    let orden = ultima.as_ref().map(|(order_id, _, _, _)| *order_id);
+
+    if let Some(total) = efectos.total
+        && ultima.as_ref().map(|o| o.3) != Some(total)
+    {
+        return Err(format!("total: {:?} en lugar de {}", ultima.map(|o| o.3), total));
+    }
+
+    if let Some(estado) = &efectos.estado_orden
+        && ultima.as_ref().map(|o| &o.1) != Some(estado)
+    {
+        return Err(format!("estado de la orden: {:?} en lugar de {}", ultima.map(|o| o.1), estado));
+    }
+    if let Some(metodo) = &efectos.metodo_pago
+        && ultima.as_ref().and_then(|o| o.2.as_ref()) != Some(metodo)
+    {
+        return Err(format!("método de pago: {:?} en lugar de {}", ultima.and_then(|o| o.2), metodo));
+    }
+
+    if let Some(carrito) = &efectos.carrito {
+        // El orden de los renglones no importa
+        let mut real: Vec<(String, i32)> = match orden {
+            Some(order_id) => database::obtener_resumen_carrito(pool, order_id).await
+                .into_iter().map(|(nombre, cantidad, _)| (nombre, cantidad)).collect(),
+            None => Vec::new(),
+        };
+        let mut carrito = carrito.clone();
+        real.sort();
+        carrito.sort();
+        if real != carrito {
+            return Err(format!("carrito {:?} en lugar de {:?}", real, carrito));
+        }
+    }
+
+    if let Some(inventario) = &efectos.inventario {
+        let mut real = sqlx::query_as::<sqlx::Postgres, (String, i32, i32)>(
+            "SELECT brand_name, existencias, reservadas FROM medications"
+        )
+        .fetch_all(pool)
+        .await
+        .map_err(|e| e.to_string())?;
+        let mut inventario = inventario.clone();
+        real.sort();
+        inventario.sort();
+        if real != inventario {
+            return Err(format!("inventario {:?} en lugar de {:?}", real, inventario));
+        }
+    }
+    if let Some(alertas) = &efectos.alertas {
+        let mut real = sqlx::query_scalar::<sqlx::Postgres, String>(
+            "SELECT m.brand_name FROM alertas_inventario a JOIN medications m ON m.med_id = a.med_id
+             WHERE a.atendida_en IS NULL"
+        )
+        .fetch_all(pool)
+        .await
+        .map_err(|e| e.to_string())?;
+        let mut alertas = alertas.clone();
+        real.sort();
+        alertas.sort();
+        if real != alertas {
+            return Err(format!("alertas {:?} en lugar de {:?}", real, alertas));
+        }
+    }
+    if let Some(direcciones) = &efectos.direcciones {
+        let mut real = sqlx::query_as::<sqlx::Postgres, (String, bool)>(
+            "SELECT a.full_address, coalesce(a.is_default, false) FROM patient_addresses a
+             JOIN patients p ON p.patient_id = a.patient_id WHERE p.whatsapp_number = $1"
+        )
+        .bind(telefono)
+        .fetch_all(pool)
+        .await
+        .map_err(|e| e.to_string())?;
+        let mut direcciones = direcciones.clone();
+        real.sort();
+        direcciones.sort();
+        if real != direcciones {
+            return Err(format!("direcciones {:?} en lugar de {:?}", real, direcciones));
+        }
+    }
+
+    if efectos.direccion.is_some() || efectos.receta.is_some() {
+        let (direccion, receta) = match orden {
+            Some(order_id) => sqlx::query_as::<sqlx::Postgres, (Option<String>, Option<String>)>(
+                "SELECT delivery_address, prescription_url FROM medication_orders WHERE order_id = $1"
+            )
+            .bind(order_id)
+            .fetch_optional(pool)
+            .await
+            .map_err(|e| e.to_string())?
+            .unwrap_or_default(),
+            None => (None, None),
+        };
+
+        if let Some(fragmento) = &efectos.direccion
+            && !direccion.as_deref().unwrap_or_default().contains(fragmento.as_str())
+        {
+            return Err(format!("direccion {:?} no contiene '{}'", direccion, fragmento));
+        }
+        if let Some(esperada) = efectos.receta
+            && receta.is_some() != esperada
+        {
+            return Err(format!("receta {:?}, se esperaba {}", receta, if esperada { "guardada" } else { "ninguna" }));
+        }
+    }
+
+    Ok(())
+}
+
+#[tokio::test]
+#[ignore = "necesita Postgres: TEST_DATABASE_URL"]
+async fn registro_de_paciente_nuevo() {
+    correr("registro_nuevo.json").await;
+}
+
+#[tokio::test]
+#[ignore = "necesita Postgres: TEST_DATABASE_URL"]
+async fn navegacion_por_categorias() {
+    correr("categorias.json").await;
+}
+
+#[tokio::test]
+#[ignore = "necesita Postgres: TEST_DATABASE_URL"]
+async fn busqueda_aproximada() {
+    correr("busqueda.json").await;
+}
+
+#[tokio::test]
+#[ignore = "necesita Postgres: TEST_DATABASE_URL"]
+async fn captura_de_datos_del_pedido() {
+    correr("checkout.json").await;
+}
+
+#[tokio::test]
+#[ignore = "necesita Postgres: TEST_DATABASE_URL"]
+async fn recompra_con_direccion_guardada() {
+    correr("recompra.json").await;
+}
+
+#[tokio::test]
+#[ignore = "necesita Postgres: TEST_DATABASE_URL"]
+async fn direccion_ya_guardada() {
+    correr("direccion_repetida.json").await;
+}
+
+#[tokio::test]
+#[ignore = "necesita Postgres: TEST_DATABASE_URL"]
+async fn edicion_del_carrito() {
+    correr("carrito.json").await;
+}
+
+#[tokio::test]
+#[ignore = "necesita Postgres: TEST_DATABASE_URL"]
+async fn existencias_reservadas() {
+    correr("inventario.json").await;
+}
+
+#[tokio::test]
+#[ignore = "necesita Postgres: TEST_DATABASE_URL"]
+async fn pedido_vencido_no_se_confirma() {
+    correr("pedido_vencido.json").await;
+}
+
+#[tokio::test]
+#[ignore = "necesita Postgres: TEST_DATABASE_URL"]
+async fn pedido_con_total_distinto_no_se_confirma() {
+    correr("pedido_total_cambiado.json").await;
+}
+
+#[tokio::test]
+#[ignore = "necesita Postgres: TEST_DATABASE_URL"]
+async fn paginas_de_resultados() {
+    correr("paginas.json").await;
+}
+
+#[tokio::test]
+#[ignore = "necesita Postgres: TEST_DATABASE_URL"]
+async fn estudios_paginados() {
+    correr("estudios.json").await;
+}
+
+#[tokio::test]
+#[ignore = "necesita Postgres: TEST_DATABASE_URL"]
+async fn menu_y_cancelar_vacian_el_carrito() {
+    correr("menu_vacia_carrito.json").await;
+}
+
+/// Meta reintenta el POST cuando no le contestamos a tiempo: el mismo mensaje entregado dos veces
+/// por el webhook se ejecuta una sola vez (➕ 1 sobre un carrito con 1 pieza deja 2, no 3).
+#[tokio::test]
+#[ignore = "necesita Postgres: TEST_DATABASE_URL"]
+async fn reintento_del_webhook() {
+    let url = url_de_pruebas();
+
+    let base = BaseDesechable::crear(&url).await;
+    let resultado = reintentar_webhook(&base.pool).await;
+    base.borrar().await;
+
+    if let Err(error) = resultado {
+        panic!("reintento_del_webhook: {}", error);
+    }
+}
+
+async fn reintentar_webhook(pool: &PgPool) -> Result<(), String> {
+    // El webhook quita el "1" de México: la sesión queda con el número normalizado
+    let telefono = "525500000108";
+    for sql in [
+        "INSERT INTO medications (brand_name, active_compound, presentation, price, category, existencias) VALUES ('Tempra', 'Paracetamol', 'Tabletas 500 mg', 45.50, 'Analgésicos', 50)",
+        "INSERT INTO users (first_name, email, password_hash, phone, role_id) VALUES ('Gil', 'gil@correo.com', 'whatsapp_user', '525500000108', 5)",
+        "INSERT INTO patients (user_id, curp, whatsapp_number) SELECT user_id, 'TEMP-525500000108', phone FROM users WHERE phone = '525500000108'",
+    ] {
+        sqlx::query(sql).execute(pool).await.map_err(|e| format!("semilla: {}", e))?;
+    }
+    let med_id = sqlx::query_scalar::<sqlx::Postgres, uuid::Uuid>("SELECT med_id FROM medications")
+        .fetch_one(pool)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    let messenger = Arc::new(RecordingMessenger::new());
+    let storage = Arc::new(LocalStorage::new(std::env::temp_dir().join(format!("golden_{}", telefono))));
+
+    // Una pieza de Tempra en el carrito y la conversación en Ver carrito
+    let entradas = [
+        UserInput::Texto("hola".to_string()),
+        UserInput::desde_respuesta("menu:farmacia", "Medicamentos"),
+        UserInput::desde_respuesta("farmacia:buscar", "Buscar"),
+        UserInput::Texto("tempra".to_string()),
+        UserInput::desde_respuesta(&format!("add_med:{}", med_id), "Tempra"),
+        UserInput::desde_respuesta("cantidad:1", "1"),
+        UserInput::desde_respuesta("carrito:ver", "Ver carrito"),
+    ];
+    for entrada in &entradas {
+        bot_logic::procesar(pool, messenger.as_ref(), storage.as_ref(), telefono, entrada).await;
+    }
+    let estado = database::obtener_sesion(pool, telefono).await.estado;
+    if estado != "REVISANDO_CARRITO" {
+        return Err(format!("estado {} antes del reintento", estado));
+    }
+
+    let cola = iniciar_workers(
+        pool.clone(),
+        messenger.clone(),
+        storage.clone(),
+        ConfigCola { workers: 2, max_intentos: 3, timeout_segundos: 120 },
+    ).await;
+    let app = AppState { pool: pool.clone(), cola, webhook: Arc::new(ConfigWebhook::default()) };
+
+    let post = serde_json::json!({
+        "object": "whatsapp_business_account",
+        "entry": [{
+            "id": "102290129340398",
+            "changes": [{
+                "field": "messages",
+                "value": {
+                    "messaging_product": "whatsapp",
+                    "messages": [{
+                        "from": "5215500000108",
+                        "id": "wamid.reintento-golden",
+                        "timestamp": "1760000000",
+                        "type": "interactive",
+                        "interactive": {
+                            "type": "button_reply",
+                            "button_reply": { "id": format!("item_mas:{}", med_id), "title": "➕ 1" }
+                        }
+                    }]
+                }
+            }]
+        }]
+    });
+    for intento in 1..=2 {
+        let payload = serde_json::from_value(post.clone()).map_err(|e| e.to_string())?;
+        recibir_mensaje(&app, payload).await.map_err(|e| format!("intento {}: {}", intento, e))?;
+    }
+
+    // Los workers drenan la cola en segundo plano
+    for _ in 0..100 {
+        let en_curso = sqlx::query_scalar::<sqlx::Postgres, i64>(
+            "SELECT count(*) FROM cola_mensajes WHERE estado IN ('pendiente', 'procesando')"
+        )
+        .fetch_one(pool)
+        .await
+        .map_err(|e| e.to_string())?;
+        if en_curso == 0 {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+
+    let encolados = sqlx::query_scalar::<sqlx::Postgres, i64>("SELECT count(*) FROM cola_mensajes")
+        .fetch_one(pool)
+        .await
+        .map_err(|e| e.to_string())?;
+    if encolados != 1 {
+        return Err(format!("{} mensajes en la cola en lugar de 1", encolados));
+    }
+
+    revisar_efectos(pool, telefono, &Efectos {
+        carrito: Some(vec![("Tempra".to_string(), 2)]),
+        inventario: Some(vec![("Tempra".to_string(), 50, 2)]),
+        ..Default::default()
+    }).await
+}
+
+/// Los pedidos que la farmacia marca como `listo` y los resultados publicados se avisan una sola vez;
+/// fuera de la ventana de 24 horas el aviso sale como plantilla aprobada.
+#[tokio::test]
+#[ignore = "necesita Postgres: TEST_DATABASE_URL"]
+async fn avisos_de_pedido_listo_y_resultados() {
+    let url = url_de_pruebas();
+
+    let base = BaseDesechable::crear(&url).await;
+    let resultado = mandar_avisos(&base.pool).await;
+    base.borrar().await;
+
+    if let Err(error) = resultado {
+        panic!("avisos_de_pedido_listo_y_resultados: {}", error);
+    }
+}
+
+async fn mandar_avisos(pool: &PgPool) -> Result<(), String> {
+    for sql in [
+        "INSERT INTO lab_tests (test_name, instructions, price) VALUES ('Biometría hemática', 'Ayuno de 8 horas', 150.00)",
+        "INSERT INTO users (first_name, email, password_hash, phone, role_id) VALUES ('Rosa', 'rosa@correo.com', 'whatsapp_user', '5215500000110', 5)",
+        "INSERT INTO patients (user_id, curp, whatsapp_number) SELECT user_id, 'TEMP-5215500000110', phone FROM users WHERE phone = '5215500000110'",
+        "INSERT INTO orders (patient_id, order_type, total_amount, p_status) SELECT patient_id, 'medication', 150.50, 'listo' FROM patients",
+        "INSERT INTO orders (patient_id, order_type, total_amount, p_status) SELECT patient_id, 'medication', 80.00, 'confirmada' FROM patients",
+        "INSERT INTO resultados_laboratorio (patient_id, test_id, tomado_en) SELECT patient_id, test_id, '2026-10-15' FROM patients, lab_tests",
+    ] {
+        sqlx::query(sql).execute(pool).await.map_err(|e| format!("semilla: {}", e))?;
+    }
+
+    let messenger = RecordingMessenger::new();
+    let enviados = bot_logic::notificaciones::enviar_avisos_pendientes(pool, &messenger).await;
+    if enviados != 2 {
+        return Err(format!("{} avisos en lugar de 2: {:#?}", enviados, messenger.mensajes()));
+    }
+
+    let plantillas: Vec<(String, Vec<serde_json::Value>)> = messenger.tomar().into_iter()
+        .filter_map(|m| match m {
+            MensajeEnviado::Plantilla { telefono, plantilla } if telefono == "5215500000110" => {
+                Some((plantilla.nombre, plantilla.cuerpo.iter().map(|p| p.a_json()).collect()))
+            }
+            _ => None,
+        })
+        .collect();
+    let esperadas = vec![
+        ("pedido_listo".to_string(), vec![
+            serde_json::json!({ "type": "text", "text": "Rosa" }),
+            serde_json::json!({ "type": "text", "text": "#1" }),
+            serde_json::json!({ "type": "currency", "currency": { "fallback_value": "$150.50", "code": "MXN", "amount_1000": 150500 } }),
+        ]),
+        ("resultados_laboratorio".to_string(), vec![
+            serde_json::json!({ "type": "text", "text": "Rosa" }),
+            serde_json::json!({ "type": "text", "text": "Biometría hemática" }),
+            serde_json::json!({ "type": "date_time", "date_time": { "fallback_value": "15/10/2026" } }),
+        ]),
+    ];
+    if plantillas != esperadas {
+        return Err(format!("plantillas {:#?} en lugar de {:#?}", plantillas, esperadas));
+    }
+
+    // Ya avisados: la siguiente vuelta no repite nada
+    let repetidos = bot_logic::notificaciones::enviar_avisos_pendientes(pool, &messenger).await;
+    if repetidos != 0 || !messenger.tomar().is_empty() {
+        return Err(format!("se repitieron {} avisos", repetidos));
+    }
+    Ok(())
+}
+
+/// Los carritos olvidados se vacían y liberan sus piezas; uno que se está confirmando en ese
+/// momento (su orden ya está bloqueada) se salta sin esperar y queda intacto.
+#[tokio::test]
+#[ignore = "necesita Postgres: TEST_DATABASE_URL"]
+async fn carritos_vencidos_se_liberan() {
+    let url = url_de_pruebas();
+
+    let base = BaseDesechable::crear(&url).await;
+    let resultado = liberar_vencidos(&base.pool).await;
+    base.borrar().await;
+
+    if let Err(error) = resultado {
+        panic!("carritos_vencidos_se_liberan: {}", error);
+    }
+}
+
+async fn liberar_vencidos(pool: &PgPool) -> Result<(), String> {
+    for sql in [
+        "INSERT INTO medications (brand_name, active_compound, presentation, price, category, existencias, reservadas) VALUES ('Tempra', 'Paracetamol', 'Tabletas 500 mg', 45.50, 'Analgésicos', 50, 5)",
+        "INSERT INTO users (first_name, email, password_hash, phone, role_id) VALUES ('Lía', 'lia@correo.com', 'whatsapp_user', '5215500000114', 5), ('Noé', 'noe@correo.com', 'whatsapp_user', '5215500000115', 5)",
+        "INSERT INTO patients (user_id, curp, whatsapp_number) SELECT user_id, 'TEMP-' || phone, phone FROM users",
+        "INSERT INTO orders (patient_id, order_type, total_amount, carrito_actualizado_en) SELECT patient_id, 'medication', 91.00, now() - interval '2 days' FROM patients WHERE whatsapp_number = '5215500000114'",
+        "INSERT INTO orders (patient_id, order_type, total_amount, carrito_actualizado_en) SELECT patient_id, 'medication', 136.50, now() - interval '2 days' FROM patients WHERE whatsapp_number = '5215500000115'",
+        "INSERT INTO medication_items (order_id, med_id, quantity, unit_price) SELECT o.order_id, m.med_id, 2, 45.50 FROM orders o JOIN patients p ON p.patient_id = o.patient_id, medications m WHERE p.whatsapp_number = '5215500000114'",
+        "INSERT INTO medication_items (order_id, med_id, quantity, unit_price) SELECT o.order_id, m.med_id, 3, 45.50 FROM orders o JOIN patients p ON p.patient_id = o.patient_id, medications m WHERE p.whatsapp_number = '5215500000115'",
+    ] {
+        sqlx::query(sql).execute(pool).await.map_err(|e| format!("semilla: {}", e))?;
+    }
+
+    // Noé está confirmando su pedido: su orden queda bloqueada mientras se libera lo demás
+    let mut confirmando = pool.begin().await.map_err(|e| e.to_string())?;
+    sqlx::query(
+        "SELECT o.order_id FROM orders o JOIN patients p ON p.patient_id = o.patient_id
+         WHERE p.whatsapp_number = '5215500000115' FOR UPDATE"
+    )
+    .execute(&mut *confirmando)
+    .await
+    .map_err(|e| e.to_string())?;
+
+    let vaciados = tokio::time::timeout(
+        std::time::Duration::from_secs(5),
+        database::liberar_carritos_vencidos(pool, 24),
+    )
+    .await
+    .map_err(|_| "liberar_carritos_vencidos esperó al carrito que se está confirmando".to_string())?;
+    confirmando.rollback().await.map_err(|e| e.to_string())?;
+    if vaciados != 1 {
+        return Err(format!("{} carritos vaciados en lugar de 1", vaciados));
+    }
+
+    let carritos = sqlx::query_as::<sqlx::Postgres, (String, i64, Decimal)>(
+        "SELECT p.whatsapp_number, COUNT(mi.item_id), o.total_amount
+         FROM orders o
+         JOIN patients p ON p.patient_id = o.patient_id
+         LEFT JOIN medication_items mi ON mi.order_id = o.order_id
+         GROUP BY p.whatsapp_number, o.total_amount
+         ORDER BY p.whatsapp_number"
+    )
+    .fetch_all(pool)
+    .await
+    .map_err(|e| e.to_string())?;
+    let esperados = vec![
+        ("5215500000114".to_string(), 0, Decimal::ZERO),
+        ("5215500000115".to_string(), 1, Decimal::new(13650, 2)),
+    ];
+    if carritos != esperados {
+        return Err(format!("carritos {:?} en lugar de {:?}", carritos, esperados));
+    }
+
+    let reservadas = sqlx::query_scalar::<sqlx::Postgres, i32>("SELECT reservadas FROM medications")
+        .fetch_one(pool)
+        .await
+        .map_err(|e| e.to_string())?;
+    if reservadas != 3 {
+        return Err(format!("{} piezas reservadas en lugar de 3", reservadas));
+    }
+
+    // Ya vacío, la siguiente vuelta no lo vuelve a contar
+    let otra_vez = database::liberar_carritos_vencidos(pool, 24).await;
+    if otra_vez != 1 {
+        return Err(format!("la segunda vuelta vació {} carritos en lugar del de Noé", otra_vez));
+    }
+    Ok(())
+}
@@ -0,0 +1,60 @@
+-- Esquema base de Biotecza que las migraciones de `migrations/` dan por hecho (usuarios, pacientes,
+-- catálogo, órdenes y sesiones). En producción lo crea el sistema principal; aquí se reproduce
+-- lo mínimo que usa el bot para armar la base plantilla de las pruebas golden:
+--
+--   createdb biotecza_plantilla && psql -d biotecza_plantilla -f tests/plantilla.sql
+--
+-- Sin datos: cada guion siembra su propio catálogo y pacientes.
+CREATE EXTENSION IF NOT EXISTS pg_trgm;
+CREATE TABLE users (
+    user_id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    first_name TEXT, paternal_last_name TEXT, maternal_last_name TEXT,
+    email TEXT, password_hash TEXT, phone TEXT UNIQUE, role_id INT
+);
+CREATE VIEW view_usuarios_full AS SELECT * FROM users;
+CREATE TABLE patients (
+    patient_id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    user_id UUID REFERENCES users(user_id),
+    curp TEXT NOT NULL,
+    whatsapp_number TEXT NOT NULL UNIQUE,
+    gender TEXT
+);
+CREATE TABLE patient_addresses (
+    address_id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    patient_id UUID REFERENCES patients(patient_id),
+    address_label TEXT, full_address TEXT, is_default BOOLEAN
+);
+CREATE TABLE medications (
+    med_id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    brand_name TEXT NOT NULL, active_compound TEXT NOT NULL, presentation TEXT,
+    price NUMERIC(10,2) NOT NULL, category TEXT, stock BOOLEAN DEFAULT true
+);
+CREATE TABLE lab_tests (
+    test_id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    test_name TEXT NOT NULL, instructions TEXT NOT NULL, price NUMERIC(10,2) NOT NULL, category TEXT
+);
+CREATE TABLE orders (
+    order_id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    patient_id UUID REFERENCES patients(patient_id),
+    order_type TEXT, total_amount NUMERIC(10,2) NOT NULL DEFAULT 0,
+    p_method TEXT, p_status TEXT NOT NULL DEFAULT 'pendiente'
+);
+CREATE TABLE medication_orders (
+    order_id UUID PRIMARY KEY REFERENCES orders(order_id),
+    delivery_address TEXT, prescription_url TEXT
+);
+CREATE TABLE medication_items (
+    item_id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    order_id UUID REFERENCES orders(order_id),
+    med_id UUID REFERENCES medications(med_id),
+    quantity INT NOT NULL, unit_price NUMERIC(10,2) NOT NULL
+);
+CREATE TABLE sesiones (telefono TEXT PRIMARY KEY, estado TEXT NOT NULL DEFAULT 'INICIO');
+CREATE FUNCTION fn_obtener_o_crear_estado(p_tel TEXT) RETURNS TEXT AS $$
+    INSERT INTO sesiones (telefono, estado) VALUES (p_tel, 'NUEVO') ON CONFLICT (telefono) DO NOTHING;
+    SELECT estado FROM sesiones WHERE telefono = p_tel;
+$$ LANGUAGE sql;
+CREATE FUNCTION fn_actualizar_estado_sesion(p_tel TEXT, p_estado TEXT) RETURNS VOID AS $$
+    INSERT INTO sesiones (telefono, estado) VALUES (p_tel, p_estado)
+    ON CONFLICT (telefono) DO UPDATE SET estado = EXCLUDED.estado;
+$$ LANGUAGE sql;